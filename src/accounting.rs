@@ -0,0 +1,136 @@
+//! Reconciliation of where the total issuance goes.
+
+use crate::format::BalanceFormatter;
+use crate::Balance;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+
+/// On-chain balances (free plus reserved) accumulated per bucket during the iteration.
+///
+/// Every account falls into exactly one bucket, except the new accounts whose balance
+/// is split between the exported part and the part withheld by `--balance-kind`, so the
+/// buckets must always add up to the total balance of all accounts.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Accounting {
+    /// Exported balances of the new accounts, before `--balance-expr`.
+    pub new_accounts: Balance,
+    /// Balances of the new accounts not exported due to `--balance-kind`.
+    pub withheld: Balance,
+    /// Balances of the new accounts outside of `--balance-min`/`--balance-max`.
+    pub out_of_range: Balance,
+    /// Balances of the new accounts absent at `--min-age-block`.
+    pub too_new: Balance,
+    /// Balances of the token grant accounts.
+    pub grants: Balance,
+    /// Balances of the endowed accounts.
+    pub endowed: Balance,
+}
+
+impl Accounting {
+    pub fn total(&self) -> Result<Balance> {
+        [
+            self.withheld,
+            self.out_of_range,
+            self.too_new,
+            self.grants,
+            self.endowed,
+        ]
+        .into_iter()
+        .try_fold(self.new_accounts, Balance::checked_add)
+        .ok_or_else(|| anyhow!("Accounting buckets overflow"))
+    }
+
+    /// Prints the accounting table against `expected_total_issuance`.
+    pub fn print(
+        &self,
+        expected_total_issuance: Balance,
+        formatter: &BalanceFormatter,
+    ) -> Result<()> {
+        let total = self.total()?;
+        let rows = [
+            ("New accounts", self.new_accounts),
+            ("Withheld", self.withheld),
+            ("Out of range", self.out_of_range),
+            ("Too new", self.too_new),
+            ("Token grants", self.grants),
+            ("Endowed", self.endowed),
+            ("Total", total),
+            ("Total issuance", expected_total_issuance),
+        ];
+        status!("Accounting:");
+        for (name, balance) in rows {
+            status!("  {:<16}{}", name, formatter.display(balance));
+        }
+        if total != expected_total_issuance {
+            status!(
+                "  {:<16}{}{}",
+                "Residual",
+                if total > expected_total_issuance {
+                    "+"
+                } else {
+                    "-"
+                },
+                formatter.display(total.abs_diff(expected_total_issuance))
+            );
+        }
+        Ok(())
+    }
+
+    /// Prints the facts of an iterated total not matching `expected_total_issuance`,
+    /// the per bucket breakdown is the accounting table.
+    pub fn print_mismatch(
+        &self,
+        accounts: usize,
+        expected_total_issuance: Balance,
+        inactive_issuance: Option<Balance>,
+        formatter: &BalanceFormatter,
+    ) -> Result<()> {
+        let iterated = self.total()?;
+        status!("Total issuance mismatch:");
+        status!("  {:<20}{}", "Accounts scanned", accounts);
+        status!("  {:<20}{}", "Iterated sum", formatter.display(iterated));
+        status!(
+            "  {:<20}{}",
+            "Total issuance",
+            formatter.display(expected_total_issuance)
+        );
+        status!(
+            "  {:<20}{}{}",
+            "Difference",
+            if iterated > expected_total_issuance {
+                "+"
+            } else {
+                "-"
+            },
+            formatter.display(iterated.abs_diff(expected_total_issuance))
+        );
+        if let Some(inactive) = inactive_issuance {
+            status!(
+                "  {:<20}{}",
+                "Inactive issuance",
+                formatter.display(inactive)
+            );
+        }
+        self.print(expected_total_issuance, formatter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn total_is_checked() {
+        let mut accounting = Accounting {
+            new_accounts: 1,
+            withheld: 2,
+            out_of_range: 3,
+            too_new: 4,
+            grants: 5,
+            endowed: 6,
+        };
+        assert_eq!(accounting.total().unwrap(), 21);
+        accounting.endowed = Balance::MAX;
+        assert!(accounting.total().is_err());
+    }
+}
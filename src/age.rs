@@ -0,0 +1,84 @@
+//! Approximation of the account age for `--min-age-block`.
+//!
+//! `System::Account` does not record when an account was created, so an account is
+//! deemed old enough when it already existed at the reference block. An account reaped
+//! and recreated since then counts as old, one created and reaped before it as new.
+
+use crate::storage::account_key;
+use crate::{AccountId, BlockHash, BlockNumber};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeSet;
+use subxt::sp_core::storage::StorageKey;
+use subxt::{Client, DefaultConfig};
+
+/// Number of concurrent existence lookups per node.
+const LOOKUPS_PER_CLIENT: usize = 8;
+
+/// Returns the number of the reference block, which must not be after `block_number`.
+pub async fn reference_block_number(
+    client: &Client<DefaultConfig>,
+    reference: BlockHash,
+    block_number: BlockNumber,
+) -> Result<BlockNumber> {
+    let header = client
+        .rpc()
+        .header(Some(reference))
+        .await?
+        .ok_or_else(|| anyhow!("--min-age-block {:?} not found", reference))?;
+    if header.number > block_number {
+        return Err(anyhow!(
+            "--min-age-block #{} ({:?}) is after the snapshot block #{}",
+            header.number,
+            reference,
+            block_number
+        ));
+    }
+    Ok(header.number)
+}
+
+/// Returns the accounts of `account_ids` absent from the account storage at `prefix`
+/// at the `reference` block, looked up concurrently across `clients`.
+pub async fn absent_at(
+    clients: &[Client<DefaultConfig>],
+    prefix: &[u8],
+    reference: BlockHash,
+    account_ids: Vec<AccountId>,
+) -> Result<BTreeSet<AccountId>> {
+    let tasks = (clients.len() * LOOKUPS_PER_CLIENT).max(1);
+    let chunk_size = (account_ids.len() + tasks - 1) / tasks;
+    if chunk_size == 0 {
+        return Ok(BTreeSet::new());
+    }
+
+    let lookups = account_ids
+        .chunks(chunk_size)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let client = clients[index % clients.len()].clone();
+            let keys = chunk
+                .iter()
+                .map(|account_id| {
+                    (
+                        account_id.clone(),
+                        StorageKey(account_key(prefix, account_id)),
+                    )
+                })
+                .collect::<Vec<_>>();
+            tokio::spawn(async move {
+                let mut absent = Vec::new();
+                for (account_id, key) in keys {
+                    if client.rpc().storage(&key, Some(reference)).await?.is_none() {
+                        absent.push(account_id);
+                    }
+                }
+                Ok::<_, anyhow::Error>(absent)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut absent = BTreeSet::new();
+    for lookup in lookups {
+        absent.extend(lookup.await??);
+    }
+    Ok(absent)
+}
@@ -0,0 +1,194 @@
+//! Compact binary snapshot format.
+//!
+//! All the integers are little endian:
+//!
+//! | Field      | Size                       |
+//! |------------|----------------------------|
+//! | magic      | 4 bytes, `SRGB`            |
+//! | version    | 4 bytes, `u32`             |
+//! | block hash | 32 bytes                   |
+//! | count      | 8 bytes, `u64`             |
+//! | total      | 16 bytes, `u128`           |
+//! | records    | `count` times 48 bytes     |
+//!
+//! Each record is the 32 byte account id followed by its 16 byte `u128` balance.
+
+use crate::snapshot::{Entry, Snapshot};
+use crate::{AccountId, Balance, BlockHash};
+use anyhow::{anyhow, Result};
+use std::io::{self, Read, Write};
+
+/// Magic bytes at the start of a binary snapshot.
+pub const MAGIC: [u8; 4] = *b"SRGB";
+/// Version of the binary layout.
+const VERSION: u32 = 1;
+/// Length of the header preceding the records.
+const HEADER_LEN: u64 = 4 + 4 + 32 + 8 + 16;
+/// Length of a record.
+const RECORD_LEN: u64 = 32 + 16;
+
+/// Writes `entries` taken at `block_hash`, returns the number of bytes written.
+///
+/// If `index` is given, the byte offset of every record is written to it as the 32 byte
+/// account id followed by the `u64` little endian offset, in the record order.
+pub fn write(
+    mut writer: impl Write,
+    entries: &[Entry],
+    block_hash: BlockHash,
+    mut index: Option<&mut dyn Write>,
+) -> Result<u64> {
+    let total = entries
+        .iter()
+        .try_fold(0 as Balance, |total, Entry(_, balance)| {
+            total.checked_add(*balance)
+        })
+        .ok_or_else(|| anyhow!("Total balance of the snapshot overflows"))?;
+
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())?;
+    writer.write_all(block_hash.as_bytes())?;
+    writer.write_all(&(entries.len() as u64).to_le_bytes())?;
+    writer.write_all(&total.to_le_bytes())?;
+
+    let mut offset = HEADER_LEN;
+    for Entry(account_id, balance) in entries {
+        if let Some(index) = &mut index {
+            index.write_all(account_id.as_ref())?;
+            index.write_all(&offset.to_le_bytes())?;
+        }
+        writer.write_all(account_id.as_ref())?;
+        writer.write_all(&balance.to_le_bytes())?;
+        offset += RECORD_LEN;
+    }
+
+    writer.flush()?;
+    if let Some(index) = index {
+        index.flush()?;
+    }
+
+    Ok(offset)
+}
+
+/// Reads a binary snapshot of `len` bytes, returns the block hash and the entries.
+///
+/// The length must match the count of the header exactly and the balances must add up
+/// to the total of the header.
+pub fn read(reader: impl Read, len: u64) -> Result<(BlockHash, Snapshot)> {
+    let (block_hash, records) = records(reader, len)?;
+    Ok((block_hash, records.collect::<Result<_>>()?))
+}
+
+/// Reads the header of a binary snapshot of `len` bytes, returns the block hash and
+/// the records, read one at a time.
+///
+/// The same checks as [`read`] apply, the total being checked along the last record.
+pub fn records<R: Read>(mut reader: R, len: u64) -> Result<(BlockHash, Records<R>)> {
+    let mut header = [0u8; HEADER_LEN as usize];
+    reader
+        .read_exact(&mut header)
+        .map_err(|err| truncated(err, "header"))?;
+
+    if header[..4] != MAGIC {
+        return Err(anyhow!("Not a binary snapshot, bad magic bytes"));
+    }
+    let version = u32::from_le_bytes(header[4..8].try_into().expect("4 bytes; qed"));
+    if version != VERSION {
+        return Err(anyhow!(
+            "Unsupported binary snapshot version {}, expected {}",
+            version,
+            VERSION
+        ));
+    }
+    let block_hash = BlockHash::from_slice(&header[8..40]);
+    let count = u64::from_le_bytes(header[40..48].try_into().expect("8 bytes; qed"));
+    let total = Balance::from_le_bytes(header[48..64].try_into().expect("16 bytes; qed"));
+
+    let expected_len = count
+        .checked_mul(RECORD_LEN)
+        .and_then(|records_len| records_len.checked_add(HEADER_LEN));
+    if expected_len != Some(len) {
+        return Err(anyhow!(
+            "Binary snapshot of {} bytes does not hold the {} records of its header",
+            len,
+            count
+        ));
+    }
+
+    let records = Records {
+        reader,
+        remaining: count,
+        total,
+        sum: 0,
+    };
+    if count == 0 {
+        records.check_total()?;
+    }
+    Ok((block_hash, records))
+}
+
+/// Records of a binary snapshot, see [`records`].
+pub struct Records<R> {
+    reader: R,
+    remaining: u64,
+    total: Balance,
+    sum: Balance,
+}
+
+impl<R: Read> Records<R> {
+    fn read_record(&mut self) -> Result<Entry> {
+        let mut record = [0u8; RECORD_LEN as usize];
+        self.reader
+            .read_exact(&mut record)
+            .map_err(|err| truncated(err, "record"))?;
+        let account_id = AccountId::new(record[..32].try_into().expect("32 bytes; qed"));
+        let balance = Balance::from_le_bytes(record[32..].try_into().expect("16 bytes; qed"));
+        self.sum = self
+            .sum
+            .checked_add(balance)
+            .ok_or_else(|| anyhow!("Total balance of the binary snapshot overflows"))?;
+        if self.remaining == 0 {
+            self.check_total()?;
+        }
+        Ok(Entry(account_id, balance))
+    }
+
+    fn check_total(&self) -> Result<()> {
+        if self.sum != self.total {
+            return Err(anyhow!(
+                "Balances of the binary snapshot add up to {} instead of {}",
+                self.sum,
+                self.total
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Iterator for Records<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let record = self.read_record();
+        if record.is_err() {
+            self.remaining = 0;
+        }
+        Some(record)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = self.remaining as usize;
+        (remaining, Some(remaining))
+    }
+}
+
+fn truncated(err: io::Error, what: &str) -> anyhow::Error {
+    if err.kind() == io::ErrorKind::UnexpectedEof {
+        anyhow!("Binary snapshot is truncated in a {}", what)
+    } else {
+        err.into()
+    }
+}
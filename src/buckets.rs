@@ -0,0 +1,83 @@
+//! Distribution of the snapshot balances over `--bucket-edges`, see `--buckets-file`.
+
+use crate::snapshot::Entry;
+use crate::Balance;
+use anyhow::{anyhow, Context, Result};
+use serde::Serialize;
+use std::io::Write;
+use std::path::Path;
+
+/// Balances from `min` included to `max` excluded, `max` being `None` for the last
+/// bucket above the last edge.
+#[derive(Debug, Serialize)]
+pub struct Bucket {
+    pub min: Balance,
+    pub max: Option<Balance>,
+    pub count: usize,
+    pub sum: Balance,
+}
+
+/// Checks that the edges are strictly increasing and above zero.
+pub fn check_edges(edges: &[Balance]) -> Result<()> {
+    if edges.first() == Some(&0) {
+        return Err(anyhow!("--bucket-edges must be greater than 0"));
+    }
+    if let Some(pair) = edges.windows(2).find(|pair| pair[0] >= pair[1]) {
+        return Err(anyhow!(
+            "--bucket-edges must be strictly increasing, {} is followed by {}",
+            pair[0],
+            pair[1]
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the `edges.len() + 1` buckets of `entries`.
+pub fn bucketize(entries: &[Entry], edges: &[Balance]) -> Result<Vec<Bucket>> {
+    let mut buckets = std::iter::once(0)
+        .chain(edges.iter().copied())
+        .zip(edges.iter().copied().map(Some).chain(std::iter::once(None)))
+        .map(|(min, max)| Bucket {
+            min,
+            max,
+            count: 0,
+            sum: 0,
+        })
+        .collect::<Vec<_>>();
+
+    for Entry(_, balance) in entries {
+        // Number of edges at or below the balance.
+        let bucket = &mut buckets[edges.partition_point(|edge| edge <= balance)];
+        bucket.count += 1;
+        bucket.sum = bucket
+            .sum
+            .checked_add(*balance)
+            .ok_or_else(|| anyhow!("Total balance of a bucket overflows"))?;
+    }
+
+    Ok(buckets)
+}
+
+/// Writes the buckets to `path` as CSV if it ends with `.csv`, as JSON otherwise.
+pub fn write(path: &Path, buckets: &[Bucket]) -> Result<()> {
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+    if path.extension().map_or(false, |ext| ext == "csv") {
+        writeln!(writer, "min,max,count,sum")?;
+        for bucket in buckets {
+            writeln!(
+                writer,
+                "{},{},{},{}",
+                bucket.min,
+                bucket.max.map(|max| max.to_string()).unwrap_or_default(),
+                bucket.count,
+                bucket.sum
+            )?;
+        }
+    } else {
+        serde_json::to_writer_pretty(&mut writer, buckets)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
@@ -0,0 +1,132 @@
+//! Local cache of the node metadata and runtime version.
+
+use anyhow::{anyhow, Context, Result};
+use codec::{Decode, Encode};
+use frame_metadata::RuntimeMetadataV14;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataCache {
+    pub spec_version: u32,
+    pub transaction_version: u32,
+    /// Hex encoded SCALE `RuntimeMetadataV14`.
+    pub metadata: String,
+}
+
+impl MetadataCache {
+    /// Caches the node `metadata` for a block at `spec_version`.
+    ///
+    /// The node serves the metadata of its best block, at `metadata_spec_version`. The
+    /// metadata of another runtime is refused, cached under the `spec_version` of the
+    /// block it would later be taken for the metadata of that runtime.
+    pub fn new(
+        metadata: &RuntimeMetadataV14,
+        metadata_spec_version: u32,
+        spec_version: u32,
+        transaction_version: u32,
+    ) -> Result<Self> {
+        if metadata_spec_version != spec_version {
+            return Err(anyhow!(
+                "Can't cache the metadata of spec_version {} for a block at spec_version {}, \
+                 the node only serves the metadata of its best block",
+                metadata_spec_version,
+                spec_version
+            ));
+        }
+        Ok(Self {
+            spec_version,
+            transaction_version,
+            metadata: hex::encode(metadata.encode()),
+        })
+    }
+
+    /// Reads the cache at `path`, `None` if it does not exist yet.
+    pub fn load(path: &Path) -> Result<Option<Self>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let file = std::fs::File::open(path)?;
+        let cache = serde_json::from_reader(std::io::BufReader::new(file))
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        Ok(Some(cache))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        serde_json::to_writer_pretty(std::fs::File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Decodes the cached metadata, see `--offline-decode`.
+    pub fn runtime_metadata(&self) -> Result<RuntimeMetadataV14> {
+        let bytes = hex::decode(&self.metadata)
+            .map_err(|err| anyhow!("Malformed cached metadata: {}", err))?;
+        RuntimeMetadataV14::decode(&mut bytes.as_slice())
+            .map_err(|err| anyhow!("Failed to decode the cached metadata: {}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::subspace::runtime_types::{frame_system, pallet_balances::AccountData};
+    use crate::{dynamic, storage};
+    use frame_metadata::{RuntimeMetadata, RuntimeMetadataPrefixed};
+
+    fn bundled_metadata() -> RuntimeMetadataV14 {
+        let bundled = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/subspace_metadata.scale"
+        ));
+        match RuntimeMetadataPrefixed::decode(&mut &bundled[..])
+            .unwrap()
+            .1
+        {
+            RuntimeMetadata::V14(runtime) => runtime,
+            _ => panic!("subspace_metadata.scale is not V14"),
+        }
+    }
+
+    #[test]
+    fn metadata_of_another_runtime_is_not_cached() {
+        let runtime = bundled_metadata();
+        // Best block upgraded to spec_version 2 while the target block is still at 1.
+        assert!(MetadataCache::new(&runtime, 2, 1, 1).is_err());
+        let cache = MetadataCache::new(&runtime, 1, 1, 1).unwrap();
+        assert_eq!(cache.spec_version, 1);
+    }
+
+    #[test]
+    fn offline_decode_against_the_cached_metadata() {
+        let runtime = bundled_metadata();
+        let cache = MetadataCache::new(&runtime, 1, 1, 1).unwrap();
+        let cached = cache.runtime_metadata().unwrap();
+        assert_eq!(cached.encode(), runtime.encode());
+
+        let account_storage = "System.Account".parse::<storage::AccountStorage>().unwrap();
+        let decoder = dynamic::AccountDecoder::from_metadata(&account_storage, &cached).unwrap();
+        let info = frame_system::AccountInfo {
+            nonce: 3,
+            consumers: 0,
+            providers: 1,
+            sufficients: 0,
+            data: AccountData {
+                free: 42,
+                reserved: 5,
+                misc_frozen: 0,
+                fee_frozen: 0,
+            },
+        };
+        let decoded = decoder.decode(&info.encode()).unwrap();
+        assert_eq!(
+            (decoded.nonce, decoded.data.free, decoded.data.reserved),
+            (3, 42, 5)
+        );
+
+        let malformed = MetadataCache {
+            metadata: "zz".to_string(),
+            ..cache
+        };
+        assert!(malformed.runtime_metadata().is_err());
+    }
+}
@@ -0,0 +1,50 @@
+//! Canonical JSON snapshots, meant to be kept under version control.
+//!
+//! The snapshot is a single object sorted by SS58 address with one `"<ss58>": <u128>`
+//! member per line, so that a line based diff between two snapshots only shows the
+//! accounts whose balance actually changed.
+
+use crate::snapshot::{Entry, Snapshot};
+use crate::{AccountId, Balance};
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+/// Writes `entries` sorted by address, failing on an account appearing twice.
+pub fn write(mut writer: impl Write, entries: &[Entry]) -> Result<()> {
+    let mut sorted = BTreeMap::new();
+    for Entry(account_id, balance) in entries {
+        if sorted.insert(account_id.to_string(), *balance).is_some() {
+            return Err(anyhow!("Account {} appears more than once", account_id));
+        }
+    }
+
+    if sorted.is_empty() {
+        writeln!(writer, "{{}}")?;
+    } else {
+        writeln!(writer, "{{")?;
+        for (i, (address, balance)) in sorted.iter().enumerate() {
+            let separator = if i + 1 < sorted.len() { "," } else { "" };
+            writeln!(writer, "  \"{}\": {}{}", address, balance, separator)?;
+        }
+        writeln!(writer, "}}")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a snapshot, in address order.
+pub fn read(path: &Path) -> Result<Snapshot> {
+    let sorted: BTreeMap<String, Balance> =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?;
+    sorted
+        .into_iter()
+        .map(|(address, balance)| {
+            let account_id = address
+                .parse::<AccountId>()
+                .map_err(|err| anyhow!("invalid account {}: {:?}", address, err))?;
+            Ok(Entry(account_id, balance))
+        })
+        .collect()
+}
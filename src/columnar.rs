@@ -0,0 +1,151 @@
+//! Apache Parquet output, enabled with the `parquet` feature.
+//!
+//! The file has two columns, `account_id` with the SS58 encoded account and `balance`
+//! with the 16 bytes little endian `u128`, the snapshot metadata is stored in the
+//! key-value metadata of the file.
+
+use crate::snapshot::{Entry, Metadata};
+use anyhow::Result;
+use arrow::array::{FixedSizeBinaryArray, StringArray};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::file::metadata::KeyValue;
+use parquet::file::properties::WriterProperties;
+use std::path::Path;
+use std::sync::Arc;
+
+const BALANCE_LEN: i32 = 16;
+
+fn schema() -> Arc<Schema> {
+    Arc::new(Schema::new(vec![
+        Field::new("account_id", DataType::Utf8, false),
+        Field::new("balance", DataType::FixedSizeBinary(BALANCE_LEN), false),
+    ]))
+}
+
+/// Writes `entries` to `path`.
+pub fn write(path: &Path, entries: &[Entry], metadata: &Metadata) -> Result<()> {
+    let account_ids = StringArray::from(
+        entries
+            .iter()
+            .map(|Entry(account_id, _)| account_id.to_string())
+            .collect::<Vec<_>>(),
+    );
+    let balances = FixedSizeBinaryArray::try_from_iter(
+        entries
+            .iter()
+            .map(|Entry(_, balance)| balance.to_le_bytes()),
+    )?;
+
+    let batch = RecordBatch::try_new(schema(), vec![Arc::new(account_ids), Arc::new(balances)])?;
+
+    let key_value_metadata = vec![
+        KeyValue::new(
+            "block_number".to_string(),
+            metadata.block_number.to_string(),
+        ),
+        KeyValue::new(
+            "block_hash".to_string(),
+            format!("{:?}", metadata.block_hash),
+        ),
+        KeyValue::new(
+            "spec_version".to_string(),
+            metadata.spec_version.to_string(),
+        ),
+        KeyValue::new("tool_version".to_string(), metadata.tool_version.clone()),
+    ];
+    let properties = WriterProperties::builder()
+        .set_key_value_metadata(Some(key_value_metadata))
+        .build();
+
+    let mut writer =
+        ArrowWriter::try_new(std::fs::File::create(path)?, schema(), Some(properties))?;
+    writer.write(&batch)?;
+    writer.close()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AccountId, BlockHash};
+    use anyhow::anyhow;
+    use arrow::array::Array;
+    use parquet::arrow::{ArrowReader, ParquetFileArrowReader};
+    use parquet::file::reader::SerializedFileReader;
+
+    /// Reads the entries of a parquet snapshot.
+    fn read(path: &Path) -> Result<Vec<Entry>> {
+        let reader = SerializedFileReader::new(std::fs::File::open(path)?)?;
+        let mut arrow_reader = ParquetFileArrowReader::new(Arc::new(reader));
+
+        let mut entries = Vec::new();
+        for batch in arrow_reader.get_record_reader(8192)? {
+            let batch = batch?;
+            let account_ids = batch
+                .column(0)
+                .as_any()
+                .downcast_ref::<StringArray>()
+                .ok_or_else(|| anyhow!("Column account_id is not a string column"))?;
+            let balances = batch
+                .column(1)
+                .as_any()
+                .downcast_ref::<FixedSizeBinaryArray>()
+                .ok_or_else(|| anyhow!("Column balance is not a fixed size binary column"))?;
+
+            for i in 0..batch.num_rows() {
+                let account_id = account_ids.value(i).parse().map_err(|err| {
+                    anyhow!("Invalid account id {}: {:?}", account_ids.value(i), err)
+                })?;
+                let balance = balances
+                    .value(i)
+                    .try_into()
+                    .map(u128::from_le_bytes)
+                    .map_err(|_| anyhow!("Invalid balance at row {}", i))?;
+                entries.push(Entry(account_id, balance));
+            }
+        }
+
+        Ok(entries)
+    }
+
+    #[test]
+    fn round_trip() {
+        let entries = vec![
+            Entry(AccountId::new([1; 32]), 0),
+            Entry(AccountId::new([2; 32]), 1_000_000_000_000_000_000),
+            Entry(AccountId::new([0xff; 32]), u128::MAX),
+        ];
+        let metadata = Metadata {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            spec_version: 1,
+            block_number: 100,
+            block_hash: BlockHash::repeat_byte(7),
+            options: serde_json::Value::Null,
+            resume: None,
+            sha256: None,
+            fingerprint: None,
+            merkle_root: None,
+            partial: false,
+            skipped_undecodable: 0,
+            accounts: None,
+            total: None,
+        };
+        let path =
+            std::env::temp_dir().join(format!("regenesis-columnar-{}.parquet", std::process::id()));
+
+        write(&path, &entries, &metadata).unwrap();
+        let read_back = read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let pairs = |entries: &[Entry]| {
+            entries
+                .iter()
+                .map(|Entry(account_id, balance)| (account_id.clone(), *balance))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(pairs(&read_back), pairs(&entries));
+    }
+}
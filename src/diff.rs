@@ -0,0 +1,453 @@
+//! Comparison of two snapshots.
+
+use crate::snapshot::{self, Entry};
+use crate::storage::{account_key, account_storage_key, encode_account_info};
+use crate::{block_hash_at, connect_client, error, pager, AccountId, AccountInfo, Balance};
+use crate::{BlockHash, Cli};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use codec::Decode;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use subxt::sp_core::storage::StorageKey;
+use subxt::{Client, DefaultConfig};
+
+/// Compare two snapshots.
+#[derive(Parser, Debug, Serialize)]
+pub struct DiffCmd {
+    /// The older snapshot.
+    pub old: PathBuf,
+
+    /// The newer snapshot.
+    pub new: PathBuf,
+
+    /// Print the changes as storage writes of the `--account-storage` map instead.
+    ///
+    /// The output is a JSON object of hex encoded storage key to hex encoded
+    /// `AccountInfo`. Only the free balance is written, from the snapshot, or zero for
+    /// the accounts to be removed. The rest of `AccountInfo` is kept from the account
+    /// on the node at `--url`, read at `--block-hash` or `--block-number` or else the
+    /// best block, and is that of a freshly created account if it doesn't exist there.
+    /// Removed accounts that don't exist there are left out. Not available with
+    /// `--asset-id`.
+    #[clap(long)]
+    pub as_storage_diff: bool,
+
+    /// Compare the snapshots in a single pass streaming both, without indexing them,
+    /// checking along the way that they are sorted in storage order.
+    #[clap(long)]
+    pub assert_sorted: bool,
+}
+
+/// Differences between two snapshots.
+#[derive(Default)]
+struct Changes {
+    added: Vec<(AccountId, Balance)>,
+    removed: Vec<(AccountId, Balance)>,
+    changed: Vec<(AccountId, Balance, Balance)>,
+    old_total: Balance,
+    new_total: Balance,
+}
+
+/// Returns the sum of the `balances` of the snapshot at `path`, failing on overflow.
+fn checked_total<'a>(
+    path: &Path,
+    mut balances: impl Iterator<Item = &'a Balance>,
+) -> Result<Balance> {
+    balances
+        .try_fold(0, |total: Balance, balance| total.checked_add(*balance))
+        .ok_or_else(|| anyhow!("Total balance of {} overflows", path.display()))
+}
+
+impl Changes {
+    fn by_map(
+        old_path: &Path,
+        old: &BTreeMap<AccountId, Balance>,
+        new_path: &Path,
+        new: &BTreeMap<AccountId, Balance>,
+    ) -> Result<Self> {
+        let mut changes = Self::default();
+
+        for (account_id, &balance) in new {
+            match old.get(account_id) {
+                None => changes.added.push((account_id.clone(), balance)),
+                Some(&old_balance) if old_balance != balance => {
+                    changes
+                        .changed
+                        .push((account_id.clone(), old_balance, balance))
+                }
+                Some(_) => {}
+            }
+        }
+
+        for (account_id, &balance) in old {
+            if !new.contains_key(account_id) {
+                changes.removed.push((account_id.clone(), balance));
+            }
+        }
+
+        changes.old_total = checked_total(old_path, old.values())?;
+        changes.new_total = checked_total(new_path, new.values())?;
+        Ok(changes)
+    }
+
+    /// Merge-join of two snapshots streamed in storage order.
+    fn by_merge_join(mut old: SortedEntries, mut new: SortedEntries) -> Result<Self> {
+        let mut changes = Self::default();
+        let mut old_next = old.next()?;
+        let mut new_next = new.next()?;
+
+        loop {
+            let ordering = match (&old_next, &new_next) {
+                (Some((old_key, _)), Some((new_key, _))) => old_key.cmp(new_key),
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => break,
+            };
+            match ordering {
+                Ordering::Less => {
+                    let (_, Entry(account_id, balance)) = old_next.expect("Compared above; qed");
+                    changes.removed.push((account_id, balance));
+                    old_next = old.next()?;
+                }
+                Ordering::Greater => {
+                    let (_, Entry(account_id, balance)) = new_next.expect("Compared above; qed");
+                    changes.added.push((account_id, balance));
+                    new_next = new.next()?;
+                }
+                Ordering::Equal => {
+                    let (_, Entry(_, old_balance)) = old_next.expect("Compared above; qed");
+                    let (_, Entry(account_id, balance)) = new_next.expect("Compared above; qed");
+                    if old_balance != balance {
+                        changes.changed.push((account_id, old_balance, balance));
+                    }
+                    old_next = old.next()?;
+                    new_next = new.next()?;
+                }
+            }
+        }
+
+        changes.old_total = old.total;
+        changes.new_total = new.total;
+        Ok(changes)
+    }
+}
+
+/// Entries of a snapshot streamed along with their storage key, checked to be strictly
+/// sorted in storage order, i.e. by `blake2_128_concat(account_id)` as the unshuffled
+/// snapshots are written, which also rules out duplicates.
+struct SortedEntries<'a> {
+    path: &'a Path,
+    entries: Box<dyn Iterator<Item = Result<Entry>>>,
+    /// Storage key and account of the last entry.
+    last: Option<(Vec<u8>, AccountId)>,
+    /// Number of entries read so far.
+    count: usize,
+    total: Balance,
+}
+
+impl<'a> SortedEntries<'a> {
+    fn open(path: &'a Path) -> Result<Self> {
+        Ok(Self {
+            path,
+            entries: snapshot::entries(path)?,
+            last: None,
+            count: 0,
+            total: 0,
+        })
+    }
+
+    /// Returns the next entry with its storage key, failing if it is out of order.
+    fn next(&mut self) -> Result<Option<(Vec<u8>, Entry)>> {
+        let entry = match self.entries.next().transpose()? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        self.count += 1;
+        let key = account_storage_key(&entry.0);
+        match &self.last {
+            Some((_, last)) if *last == entry.0 => {
+                return Err(anyhow!(
+                    "{} is not sorted: {} appears twice at entry {}",
+                    self.path.display(),
+                    entry.0,
+                    self.count
+                ))
+            }
+            Some((last_key, last)) if *last_key > key => {
+                return Err(anyhow!(
+                    "{} is not sorted in storage order: {} at entry {} comes after {}",
+                    self.path.display(),
+                    entry.0,
+                    self.count,
+                    last
+                ))
+            }
+            _ => {}
+        }
+        self.total = self
+            .total
+            .checked_add(entry.1)
+            .ok_or_else(|| anyhow!("Total balance of {} overflows", self.path.display()))?;
+        self.last = Some((key.clone(), entry.0.clone()));
+        Ok(Some((key, entry)))
+    }
+}
+
+/// Reads the values of `account_ids` in the account storage at `prefix` at
+/// `block_hash`, leaving out the accounts that don't exist.
+async fn existing_accounts(
+    client: &Client<DefaultConfig>,
+    block_hash: BlockHash,
+    prefix: &[u8],
+    account_ids: &[&AccountId],
+) -> Result<BTreeMap<AccountId, AccountInfo>> {
+    let mut existing = BTreeMap::new();
+    for chunk in account_ids.chunks(pager::PAGE_SIZE as usize) {
+        let by_key = chunk
+            .iter()
+            .map(|&account_id| (account_key(prefix, account_id), account_id))
+            .collect::<BTreeMap<_, _>>();
+        let keys = by_key.keys().cloned().map(StorageKey).collect::<Vec<_>>();
+        for change_set in client
+            .rpc()
+            .query_storage_at(&keys, Some(block_hash))
+            .await?
+        {
+            for (key, data) in change_set.changes {
+                let (account_id, data) = match (by_key.get(&key.0), data) {
+                    (Some(account_id), Some(data)) => (*account_id, data),
+                    (None, _) => return Err(anyhow!("Unexpected key 0x{}", hex::encode(&key.0))),
+                    (Some(_), None) => continue,
+                };
+                let info = AccountInfo::decode(&mut data.0.as_slice()).map_err(|err| {
+                    anyhow!("Failed to decode the account {}: {}", account_id, err)
+                })?;
+                existing.insert(account_id.clone(), info);
+            }
+        }
+    }
+    Ok(existing)
+}
+
+impl DiffCmd {
+    pub async fn run(self, cli: &Cli) -> Result<()> {
+        if self.as_storage_diff && cli.asset_id.is_some() {
+            return Err(anyhow!(
+                "--as-storage-diff writes AccountInfo values and doesn't support --asset-id"
+            ));
+        }
+
+        let Changes {
+            added,
+            removed,
+            changed,
+            old_total,
+            new_total,
+        } = if self.assert_sorted {
+            Changes::by_merge_join(
+                SortedEntries::open(&self.old)?,
+                SortedEntries::open(&self.new)?,
+            )?
+        } else {
+            let old_map = snapshot::load_map(&self.old)?;
+            let new_map = snapshot::load_map(&self.new)?;
+            Changes::by_map(&self.old, &old_map, &self.new, &new_map)?
+        };
+
+        if self.as_storage_diff {
+            let written = added
+                .iter()
+                .map(|(account_id, balance)| (account_id, *balance))
+                .chain(
+                    changed
+                        .iter()
+                        .map(|(account_id, _, balance)| (account_id, *balance)),
+                )
+                .collect::<Vec<_>>();
+
+            let client = connect_client(cli, &cli.url).await?;
+            let block_hash = match (cli.block_hash, cli.block_number) {
+                (Some(block_hash), _) => block_hash,
+                (None, Some(block_number)) => block_hash_at(&client, block_number).await?,
+                (None, None) => client.rpc().block_hash(None).await?.ok_or_else(|| {
+                    error::Error::Connection(format!("Best block hash not found on {}", cli.url))
+                })?,
+            };
+            let prefix = cli.account_storage.resolve(&client)?;
+            let account_ids = written
+                .iter()
+                .map(|&(account_id, _)| account_id)
+                .chain(removed.iter().map(|(account_id, _)| account_id))
+                .collect::<Vec<_>>();
+            let existing = existing_accounts(&client, block_hash, &prefix, &account_ids).await?;
+
+            let mut writes = BTreeMap::new();
+            let mut write = |account_id: &AccountId, info: Option<&AccountInfo>, free| {
+                writes.insert(
+                    format!("0x{}", hex::encode(account_key(&prefix, account_id))),
+                    format!("0x{}", hex::encode(encode_account_info(info, free))),
+                );
+            };
+            for (account_id, balance) in written {
+                write(account_id, existing.get(account_id), balance);
+            }
+            // Zeroing the free balance rather than deleting the value keeps the nonce,
+            // the reference counters and the reserved balance of the account.
+            for (account_id, _) in &removed {
+                if let Some(info) = existing.get(account_id) {
+                    write(account_id, Some(info), 0);
+                }
+            }
+            println!("{}", serde_json::to_string_pretty(&writes)?);
+            return Ok(());
+        }
+
+        for (account_id, balance) in &added {
+            println!("+ {} {}", account_id, balance);
+        }
+        for (account_id, balance) in &removed {
+            println!("- {} {}", account_id, balance);
+        }
+        for (account_id, old_balance, new_balance) in &changed {
+            println!("~ {} {} -> {}", account_id, old_balance, new_balance);
+        }
+
+        println!(
+            "Added: {}, removed: {}, changed: {}",
+            added.len(),
+            removed.len(),
+            changed.len()
+        );
+        println!("Total issuance: {} -> {}", old_total, new_total);
+
+        Ok(())
+    }
+}
+
+/// Counts of the accounts not gaining balance since a `--baseline` snapshot.
+#[derive(Debug, Default)]
+pub struct BaselineDeltas {
+    /// Accounts whose balance decreased, written with a zero delta.
+    pub decreased: usize,
+    /// Accounts of the baseline absent from the snapshot, left out.
+    pub gone: usize,
+}
+
+/// Replaces each balance of `snapshot` by its increase since `baseline`.
+///
+/// Accounts missing from the baseline count from zero, so their delta is their whole
+/// balance, and decreases are clamped at zero.
+pub fn subtract_baseline(
+    snapshot: snapshot::Snapshot,
+    baseline: &BTreeMap<AccountId, Balance>,
+) -> (snapshot::Snapshot, BaselineDeltas) {
+    let mut deltas = BaselineDeltas::default();
+    let mut present = 0;
+    let snapshot = snapshot
+        .into_iter()
+        .map(|Entry(account_id, balance)| {
+            let base = match baseline.get(&account_id) {
+                Some(&base) => {
+                    present += 1;
+                    base
+                }
+                None => 0,
+            };
+            if balance < base {
+                deltas.decreased += 1;
+            }
+            Entry(account_id, balance.saturating_sub(base))
+        })
+        .collect();
+    deltas.gone = baseline.len() - present;
+    (snapshot, deltas)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output;
+
+    /// Returns an empty scratch directory for the test `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("regenesis-diff-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes `entries` to the JSON snapshot at `path`.
+    fn write_snapshot(path: PathBuf, entries: &[Entry]) -> PathBuf {
+        let mut writer = output::FileWriter::create(&path).unwrap();
+        output::write_json(&mut writer, entries, 0).unwrap();
+        writer.finish(false).unwrap();
+        path
+    }
+
+    fn sorted(balances: &[(u8, Balance)]) -> Vec<Entry> {
+        let mut entries = balances
+            .iter()
+            .map(|&(byte, balance)| Entry(AccountId::new([byte; 32]), balance))
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|Entry(account_id, _)| account_storage_key(account_id));
+        entries
+    }
+
+    #[test]
+    fn merge_join_matches_the_maps() {
+        let dir = scratch_dir("merge-join");
+        let old = write_snapshot(dir.join("old.json"), &sorted(&[(1, 10), (2, 20), (3, 30)]));
+        let new = write_snapshot(dir.join("new.json"), &sorted(&[(2, 20), (3, 31), (4, 40)]));
+
+        let joined = Changes::by_merge_join(
+            SortedEntries::open(&old).unwrap(),
+            SortedEntries::open(&new).unwrap(),
+        )
+        .unwrap();
+        let mapped = Changes::by_map(
+            &old,
+            &snapshot::load_map(&old).unwrap(),
+            &new,
+            &snapshot::load_map(&new).unwrap(),
+        )
+        .unwrap();
+        for changes in [&joined, &mapped] {
+            assert_eq!(changes.added, [(AccountId::new([4; 32]), 40)]);
+            assert_eq!(changes.removed, [(AccountId::new([1; 32]), 10)]);
+            assert_eq!(changes.changed, [(AccountId::new([3; 32]), 30, 31)]);
+            assert_eq!((changes.old_total, changes.new_total), (60, 91));
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn merge_join_rejects_unsorted_and_overflowing_snapshots() {
+        let dir = scratch_dir("unsorted");
+        let sorted_path = write_snapshot(dir.join("sorted.json"), &sorted(&[(1, 10), (2, 20)]));
+        let mut unsorted = sorted(&[(1, 10), (2, 20)]);
+        unsorted.reverse();
+        let unsorted_path = write_snapshot(dir.join("unsorted.json"), &unsorted);
+        let mut duplicated = sorted(&[(1, 10)]);
+        duplicated.push(duplicated[0].clone());
+        let duplicated_path = write_snapshot(dir.join("duplicated.json"), &duplicated);
+        let overflowing_path = write_snapshot(
+            dir.join("overflowing.json"),
+            &sorted(&[(1, Balance::MAX), (2, 1)]),
+        );
+
+        for path in [&unsorted_path, &duplicated_path, &overflowing_path] {
+            assert!(Changes::by_merge_join(
+                SortedEntries::open(&sorted_path).unwrap(),
+                SortedEntries::open(path).unwrap()
+            )
+            .is_err());
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,247 @@
+//! Decoding of the account storage values, and of the accounts of events, against the
+//! runtime metadata of the node instead of the types generated from
+//! `subspace_metadata.scale`, see `--dynamic`.
+//!
+//! The values are walked through the type registry of the metadata and the fields of
+//! `AccountInfo` are picked by name, so layouts differing from the generated one, e.g.
+//! a `u64` nonce or a balance model with `frozen` and `flags`, still decode.
+
+use crate::storage::AccountStorage;
+use crate::subspace::runtime_types::{frame_system, pallet_balances::AccountData};
+use crate::{AccountId, AccountInfo, Balance};
+use anyhow::{anyhow, Result};
+use codec::{Compact, Decode};
+use frame_metadata::RuntimeMetadataV14;
+use scale_info::{form::PortableForm, Field, PortableRegistry, TypeDef, TypeDefPrimitive};
+use subxt::{Client, DefaultConfig};
+
+/// A decoded value, only keeping what is needed to find the balances in it.
+#[derive(Debug)]
+enum Value {
+    /// Any unsigned integer, compact or not.
+    Uint(u128),
+    /// A struct, or a tuple with unnamed fields.
+    Composite(Vec<(Option<String>, Value)>),
+    /// Any other value, decoded and discarded.
+    Other,
+}
+
+impl Value {
+    fn field(&self, name: &str) -> Option<&Value> {
+        match self {
+            Self::Composite(fields) => fields
+                .iter()
+                .find(|(field, _)| field.as_deref() == Some(name))
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    /// Returns the unsigned integer field `name`, `None` if there is no such field.
+    fn uint(&self, name: &str) -> Result<Option<u128>> {
+        match self.field(name) {
+            Some(Self::Uint(value)) => Ok(Some(*value)),
+            Some(value) => Err(anyhow!(
+                "Field {} is not an unsigned integer: {:?}",
+                name,
+                value
+            )),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the first of the unsigned integer fields `names` found, 0 if none is.
+    fn uint_or_zero(&self, names: &[&str]) -> Result<u128> {
+        for name in names {
+            if let Some(value) = self.uint(name)? {
+                return Ok(value);
+            }
+        }
+        Ok(0)
+    }
+
+    fn counter(&self, name: &str) -> Result<u32> {
+        let value = self.uint(name)?.unwrap_or_default();
+        u32::try_from(value).map_err(|_| anyhow!("Field {} overflows u32: {}", name, value))
+    }
+}
+
+/// Decoder of the values of the account storage, built from the node metadata.
+pub struct AccountDecoder {
+    registry: PortableRegistry,
+    value_type: u32,
+}
+
+impl AccountDecoder {
+    pub fn new(account_storage: &AccountStorage, client: &Client<DefaultConfig>) -> Result<Self> {
+        Self::from_metadata(account_storage, client.metadata().runtime_metadata())
+    }
+
+    /// Builds the decoder from the `runtime` metadata, e.g. a cached one.
+    pub fn from_metadata(
+        account_storage: &AccountStorage,
+        runtime: &RuntimeMetadataV14,
+    ) -> Result<Self> {
+        Ok(Self {
+            value_type: account_storage.value_type(runtime)?,
+            registry: runtime.types.clone(),
+        })
+    }
+
+    /// Decodes a value into the generated `AccountInfo`.
+    ///
+    /// `frozen` is mapped to `misc_frozen` and `flags` to `fee_frozen`, the same way as
+    /// when the newer layout is decoded with the generated types, see
+    /// [`AccountStorage::has_extra_flags`].
+    pub fn decode(&self, mut value: &[u8]) -> Result<AccountInfo> {
+        let info = decode_value(&self.registry, self.value_type, &mut value)?;
+        if !value.is_empty() {
+            return Err(anyhow!(
+                "{} bytes left after decoding the account",
+                value.len()
+            ));
+        }
+
+        let data = info
+            .field("data")
+            .ok_or_else(|| anyhow!("Account has no data field"))?;
+        let balance = |name: &str| -> Result<Balance> {
+            data.uint(name)?
+                .ok_or_else(|| anyhow!("Account data has no {} field", name))
+        };
+
+        Ok(frame_system::AccountInfo {
+            nonce: info.counter("nonce")?,
+            consumers: info.counter("consumers")?,
+            providers: info.counter("providers")?,
+            sufficients: info.counter("sufficients")?,
+            data: AccountData {
+                free: balance("free")?,
+                reserved: balance("reserved")?,
+                misc_frozen: data.uint_or_zero(&["misc_frozen", "frozen"])?,
+                fee_frozen: data.uint_or_zero(&["fee_frozen", "flags"])?,
+            },
+        })
+    }
+}
+
+/// Returns the account ids among the `fields` of an event, from its SCALE encoded
+/// `data`, the other fields being skipped.
+pub fn event_accounts(
+    registry: &PortableRegistry,
+    fields: &[Field<PortableForm>],
+    mut data: &[u8],
+) -> Result<Vec<AccountId>> {
+    let mut account_ids = Vec::new();
+    for field in fields {
+        let id = field.ty().id();
+        let is_account_id = registry.resolve(id).map_or(false, |ty| {
+            ty.path()
+                .segments()
+                .last()
+                .map_or(false, |name| name == "AccountId32")
+        });
+        if is_account_id {
+            account_ids.push(AccountId::decode(&mut data)?);
+        } else {
+            decode_value(registry, id, &mut data)?;
+        }
+    }
+    Ok(account_ids)
+}
+
+fn decode_value(registry: &PortableRegistry, id: u32, input: &mut &[u8]) -> Result<Value> {
+    let ty = registry
+        .resolve(id)
+        .ok_or_else(|| anyhow!("Type {} not found in the metadata", id))?;
+
+    Ok(match ty.type_def() {
+        TypeDef::Composite(composite) => Value::Composite(
+            composite
+                .fields()
+                .iter()
+                .map(|field| {
+                    Ok((
+                        field.name().cloned(),
+                        decode_value(registry, field.ty().id(), input)?,
+                    ))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        TypeDef::Tuple(tuple) => Value::Composite(
+            tuple
+                .fields()
+                .iter()
+                .map(|field| Ok((None, decode_value(registry, field.id(), input)?)))
+                .collect::<Result<_>>()?,
+        ),
+        TypeDef::Primitive(primitive) => decode_primitive(primitive, input)?,
+        // Compact values of any width, or of a single field wrapper, share the
+        // same encoding.
+        TypeDef::Compact(_) => Value::Uint(Compact::<u128>::decode(input)?.0),
+        TypeDef::Array(array) => {
+            for _ in 0..array.len() {
+                decode_value(registry, array.type_param().id(), input)?;
+            }
+            Value::Other
+        }
+        TypeDef::Sequence(sequence) => {
+            let len = Compact::<u32>::decode(input)?.0;
+            for _ in 0..len {
+                decode_value(registry, sequence.type_param().id(), input)?;
+            }
+            Value::Other
+        }
+        TypeDef::Variant(variant) => {
+            let index = u8::decode(input)?;
+            let variant = variant
+                .variants()
+                .iter()
+                .find(|variant| variant.index() == index)
+                .ok_or_else(|| anyhow!("Variant {} of type {} not found", index, id))?;
+            for field in variant.fields() {
+                decode_value(registry, field.ty().id(), input)?;
+            }
+            Value::Other
+        }
+        TypeDef::BitSequence(_) => {
+            return Err(anyhow!("Bit sequence of type {} is not supported", id))
+        }
+    })
+}
+
+fn decode_primitive(primitive: &TypeDefPrimitive, input: &mut &[u8]) -> Result<Value> {
+    Ok(match primitive {
+        TypeDefPrimitive::U8 => Value::Uint(u8::decode(input)?.into()),
+        TypeDefPrimitive::U16 => Value::Uint(u16::decode(input)?.into()),
+        TypeDefPrimitive::U32 => Value::Uint(u32::decode(input)?.into()),
+        TypeDefPrimitive::U64 => Value::Uint(u64::decode(input)?.into()),
+        TypeDefPrimitive::U128 => Value::Uint(u128::decode(input)?),
+        TypeDefPrimitive::Bool => {
+            bool::decode(input)?;
+            Value::Other
+        }
+        TypeDefPrimitive::Char => {
+            u32::decode(input)?;
+            Value::Other
+        }
+        TypeDefPrimitive::Str => {
+            String::decode(input)?;
+            Value::Other
+        }
+        TypeDefPrimitive::I8 => skip(input, 1)?,
+        TypeDefPrimitive::I16 => skip(input, 2)?,
+        TypeDefPrimitive::I32 => skip(input, 4)?,
+        TypeDefPrimitive::I64 => skip(input, 8)?,
+        TypeDefPrimitive::I128 => skip(input, 16)?,
+        TypeDefPrimitive::U256 | TypeDefPrimitive::I256 => skip(input, 32)?,
+    })
+}
+
+fn skip(input: &mut &[u8], len: usize) -> Result<Value> {
+    if input.len() < len {
+        return Err(anyhow!("Unexpected end of the value"));
+    }
+    *input = &input[len..];
+    Ok(Value::Other)
+}
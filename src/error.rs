@@ -0,0 +1,111 @@
+//! Failures with a dedicated process exit code.
+//!
+//! | Code | Meaning                                  |
+//! |------|------------------------------------------|
+//! | 0    | Success                                  |
+//! | 1    | Any other failure                        |
+//! | 2    | Total issuance check mismatch            |
+//! | 3    | Connection or RPC failure                |
+//! | 4    | Assertion or expected count failure      |
+//! | 5    | IO or write failure                      |
+
+use crate::{pager, Balance, BlockHash, BlockNumber};
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The sum of all the accounts does not match `Balances::TotalIssuance`.
+    IssuanceMismatch {
+        iterated: Balance,
+        expected: Balance,
+    },
+    /// An assertion on the state or an expected count does not hold.
+    Assertion(String),
+    /// The node can't be reached, or doesn't serve what it should, e.g. while syncing.
+    Connection(String),
+    /// The account iteration yielded nothing.
+    NoAccounts {
+        block_number: BlockNumber,
+        block_hash: BlockHash,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::IssuanceMismatch { iterated, expected } => write!(
+                f,
+                "Total balance of all accounts {} does not match the total issuance {}",
+                iterated, expected
+            ),
+            Self::Assertion(msg) => write!(f, "Assertion failed: {}", msg),
+            Self::Connection(msg) => write!(f, "{}", msg),
+            Self::NoAccounts {
+                block_number,
+                block_hash,
+            } => write!(
+                f,
+                "No accounts found at block #{} ({:?}); is this an archive node with state at this height?",
+                block_number, block_hash
+            ),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Returns the process exit code for `err`.
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    for cause in err.chain() {
+        if let Some(err) = cause.downcast_ref::<Error>() {
+            return match err {
+                Error::IssuanceMismatch { .. } => 2,
+                Error::Connection(_) => 3,
+                Error::Assertion(_) | Error::NoAccounts { .. } => 4,
+            };
+        }
+        if cause.is::<pager::RuntimeUpgraded>() {
+            return 4;
+        }
+        if cause.is::<subxt::BasicError>() {
+            return 3;
+        }
+        if cause.is::<std::io::Error>() {
+            return 5;
+        }
+        if let Some(err) = cause.downcast_ref::<serde_json::Error>() {
+            if err.is_io() {
+                return 5;
+            }
+        }
+    }
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn exit_codes() {
+        let connection = anyhow::Error::from(Error::Connection("node serves another chain".into()));
+        assert_eq!(exit_code(&connection), 3);
+        assert_eq!(
+            exit_code(
+                &Err::<(), _>(connection)
+                    .context("Resolving the target")
+                    .unwrap_err()
+            ),
+            3
+        );
+        assert_eq!(exit_code(&Error::Assertion("overlap".into()).into()), 4);
+        let upgraded = pager::RuntimeUpgraded {
+            spec_version: 5,
+            new_spec_version: 6,
+            block_hash: BlockHash::zero(),
+        };
+        assert_eq!(exit_code(&upgraded.into()), 4);
+        assert_eq!(exit_code(&anyhow::anyhow!("anything else")), 1);
+    }
+}
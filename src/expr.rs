@@ -0,0 +1,313 @@
+//! A tiny integer expression evaluator for transforming the exported balances.
+//!
+//! Supported syntax:
+//!
+//! - unsigned integer literals, `_` can be used as a digit separator, e.g. `1_000`.
+//! - the variable `b`, which is bound to the balance of each account.
+//! - the binary operators `+`, `-`, `*`, `/` (integer division) and `%` (remainder),
+//!   `*`, `/` and `%` bind tighter than `+` and `-`, all are left associative.
+//! - parentheses for grouping.
+//!
+//! Everything is evaluated on `u128` with checked arithmetic, an overflow, an underflow
+//! or a division by zero is reported as an error instead of producing a wrapped value.
+//!
+//! The parser and the evaluator are recursive, so the expressions are limited to
+//! [`MAX_TOKENS`] tokens and [`MAX_NESTING`] levels of parentheses.
+
+use anyhow::{anyhow, Error, Result};
+use serde::{Serialize, Serializer};
+use std::str::FromStr;
+
+/// Most tokens of an expression.
+pub const MAX_TOKENS: usize = 1024;
+/// Most levels of nested parentheses of an expression.
+pub const MAX_NESTING: usize = 32;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    fn from_char(c: char) -> Self {
+        match c {
+            '+' => Self::Add,
+            '-' => Self::Sub,
+            '*' => Self::Mul,
+            '/' => Self::Div,
+            '%' => Self::Rem,
+            _ => unreachable!("Only operator chars are tokenized as Token::Op; qed"),
+        }
+    }
+
+    fn apply(self, lhs: u128, rhs: u128) -> Option<u128> {
+        match self {
+            Self::Add => lhs.checked_add(rhs),
+            Self::Sub => lhs.checked_sub(rhs),
+            Self::Mul => lhs.checked_mul(rhs),
+            Self::Div => lhs.checked_div(rhs),
+            Self::Rem => lhs.checked_rem(rhs),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(u128),
+    Balance,
+    Binary(Op, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn eval(&self, b: u128) -> Option<u128> {
+        match self {
+            Self::Literal(n) => Some(*n),
+            Self::Balance => Some(b),
+            Self::Binary(op, lhs, rhs) => op.apply(lhs.eval(b)?, rhs.eval(b)?),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Token {
+    Number(u128),
+    Var,
+    Op(char),
+    LParen,
+    RParen,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '0'..='9' => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_ascii_digit() {
+                        digits.push(c);
+                    } else if c != '_' {
+                        break;
+                    }
+                    chars.next();
+                }
+                let n = digits
+                    .parse::<u128>()
+                    .map_err(|_| anyhow!("Integer literal {} does not fit into u128", digits))?;
+                tokens.push(Token::Number(n));
+            }
+            'b' => {
+                chars.next();
+                tokens.push(Token::Var);
+            }
+            '+' | '-' | '*' | '/' | '%' => {
+                chars.next();
+                tokens.push(Token::Op(c));
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unexpected character `{}` in balance expression",
+                    c
+                ))
+            }
+        }
+        if tokens.len() > MAX_TOKENS {
+            return Err(anyhow!(
+                "Balance expression has more than {} tokens",
+                MAX_TOKENS
+            ));
+        }
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    /// Parentheses opened and not closed yet.
+    nesting: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.peek();
+        self.pos += 1;
+        token
+    }
+
+    fn expr(&mut self) -> Result<Node> {
+        let mut lhs = self.term()?;
+        while let Some(Token::Op(c @ ('+' | '-'))) = self.peek() {
+            self.pos += 1;
+            let rhs = self.term()?;
+            lhs = Node::Binary(Op::from_char(c), Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn term(&mut self) -> Result<Node> {
+        let mut lhs = self.factor()?;
+        while let Some(Token::Op(c @ ('*' | '/' | '%'))) = self.peek() {
+            self.pos += 1;
+            let rhs = self.factor()?;
+            lhs = Node::Binary(Op::from_char(c), Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn factor(&mut self) -> Result<Node> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Node::Literal(n)),
+            Some(Token::Var) => Ok(Node::Balance),
+            Some(Token::LParen) => {
+                if self.nesting == MAX_NESTING {
+                    return Err(anyhow!(
+                        "Balance expression nests more than {} parentheses",
+                        MAX_NESTING
+                    ));
+                }
+                self.nesting += 1;
+                let node = self.expr()?;
+                self.nesting -= 1;
+                match self.next() {
+                    Some(Token::RParen) => Ok(node),
+                    _ => Err(anyhow!("Missing closing parenthesis in balance expression")),
+                }
+            }
+            Some(token) => Err(anyhow!(
+                "Unexpected token {:?} in balance expression",
+                token
+            )),
+            None => Err(anyhow!("Unexpected end of balance expression")),
+        }
+    }
+}
+
+/// A parsed balance transform expression, e.g. `b / 100 + 1000`.
+#[derive(Debug, Clone)]
+pub struct BalanceExpr {
+    source: String,
+    root: Node,
+}
+
+impl BalanceExpr {
+    /// Evaluates the expression with `b` bound to `balance`.
+    pub fn eval(&self, balance: u128) -> Result<u128> {
+        self.root.eval(balance).ok_or_else(|| {
+            anyhow!(
+                "Balance expression `{}` overflowed or divided by zero for b = {}",
+                self.source,
+                balance
+            )
+        })
+    }
+}
+
+impl FromStr for BalanceExpr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+            nesting: 0,
+        };
+
+        let root = parser.expr()?;
+
+        if let Some(token) = parser.peek() {
+            return Err(anyhow!(
+                "Unexpected trailing token {:?} in balance expression",
+                token
+            ));
+        }
+
+        Ok(Self {
+            source: s.to_string(),
+            root,
+        })
+    }
+}
+
+impl Serialize for BalanceExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(s: &str, b: u128) -> Result<u128> {
+        s.parse::<BalanceExpr>()?.eval(b)
+    }
+
+    #[test]
+    fn precedence_and_associativity() {
+        assert_eq!(eval("b / 100 + 1_000", 5_000).unwrap(), 1_050);
+        assert_eq!(eval("1 + 2 * 3", 0).unwrap(), 7);
+        assert_eq!(eval("10 - 4 - 3", 0).unwrap(), 3);
+        assert_eq!(eval("100 / 10 / 5", 0).unwrap(), 2);
+        assert_eq!(eval("7 + b % 4 * 2", 11).unwrap(), 13);
+    }
+
+    #[test]
+    fn parentheses() {
+        assert_eq!(eval("(1 + 2) * 3", 0).unwrap(), 9);
+        assert_eq!(eval("10 - (4 - 3)", 0).unwrap(), 9);
+        assert_eq!(eval("((b))", 42).unwrap(), 42);
+        assert_eq!(eval(" ( b + 1 ) * ( b - 1 ) ", 3).unwrap(), 8);
+    }
+
+    #[test]
+    fn overflow_underflow_and_division_by_zero() {
+        assert!(eval("b + 1", u128::MAX).is_err());
+        assert!(eval("b * 2", u128::MAX / 2 + 1).is_err());
+        assert!(eval("b - 1", 0).is_err());
+        assert!(eval("b / 0", 1).is_err());
+        assert!(eval("b % (b - b)", 1).is_err());
+        assert_eq!(eval("b + 1", u128::MAX - 1).unwrap(), u128::MAX);
+        assert!("340282366920938463463374607431768211456"
+            .parse::<BalanceExpr>()
+            .is_err());
+    }
+
+    #[test]
+    fn malformed() {
+        for s in ["", "b +", "* b", "(b", "b)", "b b", "2 ^ 3", "a + 1", "()"] {
+            assert!(s.parse::<BalanceExpr>().is_err(), "{:?} parsed", s);
+        }
+    }
+
+    #[test]
+    fn nesting_and_size_limits() {
+        let nested = |depth| format!("{}b{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(eval(&nested(MAX_NESTING), 7).unwrap(), 7);
+        assert!(nested(MAX_NESTING + 1).parse::<BalanceExpr>().is_err());
+        assert!(nested(100_000).parse::<BalanceExpr>().is_err());
+        assert!(["b"; MAX_TOKENS].join("+").parse::<BalanceExpr>().is_err());
+    }
+}
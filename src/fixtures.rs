@@ -0,0 +1,361 @@
+//! Fixed account set of the determinism test, along with the test itself.
+//!
+//! The test runs the decoding, the classification and every writer of a snapshot over
+//! the fixture storage and checks the totals, the Merkle root and the exact bytes of
+//! each format. Any change of the expected values must come with a deliberate format
+//! change, bumping [`FORMAT_VERSION`](crate::snapshot::FORMAT_VERSION) where it applies.
+
+use crate::snapshot::{self, Entry};
+use crate::storage::{account_storage_key, account_storage_prefix};
+use crate::subspace::runtime_types::{frame_system, pallet_balances::AccountData};
+use crate::{
+    binary, canonical, classify_accounts, debug_entries, error, genesis, ndjson, output, pager,
+    raw, special_accounts, warnings, AccountId, AccountInfo, Balance, BlockHash, Classified, Cli,
+    Selection,
+};
+use clap::Parser;
+use codec::{Decode, Encode};
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use subspace_regenesis_tool::{AccountFilter, Disposition, RawAccountId};
+use subxt::sp_core::storage::StorageKey;
+
+const UNIT: Balance = 1_000_000_000_000_000_000;
+
+struct Account {
+    account_id: AccountId,
+    free: Balance,
+    reserved: Balance,
+}
+
+fn accounts() -> Vec<Account> {
+    let (endowed, token_grants) = special_accounts().unwrap();
+    let account = |byte: u8, free, reserved| Account {
+        account_id: AccountId::new([byte; 32]),
+        free,
+        reserved,
+    };
+    vec![
+        // Endowed and token grant accounts, skipped.
+        Account {
+            account_id: endowed[1].clone(),
+            free: 1_000_000 * UNIT,
+            reserved: 0,
+        },
+        Account {
+            account_id: token_grants[0].clone(),
+            free: 100_000 * UNIT,
+            reserved: 20 * UNIT,
+        },
+        account(1, 1_000 * UNIT, 0),
+        account(2, UNIT / 2, 0),
+        // Reserved balance, only carried over as free by `--balance-kind
+        // free-plus-reserved`.
+        account(5, 10 * UNIT, 2 * UNIT),
+        // Dust.
+        account(3, 1, 0),
+        // Exported with a zero balance by default.
+        account(4, 0, 0),
+    ]
+}
+
+/// Returns the `System::Account` storage of the fixture as `(key, value)` pairs, in
+/// storage order.
+pub fn storage() -> Vec<(Vec<u8>, Vec<u8>)> {
+    let mut storage = accounts()
+        .into_iter()
+        .map(|account| {
+            let info = frame_system::AccountInfo {
+                nonce: 0u32,
+                consumers: 0,
+                providers: 1,
+                sufficients: 0,
+                data: AccountData {
+                    free: account.free,
+                    reserved: account.reserved,
+                    misc_frozen: 0,
+                    fee_frozen: 0,
+                },
+            };
+            (account_storage_key(&account.account_id), info.encode())
+        })
+        .collect::<Vec<_>>();
+    storage.sort();
+    storage
+}
+
+/// Returns the accounts of the fixture as iterated from storage.
+pub fn entries() -> Vec<pager::AccountEntry> {
+    let prefix = account_storage_prefix();
+    storage()
+        .into_iter()
+        .map(|(key, value)| {
+            let info = AccountInfo::decode(&mut value.as_slice()).unwrap();
+            pager::account_entry(StorageKey(key), &prefix, info, false).unwrap()
+        })
+        .collect()
+}
+
+/// Number of accounts carried over, the special accounts being skipped.
+const NEW_ACCOUNTS: usize = 5;
+/// Sum of the free and reserved balances of all the accounts.
+const TOTAL_ISSUANCE: Balance = 1_101_032_500_000_000_000_000_001;
+/// Sum of the balances of the new accounts.
+const NEW_ISSUANCE: Balance = 1_012_500_000_000_000_000_001;
+/// Merkle root of the new accounts, see [`snapshot::merkle_root`].
+const MERKLE_ROOT: &str = "7764014e05fd29f9e58727363a3eab84cf4a042778a9fc34671387cfb424e39f";
+/// Expected SHA-256 of the snapshot in every format but Parquet.
+const SHA256: &[(&str, &str)] = &[
+    (
+        "json",
+        "86f8506ff975f3105f83dab40a96aba9205224b5503381e1fe806d67db02bfeb",
+    ),
+    (
+        "canonical-json",
+        "0554ae2344dae3853d94cb00fd2a9e2ab18590e0409ecdcdb3a973ecb6c4d14b",
+    ),
+    (
+        "ndjson",
+        "1bbaae7d9b728c9bae483d7c150df99523ca8073b64a80b11db346c7521869a1",
+    ),
+    (
+        "bin",
+        "832a3a84dde15ffcb592e777002a21923619abae36e730d7681c421b92c0d292",
+    ),
+    (
+        "debug-json",
+        "b72a3a0d64fd75d2170d2cc81b48286136ce84914aa596273c24306417616c7c",
+    ),
+    (
+        "raw-top",
+        "9517cabdae40411514ee39065cda11a30b3693951e4ce23b5bb1c7a857701051",
+    ),
+    (
+        "kv",
+        "18d37d147664658a18c38d2421c346f5c4a2aa1a362fc1070360a44d4487f6b2",
+    ),
+    (
+        "subspace-genesis",
+        "4ed1098fbca2b097427b0f65cbc2bc9e5677ca3ef401d04aa2f950e2d84f080d",
+    ),
+];
+
+/// Returns an empty scratch directory for the test `name`.
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "regenesis-fixtures-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn classify_with(
+    cli: &Cli,
+    selection: &Selection<impl AccountFilter>,
+) -> anyhow::Result<Classified> {
+    let mut warnings = warnings::Warnings::open(None, false, cli.formatter())?;
+    let classified = classify_accounts(
+        cli,
+        entries(),
+        selection,
+        &BTreeSet::new(),
+        None,
+        true,
+        &mut warnings,
+    )?;
+    warnings.finish()?;
+    Ok(classified)
+}
+
+fn classify(cli: &Cli) -> anyhow::Result<Classified> {
+    let (endowed, token_grants) = special_accounts().unwrap();
+    classify_with(cli, &Selection::special(&endowed, &token_grants))
+}
+
+fn pairs(entries: &[Entry]) -> Vec<(AccountId, Balance)> {
+    entries
+        .iter()
+        .map(|Entry(account_id, balance)| (account_id.clone(), *balance))
+        .collect()
+}
+
+#[test]
+fn reserved_balance_is_rejected_by_default() {
+    let cli = Cli::try_parse_from(["subspace-regenesis-tool"]).unwrap();
+    let err = classify(&cli).err().unwrap();
+    assert!(matches!(
+        err.downcast_ref::<error::Error>(),
+        Some(error::Error::Assertion(_))
+    ));
+}
+
+/// Skips the special accounts and redirects `[3; 32]` and `[4; 32]` to `[1; 32]`
+/// and `[6; 32]` respectively.
+struct Redirects(Selection<crate::SkipAccounts>);
+
+impl AccountFilter for Redirects {
+    fn classify(&self, account_id: &RawAccountId, balance: Balance) -> Disposition {
+        if *account_id == [3; 32] {
+            Disposition::Redirect([1; 32])
+        } else if *account_id == [4; 32] {
+            Disposition::Redirect([6; 32])
+        } else {
+            self.0.filter.classify(account_id, balance)
+        }
+    }
+}
+
+#[test]
+fn redirected_balances_are_added_to_the_target() {
+    let cli = Cli::try_parse_from([
+        "subspace-regenesis-tool",
+        "--balance-kind",
+        "free-plus-reserved",
+    ])
+    .unwrap();
+    let (endowed, token_grants) = special_accounts().unwrap();
+    let special = Selection::special(&endowed, &token_grants);
+    let selection = Selection {
+        endowed: special.endowed.clone(),
+        token_grants: special.token_grants.clone(),
+        filter: Redirects(special),
+    };
+    let classified = classify_with(&cli, &selection).unwrap();
+
+    let mut balances = pairs(&classified.new_accounts);
+    balances.sort();
+    let mut expected = vec![
+        (AccountId::new([1; 32]), 1_000 * UNIT + 1),
+        (AccountId::new([2; 32]), UNIT / 2),
+        (AccountId::new([5; 32]), 12 * UNIT),
+        (AccountId::new([6; 32]), 0),
+    ];
+    expected.sort();
+    assert_eq!(balances, expected);
+    assert_eq!(classified.accounting.total().unwrap(), TOTAL_ISSUANCE);
+}
+
+#[test]
+fn snapshot_output_is_deterministic() {
+    let cli = Cli::try_parse_from([
+        "subspace-regenesis-tool",
+        "--balance-kind",
+        "free-plus-reserved",
+    ])
+    .unwrap();
+    let classified = classify(&cli).unwrap();
+    let entries = &classified.new_accounts;
+
+    assert_eq!(classified.total_issuance, TOTAL_ISSUANCE);
+    assert_eq!(classified.accounting.total().unwrap(), TOTAL_ISSUANCE);
+    assert_eq!(entries.len(), NEW_ACCOUNTS);
+    assert_eq!(
+        entries
+            .iter()
+            .map(|Entry(_, balance)| balance)
+            .sum::<Balance>(),
+        NEW_ISSUANCE
+    );
+    assert_eq!(hex::encode(snapshot::merkle_root(entries)), MERKLE_ROOT);
+
+    let dir = scratch_dir("determinism");
+    let prefix = account_storage_prefix();
+    let writes = classified
+        .raw_values
+        .storage_writes(&prefix, entries)
+        .unwrap();
+    let write = |name: &str, f: &dyn Fn(&mut output::FileWriter) -> anyhow::Result<()>| {
+        let path = dir.join(name);
+        let mut writer = output::FileWriter::create(&path).unwrap();
+        f(&mut writer).unwrap();
+        writer.finish(false).unwrap();
+        path
+    };
+    let paths = [
+        (
+            "json",
+            write("balances.json", &|writer| {
+                Ok(output::write_json(writer, entries, cli.json_indent)?)
+            }),
+        ),
+        (
+            "canonical-json",
+            write("balances.canonical.json", &|writer| {
+                canonical::write(writer, entries)
+            }),
+        ),
+        (
+            "ndjson",
+            write("balances.ndjson", &|writer| {
+                ndjson::write(writer, entries, true)
+            }),
+        ),
+        (
+            "bin",
+            write("balances.bin", &|writer| {
+                binary::write(writer, entries, BlockHash::zero(), None).map(drop)
+            }),
+        ),
+        (
+            "debug-json",
+            write("balances.debug.json", &|writer| {
+                Ok(output::write_json(
+                    writer,
+                    &debug_entries(&cli, entries),
+                    cli.json_indent,
+                )?)
+            }),
+        ),
+        (
+            "raw-top",
+            write("balances.raw-top.json", &|writer| {
+                raw::write_top(writer, &writes, cli.json_indent)
+            }),
+        ),
+        (
+            "kv",
+            write("balances.kv", &|writer| raw::write_kv(writer, &writes)),
+        ),
+        (
+            "subspace-genesis",
+            write("balances.subspace-genesis.json", &|writer| {
+                genesis::write(writer, entries, None, cli.json_indent)
+            }),
+        ),
+    ];
+
+    let digests = paths
+        .iter()
+        .map(|(format, path)| (*format, output::sha256_file(path).unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        digests,
+        SHA256
+            .iter()
+            .map(|(format, sha256)| (*format, sha256.to_string()))
+            .collect::<Vec<_>>()
+    );
+
+    // The snapshot formats read back to the same accounts, the canonical one in
+    // address order.
+    let mut sorted = pairs(entries);
+    sorted.sort_by_key(|(account_id, _)| account_id.to_string());
+    for (format, path) in &paths {
+        let expected = match *format {
+            "json" | "ndjson" | "bin" => pairs(entries),
+            "canonical-json" => sorted.clone(),
+            _ => continue,
+        };
+        assert_eq!(
+            pairs(&snapshot::load(path).unwrap()),
+            expected,
+            "{}",
+            format
+        );
+    }
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,50 @@
+//! Human friendly rendering of balances.
+
+use crate::Balance;
+
+/// Renders balances with token decimals and symbol, for display only.
+#[derive(Debug, Clone, Default)]
+pub struct BalanceFormatter {
+    pub decimals: Option<u8>,
+    pub symbol: Option<String>,
+}
+
+impl BalanceFormatter {
+    /// Renders `balance` as e.g. `1,234.5678 tSSC`.
+    pub fn format(&self, balance: Balance) -> String {
+        let decimals = self.decimals.unwrap_or_default() as usize;
+        let padded = format!("{:0>width$}", balance, width = decimals + 1);
+        let (integer, fraction) = padded.split_at(padded.len() - decimals);
+        let fraction = fraction.trim_end_matches('0');
+
+        let mut rendered = String::with_capacity(padded.len() * 2);
+        for (i, c) in integer.chars().enumerate() {
+            if i > 0 && (integer.len() - i) % 3 == 0 {
+                rendered.push(',');
+            }
+            rendered.push(c);
+        }
+
+        if !fraction.is_empty() {
+            rendered.push('.');
+            rendered.push_str(fraction);
+        }
+
+        if let Some(symbol) = &self.symbol {
+            rendered.push(' ');
+            rendered.push_str(symbol);
+        }
+
+        rendered
+    }
+
+    /// Renders the raw `balance`, followed by the formatted one if decimals or symbol
+    /// are specified.
+    pub fn display(&self, balance: Balance) -> String {
+        if self.decimals.is_none() && self.symbol.is_none() {
+            balance.to_string()
+        } else {
+            format!("{} ({})", balance, self.format(balance))
+        }
+    }
+}
@@ -0,0 +1,172 @@
+//! Snapshot as the balances and vesting sections of the Subspace runtime
+//! `GenesisConfig`, see `--format subspace-genesis`.
+//!
+//! The output is checked against `subspace_genesis_sample.json`, a sample of the
+//! serde shape of the runtime: every object member must be in the sample, a one
+//! element array of the sample is a list whose elements all have the shape of that
+//! element, a longer one is a tuple matched member by member, and the leaves must be
+//! of the same JSON type.
+
+use crate::snapshot::Entry;
+use crate::{output, AccountId, Balance, BlockNumber};
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use serde_json::Value;
+use std::io::Write;
+
+/// Serde shape of the Subspace runtime `GenesisConfig`, balances and vesting only.
+const SAMPLE: &str = include_str!("../subspace_genesis_sample.json");
+
+/// `(account_id, start, period, period_count, per_period)` of `orml_vesting`.
+pub type VestingSchedule = (AccountId, BlockNumber, BlockNumber, u32, Balance);
+
+#[derive(Serialize)]
+struct BalancesConfig<'a> {
+    balances: &'a [Entry],
+}
+
+#[derive(Serialize)]
+struct VestingConfig<'a> {
+    vesting: &'a [VestingSchedule],
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenesisConfig<'a> {
+    balances: BalancesConfig<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vesting: Option<VestingConfig<'a>>,
+}
+
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+/// Checks that `value` at `path` has the shape of `sample`.
+fn check_shape(path: &str, value: &Value, sample: &Value) -> Result<()> {
+    match (value, sample) {
+        (Value::Object(members), Value::Object(sample)) => {
+            for (name, member) in members {
+                let sample = sample
+                    .get(name)
+                    .ok_or_else(|| anyhow!("{}.{} is not in the genesis sample", path, name))?;
+                check_shape(&format!("{}.{}", path, name), member, sample)?;
+            }
+            Ok(())
+        }
+        (Value::Array(elements), Value::Array(sample)) if sample.len() == 1 => {
+            for (index, element) in elements.iter().enumerate() {
+                check_shape(&format!("{}[{}]", path, index), element, &sample[0])?;
+            }
+            Ok(())
+        }
+        (Value::Array(elements), Value::Array(sample)) => {
+            if elements.len() != sample.len() {
+                return Err(anyhow!(
+                    "{} has {} members instead of the {} of the genesis sample",
+                    path,
+                    elements.len(),
+                    sample.len()
+                ));
+            }
+            for (index, (element, sample)) in elements.iter().zip(sample).enumerate() {
+                check_shape(&format!("{}[{}]", path, index), element, sample)?;
+            }
+            Ok(())
+        }
+        _ if json_type(value) == json_type(sample) => Ok(()),
+        _ => Err(anyhow!(
+            "{} is a {} instead of the {} of the genesis sample",
+            path,
+            json_type(value),
+            json_type(sample)
+        )),
+    }
+}
+
+/// Writes the genesis config of `entries` and `vesting` after checking it against
+/// the sample.
+pub fn write<W: Write>(
+    mut writer: W,
+    entries: &[Entry],
+    vesting: Option<&[VestingSchedule]>,
+    indent: usize,
+) -> Result<()> {
+    let config = GenesisConfig {
+        balances: BalancesConfig { balances: entries },
+        vesting: vesting.map(|vesting| VestingConfig { vesting }),
+    };
+    let mut bytes = Vec::new();
+    output::write_json(&mut bytes, &config, indent)?;
+
+    // Without `arbitrary_precision` the balances above `u64::MAX` parse as floats,
+    // which is enough for their JSON type.
+    let value: Value = serde_json::from_slice(&bytes)?;
+    let sample: Value = serde_json::from_str(SAMPLE).expect("Genesis sample is valid JSON; qed");
+    check_shape("genesis", &value, &sample)?;
+
+    writer.write_all(&bytes)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn write_matches_the_sample() {
+        let entries = vec![
+            Entry(AccountId::new([1; 32]), 1_000),
+            Entry(AccountId::new([2; 32]), Balance::MAX),
+        ];
+        let vesting = vec![(AccountId::new([1; 32]), 0, 100, 10, 100)];
+
+        let mut bytes = Vec::new();
+        write(&mut bytes, &entries, Some(&vesting), 2).unwrap();
+        let genesis: Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            genesis["balances"]["balances"][0],
+            json!([AccountId::new([1; 32]).to_string(), 1_000])
+        );
+        assert_eq!(
+            genesis["balances"]["balances"].as_array().map(Vec::len),
+            Some(2)
+        );
+        assert_eq!(
+            genesis["vesting"]["vesting"][0],
+            json!([AccountId::new([1; 32]).to_string(), 0, 100, 10, 100])
+        );
+
+        let mut bytes = Vec::new();
+        write(&mut bytes, &entries, None, 0).unwrap();
+        let genesis: Value = serde_json::from_slice(&bytes).unwrap();
+        assert!(genesis.get("vesting").is_none());
+    }
+
+    #[test]
+    fn check_shape_rejects_other_shapes() {
+        let sample: Value = serde_json::from_str(SAMPLE).unwrap();
+        let check = |value: Value| check_shape("genesis", &value, &sample);
+
+        assert!(check(json!({ "balances": { "balances": [] } })).is_ok());
+        assert!(check(json!({ "balances": { "balances": [["a", 1], ["b", 2]] } })).is_ok());
+        // Unknown member.
+        assert!(check(json!({ "balance": { "balances": [] } })).is_err());
+        // Balance as a string.
+        assert!(check(json!({ "balances": { "balances": [["a", "1"]] } })).is_err());
+        // Tuple of the wrong length.
+        assert!(check(json!({ "vesting": { "vesting": [["a", 0, 100, 10]] } })).is_err());
+        // List instead of an object.
+        assert!(check(json!({ "balances": [] })).is_err());
+    }
+}
@@ -0,0 +1,164 @@
+//! Periodic progress lines for log aggregators, and the `--progress-file` for
+//! supervisors polling a file.
+
+use crate::Balance;
+use anyhow::Result;
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+use tokio::time::{interval, Duration, Instant};
+
+/// Seconds between two writes of the `--progress-file`.
+pub const PROGRESS_FILE_SECS: u64 = 5;
+
+/// Stage of a snapshot run.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Phase {
+    /// Fetching the accounts.
+    #[default]
+    Iterating,
+    /// Filtering and checking the accounts.
+    Checking,
+    /// Writing the snapshot.
+    Writing,
+    Done,
+}
+
+/// Progress of the account iteration, shared by all the shards.
+#[derive(Debug, Default)]
+pub struct Progress {
+    accounts: AtomicUsize,
+    total: Mutex<Balance>,
+    last_key: Mutex<Option<Vec<u8>>>,
+    phase: Mutex<Phase>,
+}
+
+impl Progress {
+    /// Records one more account holding `total`, stored under `key`.
+    pub fn record(&self, total: Balance, key: &[u8]) {
+        self.accounts.fetch_add(1, Ordering::Relaxed);
+        let mut sum = self.total.lock().expect("Heartbeat never panics; qed");
+        *sum = sum.saturating_add(total);
+        *self.last_key.lock().expect("Heartbeat never panics; qed") = Some(key.to_vec());
+    }
+}
+
+#[derive(Serialize)]
+struct Heartbeat {
+    event: &'static str,
+    accounts: usize,
+    total: Balance,
+    elapsed_secs: u64,
+}
+
+/// Prints a heartbeat JSON line every `secs` seconds until the returned task is aborted.
+pub fn spawn(progress: Arc<Progress>, secs: u64) -> JoinHandle<()> {
+    let start = Instant::now();
+    tokio::spawn(async move {
+        let mut ticks = interval(Duration::from_secs(secs));
+        // The first tick completes immediately.
+        ticks.tick().await;
+        loop {
+            ticks.tick().await;
+            let heartbeat = Heartbeat {
+                event: "heartbeat",
+                accounts: progress.accounts.load(Ordering::Relaxed),
+                total: *progress.total.lock().expect("Heartbeat never panics; qed"),
+                elapsed_secs: start.elapsed().as_secs(),
+            };
+            if let Ok(line) = serde_json::to_string(&heartbeat) {
+                status!("{}", line);
+            }
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct ProgressReport {
+    accounts_processed: usize,
+    elapsed_secs: u64,
+    /// Storage key of the account recorded last, by any of the shards.
+    last_key: Option<String>,
+    phase: Phase,
+}
+
+/// `--progress-file`, overwritten every [`PROGRESS_FILE_SECS`] and on every phase
+/// change until dropped.
+pub struct ProgressFile {
+    progress: Arc<Progress>,
+    path: PathBuf,
+    start: Instant,
+    task: JoinHandle<()>,
+}
+
+/// Overwrites `path` through a temporary file renamed over it, so that readers never
+/// see a partial file.
+fn write_progress(progress: &Progress, path: &std::path::Path, start: Instant) -> Result<()> {
+    let report = ProgressReport {
+        accounts_processed: progress.accounts.load(Ordering::Relaxed),
+        elapsed_secs: start.elapsed().as_secs(),
+        last_key: progress
+            .last_key
+            .lock()
+            .expect("Heartbeat never panics; qed")
+            .as_ref()
+            .map(|key| format!("0x{}", hex::encode(key))),
+        phase: *progress.phase.lock().expect("Heartbeat never panics; qed"),
+    };
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    std::fs::write(&tmp_path, serde_json::to_vec(&report)?)?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+impl ProgressFile {
+    pub fn spawn(progress: Arc<Progress>, path: PathBuf) -> Result<Self> {
+        let start = Instant::now();
+        write_progress(&progress, &path, start)?;
+        let task = {
+            let progress = progress.clone();
+            let path = path.clone();
+            tokio::spawn(async move {
+                let mut ticks = interval(Duration::from_secs(PROGRESS_FILE_SECS));
+                // The first tick completes immediately.
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    if let Err(err) = write_progress(&progress, &path, start) {
+                        status!(
+                            "Failed to write the progress file {}: {}",
+                            path.display(),
+                            err
+                        );
+                    }
+                }
+            })
+        };
+        Ok(Self {
+            progress,
+            path,
+            start,
+            task,
+        })
+    }
+
+    /// Moves on to `phase`, writing the file right away.
+    pub fn set_phase(&self, phase: Phase) -> Result<()> {
+        *self
+            .progress
+            .phase
+            .lock()
+            .expect("Heartbeat never panics; qed") = phase;
+        write_progress(&self.progress, &self.path, self.start)
+    }
+}
+
+impl Drop for ProgressFile {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
@@ -0,0 +1,450 @@
+//! Library interface to build the genesis of the new network from the snapshots, for
+//! a node's `chain_spec.rs` to call directly instead of parsing the JSON by hand.
+//!
+//! The account ids are handed out as any type convertible from their raw 32 bytes,
+//! e.g. the `AccountId32` of the `sp-core` version the node depends on.
+
+/// Prints a status message, to stderr when stdout carries the snapshot.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::stdout_reserved() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+pub mod dynamic;
+pub mod pager;
+pub mod ratelimit;
+pub mod storage;
+
+use anyhow::{anyhow, Context, Result};
+use clap::ArgEnum;
+use futures::{Stream, TryStreamExt};
+use pager::AccountPager;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use subxt::sp_core::crypto::{AccountId32 as AccountId, Ss58Codec};
+use subxt::sp_core::H256;
+use subxt::{Client, ClientBuilder, DefaultConfig};
+
+#[subxt::subxt(runtime_metadata_path = "subspace_metadata.scale")]
+pub mod subspace {}
+
+pub type AccountData = subspace::runtime_types::pallet_balances::AccountData<Balance>;
+pub type AccountInfo = subspace::runtime_types::frame_system::AccountInfo<u32, AccountData>;
+pub type Balance = u128;
+pub type BlockHash = H256;
+pub type BlockNumber = u32;
+/// Raw bytes of an account id.
+pub type RawAccountId = [u8; 32];
+
+/// A `--format json` snapshot as `(account_id, balance)` pairs, in file order.
+pub type Snapshot = Vec<(RawAccountId, Balance)>;
+
+fn parse_account_id(address: &str) -> Result<RawAccountId> {
+    let account_id = AccountId::from_ss58check(address)
+        .map_err(|err| anyhow!("Invalid account {}: {:?}", address, err))?;
+    Ok(account_id.into())
+}
+
+/// Reads a `--format json` snapshot, rejecting duplicate accounts.
+pub fn load_snapshot(path: &Path) -> Result<Snapshot> {
+    let raw: Vec<(String, Balance)> =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+            .with_context(|| format!("Failed to parse snapshot {}", path.display()))?;
+
+    let mut seen = BTreeSet::new();
+    raw.into_iter()
+        .map(|(address, balance)| {
+            let account_id = parse_account_id(&address)?;
+            if !seen.insert(account_id) {
+                return Err(anyhow!(
+                    "Account {} appears more than once in {}",
+                    address,
+                    path.display()
+                ));
+            }
+            Ok((account_id, balance))
+        })
+        .collect()
+}
+
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Reserves stdout for the snapshot, the status messages go to stderr from now on.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether stdout carries the snapshot.
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BalanceKind {
+    /// The free balance, new accounts with a reserved balance are rejected.
+    Free,
+    /// The free balance minus the frozen balance, clamped at zero, new accounts with
+    /// a reserved balance are rejected.
+    Transferable,
+    /// The free plus the reserved balance, i.e. the total balance, the reserves are
+    /// migrated as free. Also accepted as `total`.
+    #[clap(alias = "total")]
+    FreePlusReserved,
+}
+
+impl BalanceKind {
+    /// Whether the new accounts must not hold any reserved balance.
+    pub fn is_strict(self) -> bool {
+        matches!(self, Self::Free | Self::Transferable)
+    }
+
+    /// Returns the balance to export from the account data.
+    pub fn exported(self, data: &AccountData) -> Result<Balance> {
+        match self {
+            Self::Free => Ok(data.free),
+            Self::Transferable => Ok(data
+                .free
+                .saturating_sub(data.misc_frozen.max(data.fee_frozen))),
+            Self::FreePlusReserved => data.free.checked_add(data.reserved).ok_or_else(|| {
+                anyhow!(
+                    "Free plus reserved balance {} + {} overflows",
+                    data.free,
+                    data.reserved
+                )
+            }),
+        }
+    }
+}
+
+/// Reads the `kind` balance of every account of `pager`, in storage order.
+///
+/// The pager decides which accounts are read and how, e.g. a shard of the keyspace
+/// with [`pager::shard_range`] or a rate limit. A reserved balance is rejected under
+/// a strict `kind`, like in the snapshot runs of the tool.
+pub async fn collect_balances(pager: &mut AccountPager, kind: BalanceKind) -> Result<Snapshot> {
+    let mut snapshot = Snapshot::new();
+    let mut entries = Box::pin(pager::account_entries(pager));
+    while let Some(entry) = entries.try_next().await? {
+        if kind.is_strict() && entry.total != entry.info.data.free {
+            return Err(anyhow!(
+                "Account {} has a reserved balance, which {:?} does not export",
+                entry.account_id,
+                kind
+            ));
+        }
+        snapshot.push((entry.account_id.into(), kind.exported(&entry.info.data)?));
+    }
+    Ok(snapshot)
+}
+
+/// Reads the `kind` balance of every `System::Account` account at the block `at`, in
+/// storage order, over an already connected `client`, e.g. the `client` of a runtime
+/// API handle, so that many blocks can be snapshotted over a single connection.
+///
+/// Unlike the snapshot runs of the tool no account is skipped, run [`apply_filter`]
+/// over the result to leave out the special accounts. Use [`collect_balances`] to
+/// read a shard of the accounts or with a rate limit.
+pub async fn snapshot_balances(
+    client: &Client<DefaultConfig>,
+    at: BlockHash,
+    kind: BalanceKind,
+) -> Result<Snapshot> {
+    let mut pager = system_account_pager(client, at)?;
+    collect_balances(&mut pager, kind).await
+}
+
+/// Streams the accounts of `System::Account` at the block `at` of the node at `url`,
+/// in storage order, with their free plus reserved balance.
+///
+/// Nothing is skipped nor checked, the consumer applies its own policy. The connection
+/// is only opened once the stream is polled, and any failure ends it with an error.
+pub fn account_stream(
+    url: &str,
+    at: BlockHash,
+) -> impl Stream<Item = Result<(RawAccountId, Balance)>> {
+    let url = url.to_owned();
+    futures::stream::once(async move {
+        let client = ClientBuilder::new()
+            .set_url(&url)
+            .build::<DefaultConfig>()
+            .await?;
+        system_account_pager(&client, at)
+    })
+    .map_ok(pager::account_entries)
+    .try_flatten()
+    .map_ok(|entry| (entry.account_id.into(), entry.total))
+}
+
+/// Returns a pager over the `System::Account` accounts at `at`.
+fn system_account_pager(client: &Client<DefaultConfig>, at: BlockHash) -> Result<AccountPager> {
+    let account_storage = "System.Account".parse::<storage::AccountStorage>()?;
+    let prefix = account_storage.resolve(client)?;
+    let mut pager = AccountPager::with_range(client.clone(), &prefix, at, None, None);
+    if account_storage.has_extra_flags(client)? {
+        pager = pager.extra_flags();
+    }
+    Ok(pager)
+}
+
+/// Same as [`snapshot_balances`], connecting to the node at `url` first.
+pub async fn snapshot_balances_at(url: &str, at: BlockHash, kind: BalanceKind) -> Result<Snapshot> {
+    let client = ClientBuilder::new()
+        .set_url(url)
+        .build::<DefaultConfig>()
+        .await?;
+    snapshot_balances(&client, at, kind).await
+}
+
+/// Returns the `balances` of the `pallet_balances` genesis config.
+pub fn to_balances_genesis<A: From<RawAccountId>>(snapshot: &Snapshot) -> Vec<(A, Balance)> {
+    snapshot
+        .iter()
+        .map(|(account_id, balance)| (A::from(*account_id), *balance))
+        .collect()
+}
+
+/// Returns the balances to add to the genesis for the `endowed` accounts, e.g. the
+/// sudo and dev accounts skipped by the snapshot.
+///
+/// Fails if one of them is already in the snapshot, the balances genesis config
+/// rejects duplicate accounts.
+pub fn endowment_additions<A: From<RawAccountId>>(
+    snapshot: &Snapshot,
+    endowed: &[RawAccountId],
+    amount: Balance,
+) -> Result<Vec<(A, Balance)>> {
+    endowed
+        .iter()
+        .map(|account_id| {
+            if snapshot.iter().any(|(existing, _)| existing == account_id) {
+                return Err(anyhow!(
+                    "Endowed account {} is already in the snapshot",
+                    AccountId::from(*account_id)
+                ));
+            }
+            Ok((A::from(*account_id), amount))
+        })
+        .collect()
+}
+
+/// What to do with an account of the snapshot, see [`AccountFilter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+    /// Carry the account over with its balance.
+    Include,
+    /// Leave the account out.
+    Skip,
+    /// Credit the balance of the account to another one instead.
+    Redirect(RawAccountId),
+}
+
+/// Policy deciding which accounts are carried over to the new network.
+pub trait AccountFilter {
+    fn classify(&self, account: &RawAccountId, balance: Balance) -> Disposition;
+}
+
+/// The default policy: skips the given accounts, e.g. the endowed and token grant
+/// accounts the new genesis sets up on its own, and includes all the others.
+#[derive(Debug, Clone, Default)]
+pub struct SkipAccounts(BTreeSet<RawAccountId>);
+
+impl SkipAccounts {
+    pub fn new(accounts: impl IntoIterator<Item = RawAccountId>) -> Self {
+        Self(accounts.into_iter().collect())
+    }
+}
+
+impl AccountFilter for SkipAccounts {
+    fn classify(&self, account: &RawAccountId, _balance: Balance) -> Disposition {
+        if self.0.contains(account) {
+            Disposition::Skip
+        } else {
+            Disposition::Include
+        }
+    }
+}
+
+/// Applies `filter` to every account of `snapshot`.
+///
+/// The redirected balances are added to their target, which is appended if it is not
+/// in the snapshot, the other accounts keep their order.
+pub fn apply_filter(snapshot: &Snapshot, filter: &impl AccountFilter) -> Result<Snapshot> {
+    let mut filtered = Snapshot::with_capacity(snapshot.len());
+    let mut redirected = Vec::new();
+    for (account_id, balance) in snapshot {
+        match filter.classify(account_id, *balance) {
+            Disposition::Include => filtered.push((*account_id, *balance)),
+            Disposition::Skip => {}
+            Disposition::Redirect(target) => redirected.push((target, *balance)),
+        }
+    }
+
+    let mut index = filtered
+        .iter()
+        .enumerate()
+        .map(|(i, (account_id, _))| (*account_id, i))
+        .collect::<BTreeMap<_, _>>();
+    for (target, balance) in redirected {
+        match index.get(&target) {
+            Some(&i) => {
+                let existing = &mut filtered[i].1;
+                *existing = existing.checked_add(balance).ok_or_else(|| {
+                    anyhow!(
+                        "Balance of {} overflows with the redirected balances",
+                        AccountId::from(target)
+                    )
+                })?;
+            }
+            None => {
+                index.insert(target, filtered.len());
+                filtered.push((target, balance));
+            }
+        }
+    }
+
+    Ok(filtered)
+}
+
+#[derive(Deserialize)]
+struct VestingSchedule {
+    start: BlockNumber,
+    period: BlockNumber,
+    period_count: u32,
+    per_period: Balance,
+}
+
+#[derive(Deserialize)]
+struct VestingAccount {
+    account_id: String,
+    schedules: Vec<VestingSchedule>,
+}
+
+/// Reads the output of the `vesting` subcommand as the `vesting` of the `orml_vesting`
+/// genesis config: `(account_id, start, period, period_count, per_period)`.
+pub fn load_vesting<A: From<RawAccountId>>(
+    path: &Path,
+) -> Result<Vec<(A, BlockNumber, BlockNumber, u32, Balance)>> {
+    let accounts: Vec<VestingAccount> =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+            .with_context(|| format!("Failed to parse vesting {}", path.display()))?;
+
+    let mut vesting = Vec::new();
+    for account in accounts {
+        let account_id = parse_account_id(&account.account_id)?;
+        for schedule in account.schedules {
+            vesting.push((
+                A::from(account_id),
+                schedule.start,
+                schedule.period,
+                schedule.period_count,
+                schedule.per_period,
+            ));
+        }
+    }
+    Ok(vesting)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sweeps the balances below `limit` into `target`.
+    struct SweepDust {
+        limit: Balance,
+        target: RawAccountId,
+    }
+
+    impl AccountFilter for SweepDust {
+        fn classify(&self, account: &RawAccountId, balance: Balance) -> Disposition {
+            if balance < self.limit && *account != self.target {
+                Disposition::Redirect(self.target)
+            } else {
+                Disposition::Include
+            }
+        }
+    }
+
+    #[test]
+    fn exported_balances() {
+        let data = |free, reserved, misc_frozen| AccountData {
+            free,
+            reserved,
+            misc_frozen,
+            fee_frozen: 0,
+        };
+        let account = data(100, 30, 40);
+        assert_eq!(BalanceKind::Free.exported(&account).unwrap(), 100);
+        assert_eq!(BalanceKind::Transferable.exported(&account).unwrap(), 60);
+        assert_eq!(
+            BalanceKind::FreePlusReserved.exported(&account).unwrap(),
+            130
+        );
+        assert_eq!(
+            BalanceKind::Transferable
+                .exported(&data(10, 0, 40))
+                .unwrap(),
+            0
+        );
+        assert!(BalanceKind::FreePlusReserved
+            .exported(&data(Balance::MAX, 1, 0))
+            .is_err());
+
+        // `total` is the same kind.
+        assert!(matches!(
+            BalanceKind::from_str("total", false),
+            Ok(BalanceKind::FreePlusReserved)
+        ));
+    }
+
+    #[test]
+    fn skip_accounts() {
+        let snapshot = vec![([1; 32], 10), ([2; 32], 20), ([3; 32], 30)];
+        let filtered = apply_filter(&snapshot, &SkipAccounts::new([[2; 32]])).unwrap();
+        assert_eq!(filtered, vec![([1; 32], 10), ([3; 32], 30)]);
+    }
+
+    #[test]
+    fn redirect_into_an_existing_account() {
+        let snapshot = vec![
+            ([1; 32], 5_000),
+            ([2; 32], 1),
+            ([3; 32], 2_000),
+            ([4; 32], 0),
+        ];
+        let filter = SweepDust {
+            limit: 1_000,
+            target: [3; 32],
+        };
+        let filtered = apply_filter(&snapshot, &filter).unwrap();
+        assert_eq!(filtered, vec![([1; 32], 5_000), ([3; 32], 2_001)]);
+    }
+
+    #[test]
+    fn redirect_into_a_new_account() {
+        let snapshot = vec![([1; 32], 5_000), ([2; 32], 1), ([3; 32], 2)];
+        let filter = SweepDust {
+            limit: 1_000,
+            target: [9; 32],
+        };
+        let filtered = apply_filter(&snapshot, &filter).unwrap();
+        assert_eq!(filtered, vec![([1; 32], 5_000), ([9; 32], 3)]);
+    }
+
+    #[test]
+    fn redirect_overflow() {
+        let snapshot = vec![([1; 32], Balance::MAX), ([2; 32], 1)];
+        let filter = SweepDust {
+            limit: 1_000,
+            target: [1; 32],
+        };
+        assert!(apply_filter(&snapshot, &filter).is_err());
+    }
+}
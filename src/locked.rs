@@ -0,0 +1,80 @@
+//! Total supply still locked by the vesting schedules.
+
+use crate::vesting::{key_account_id, locked_amount};
+use crate::{Balance, Cli, Target};
+use anyhow::Result;
+
+pub async fn run(cli: &Cli, target: &Target) -> Result<()> {
+    let Target {
+        api,
+        block_hash,
+        block_number,
+        ..
+    } = target;
+
+    let mut accounts = 0usize;
+    let mut clamped = 0usize;
+    let mut total_locked: Balance = 0;
+    let mut iter = api
+        .storage()
+        .vesting()
+        .vesting_schedules_iter(Some(*block_hash))
+        .await?;
+    while let Some((key, schedules)) = iter.next().await? {
+        let account_id = key_account_id(&key.0)?;
+        let locked = schedules
+            .0
+            .iter()
+            .map(|schedule| {
+                locked_amount(
+                    schedule.start,
+                    schedule.period,
+                    schedule.period_count,
+                    schedule.per_period,
+                    *block_number,
+                )
+            })
+            .fold(0 as Balance, Balance::saturating_add);
+
+        // An account can't have more locked than it holds, e.g. after a slash.
+        let free = api
+            .storage()
+            .system()
+            .account(&account_id, Some(*block_hash))
+            .await?
+            .data
+            .free;
+        if locked > free {
+            clamped += 1;
+        }
+
+        accounts += 1;
+        total_locked = total_locked.saturating_add(locked.min(free));
+    }
+
+    let total_issuance = api
+        .storage()
+        .balances()
+        .total_issuance(Some(*block_hash))
+        .await?;
+
+    let formatter = cli.formatter();
+    println!(
+        "Vesting schedules of {} accounts at block #{} ({:?})",
+        accounts, block_number, block_hash
+    );
+    println!("Locked: {}", formatter.display(total_locked));
+    println!("Total issuance: {}", formatter.display(total_issuance));
+    println!(
+        "Circulating: {}",
+        formatter.display(total_issuance.saturating_sub(total_locked))
+    );
+    if clamped > 0 {
+        println!(
+            "Accounts with more locked than their free balance, clamped: {}",
+            clamped
+        );
+    }
+
+    Ok(())
+}
@@ -1,16 +1,86 @@
-use anyhow::{anyhow, Result};
-use clap::Parser;
+/// Prints a status message, to stderr when stdout carries the snapshot.
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if $crate::output::stdout_reserved() {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+mod accounting;
+mod age;
+mod binary;
+mod buckets;
+mod cache;
+mod canonical;
+#[cfg(feature = "parquet")]
+mod columnar;
+mod diff;
+mod error;
+mod expr;
+#[cfg(test)]
+mod fixtures;
+mod format;
+mod genesis;
+mod heartbeat;
+mod locked;
+mod merge;
+mod ndjson;
+mod output;
+mod overrides;
+mod package;
+mod plan;
+mod raw;
+mod reconcile;
+mod rounding;
+mod sample;
+mod schema;
+mod search;
+mod selftest;
+mod shards;
+mod snapshot;
+mod split;
+mod targets;
+mod template;
+mod touched;
+mod trend;
+mod upgrade;
+mod validate;
+mod verify;
+mod vesting;
+mod warnings;
+
+use anyhow::{anyhow, Context, Result};
+use clap::{ArgEnum, Parser, Subcommand};
+use expr::BalanceExpr;
+use futures::TryStreamExt;
+use rand::{seq::SliceRandom, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use snapshot::Entry;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use subspace_regenesis_tool::{
+    dynamic, pager, ratelimit, storage, subspace, AccountFilter, AccountInfo, Balance, BalanceKind,
+    BlockHash, BlockNumber, Disposition, SkipAccounts,
+};
 use subxt::{
+    rpc::RuntimeVersion,
     sp_core::{
         crypto::{AccountId32 as AccountId, Ss58Codec},
-        sr25519, Pair, H256,
+        sr25519, Pair,
     },
     sp_runtime::traits::Header as HeaderT,
-    ClientBuilder, DefaultConfig, SubstrateExtrinsicParams,
+    Client, ClientBuilder, DefaultConfig, SubstrateExtrinsicParams,
 };
 
-const BLAKE_HASH_LEN: usize = 32; // 16 bytes hex
-const STORAGE_PREFIX_LEN: usize = 64; // 32 bytes hex
+/// Sudo account of the network.
+const SUDO_ACCOUNT: &str = "5CXTmJEusve5ixyJufqHThmy4qUrrm6FyLCR7QfE4bbyMTNC";
 
 /// List of accounts which should receive token grants.
 const TOKEN_GRANTS: &[&str] = &[
@@ -33,157 +103,2522 @@ const TOKEN_GRANTS: &[&str] = &[
     "5FZwEgsvZz1vpeH7UsskmNmTpbfXvAcojjgVfShgbRqgC1nx",
 ];
 
-#[subxt::subxt(runtime_metadata_path = "subspace_metadata.scale")]
-mod subspace {}
-
-type Balance = u128;
-type BlockHash = H256;
-type BlockNumber = u32;
+type RuntimeApi = subspace::RuntimeApi<DefaultConfig, SubstrateExtrinsicParams<DefaultConfig>>;
+type Header = <DefaultConfig as subxt::Config>::Header;
 
 /// Subspace regenesis tool
-#[derive(Parser, Debug)]
+#[derive(Parser, Debug, Serialize)]
 #[clap(author, version, about, long_about = None)]
 struct Cli {
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+
     /// The websocket url of Subspace node.
-    #[clap(long, default_value = "ws://127.0.0.1:9944")]
+    #[clap(long, global = true, default_value = "ws://127.0.0.1:9944")]
     pub url: String,
 
+    /// Give up connecting to a node after this many seconds, e.g. on a mistyped URL.
+    #[clap(long, global = true, default_value = "30")]
+    pub connect_timeout_secs: u64,
+
+    /// Read the accounts from several nodes concurrently, overrides `--url`.
+    ///
+    /// The account keyspace is split into one shard per node and the results are
+    /// merged, all the nodes must serve the same block.
+    #[clap(long, use_value_delimiter = true)]
+    pub urls: Vec<String>,
+
     /// Specify the block number.
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub block_number: Option<BlockNumber>,
 
     /// Specify the block hash.
-    #[clap(long)]
+    #[clap(long, global = true)]
     pub block_hash: Option<BlockHash>,
+
+    /// Snapshot each of these blocks in turn over the same connections, one snapshot
+    /// file per block.
+    #[clap(
+        long,
+        use_value_delimiter = true,
+        conflicts_with_all = &[
+            "block_number",
+            "block_hash",
+            "block_hash_file",
+            "stop_at_event",
+            "stdout",
+            "append",
+            "package",
+            "warnings_file",
+            "buckets_file",
+            "progress_file",
+        ],
+    )]
+    pub block_numbers: Vec<BlockNumber>,
+
+    /// Fail unless `--block-number` resolves to this hash, e.g. after a reorg or on
+    /// another chain.
+    #[clap(long, global = true, requires = "block_number")]
+    pub expected_block_hash: Option<BlockHash>,
+
+    /// Read the block hash from a file holding a single hex encoded hash.
+    #[clap(long, global = true, conflicts_with = "block_hash")]
+    pub block_hash_file: Option<PathBuf>,
+
+    /// Snapshot at the first block holding this event, given as `Pallet.Event`,
+    /// searching from `--block-number` (or the block hash, or the best block) on.
+    #[clap(long, global = true)]
+    pub stop_at_event: Option<search::EventName>,
+
+    /// Number of blocks searched for `--stop-at-event`.
+    #[clap(long, global = true, default_value = "1000")]
+    pub search_window: BlockNumber,
+
+    /// Search `--stop-at-event` backward from the starting block instead of forward.
+    #[clap(long, global = true)]
+    pub search_backward: bool,
+
+    /// Skip checking with a single storage read that the nodes still have the state at
+    /// the block, which fails fast on a pruned node.
+    #[clap(long, global = true)]
+    pub no_probe: bool,
+
+    /// Refuse to run unless every node reports this chain name, e.g. `Subspace Gemini
+    /// 1`, as a guard against pointing at the wrong network.
+    #[clap(long, global = true)]
+    pub confirm_chain: Option<String>,
+
+    /// Transform the balance `b` of each new account with an integer expression,
+    /// e.g. `b / 100 + 1000`.
+    ///
+    /// Supports integer literals, `b`, `+`, `-`, `*`, `/`, `%` and parentheses,
+    /// evaluated with checked u128 arithmetic.
+    #[clap(long)]
+    pub balance_expr: Option<BalanceExpr>,
+
+    /// The balance to export for each new account.
+    #[clap(long, arg_enum, default_value = "free")]
+    pub balance_kind: BalanceKind,
+
+    /// Merge the queried accounts into an existing snapshot.
+    #[clap(long)]
+    pub append: Option<PathBuf>,
+
+    /// How to merge an account already present in the `--append` snapshot.
+    #[clap(long, arg_enum, default_value = "replace")]
+    pub append_mode: snapshot::AppendMode,
+
+    /// Apply the `address,balance` corrections of this file to the new accounts, a
+    /// balance prefixed with `+` or `-` adjusts the queried one instead of replacing it.
+    #[clap(long)]
+    pub overrides_file: Option<PathBuf>,
+
+    /// Let `--overrides-file` add accounts absent from the snapshot.
+    #[clap(long, requires = "overrides_file")]
+    pub allow_new_overrides: bool,
+
+    /// Write the increase of each balance since this earlier snapshot instead, the
+    /// decreases being clamped at zero.
+    #[clap(long, conflicts_with = "append")]
+    pub baseline: Option<PathBuf>,
+
+    /// Only warn instead of aborting when the sum of all the accounts does not match
+    /// the total issuance, e.g. on forks or states after manual surgery.
+    #[clap(long)]
+    pub no_issuance_check: bool,
+
+    /// Storage map holding the accounts, as `Pallet.Item`.
+    ///
+    /// It must be keyed by `blake2_128_concat(account_id)` and hold `AccountInfo`
+    /// values like `System.Account`.
+    #[clap(long, default_value = "System.Account")]
+    pub account_storage: storage::AccountStorage,
+
+    /// Export the balances of this asset from the `Assets::Account` double map instead
+    /// of the native accounts, checked against the supply of the asset. Overrides
+    /// `--account-storage`.
+    #[clap(long, global = true)]
+    pub asset_id: Option<u32>,
+
+    /// Decode the accounts against the metadata of the node at runtime instead of the
+    /// types generated from `subspace_metadata.scale`, to read runtimes with a different
+    /// `AccountInfo` layout without recompiling. Slower than the default.
+    #[clap(long, global = true, conflicts_with = "asset_id")]
+    pub dynamic: bool,
+
+    /// Existential deposit of the new chain, `Balances::ExistentialDeposit` of the
+    /// queried runtime by default.
+    ///
+    /// The new accounts written below it are reported.
+    #[clap(long)]
+    pub existential_deposit: Option<Balance>,
+
+    /// Enable additional validations of the new accounts, e.g. reject those written
+    /// with a zero balance.
+    #[clap(long)]
+    pub strict: bool,
+
+    /// What to do with the new accounts holding nothing on chain, e.g. accounts only
+    /// kept alive by their references.
+    #[clap(long, arg_enum, default_value = "keep")]
+    pub zero_balance: ZeroBalance,
+
+    /// Accept new accounts with a zero balance under `--strict`.
+    #[clap(long, requires = "strict")]
+    pub allow_zero: bool,
+
+    /// Round the balance of each new account to a multiple of this unit, after
+    /// `--balance-expr`.
+    #[clap(long)]
+    pub round_to: Option<Balance>,
+
+    /// Direction of `--round-to`.
+    #[clap(long, arg_enum, default_value = "down")]
+    pub rounding: rounding::Rounding,
+
+    /// What becomes of the net amount removed by `--round-to`.
+    #[clap(long, arg_enum, default_value = "burn")]
+    pub remainder: rounding::Remainder,
+
+    /// Account credited with the `--round-to` remainder under `--remainder collect`.
+    #[clap(long)]
+    pub dust_account: Option<AccountId>,
+
+    /// Width of the balance type of the new chain, every balance of the snapshot and
+    /// their total must fit into it.
+    #[clap(long, default_value = "128", possible_values = &["64", "128"])]
+    pub target_balance_bits: u32,
+
+    /// List the accounts not yet migrated to the newer balance model, on runtimes whose
+    /// `AccountData` has the `flags` field.
+    #[clap(long)]
+    pub report_flags: bool,
+
+    /// Skip the accounts whose value fails to decode instead of aborting, the snapshot
+    /// is then marked as partial in its metadata.
+    #[clap(long)]
+    pub skip_undecodable: bool,
+
+    /// Only write the new accounts whose balance is at least this amount.
+    ///
+    /// The range is checked against the `--balance-kind` balance before `--balance-expr`
+    /// is applied, after the grant and endowed accounts are skipped. The filtered out
+    /// accounts still count towards the issuance check.
+    #[clap(long)]
+    pub balance_min: Option<Balance>,
+
+    /// Only write the new accounts whose balance is at most this amount.
+    #[clap(long)]
+    pub balance_max: Option<Balance>,
+
+    /// Only write the new accounts which already existed at this earlier block, to
+    /// leave out recently created accounts. Costs one more storage read per account.
+    #[clap(long)]
+    pub min_age_block: Option<BlockHash>,
+
+    /// Write the number of accounts and their total balance per balance bucket to this
+    /// file, as CSV if it ends with `.csv` and JSON otherwise.
+    #[clap(long, requires = "bucket_edges")]
+    pub buckets_file: Option<PathBuf>,
+
+    /// Comma separated, strictly increasing edges of the `--buckets-file` buckets. Each
+    /// bucket includes its lower edge and excludes its upper one, the first starts at 0
+    /// and the last holds the balances from the last edge on.
+    #[clap(long, use_value_delimiter = true, requires = "buckets_file")]
+    pub bucket_edges: Vec<Balance>,
+
+    /// Check the distribution of the new accounts against the targets of this JSON
+    /// file, e.g. the largest share of a single account, and report each as passed or
+    /// failed. Any failed target fails the run under `--strict`.
+    #[clap(long)]
+    pub target_file: Option<PathBuf>,
+
+    /// Only print the summary of the run and check the targets, without writing the
+    /// snapshot file.
+    #[clap(long, conflicts_with = "stdout")]
+    pub summary_only: bool,
+
+    /// Directory of the snapshot file, the current directory by default.
+    #[clap(long)]
+    pub out_dir: Option<PathBuf>,
+
+    /// File name of the snapshot instead of `balances_{number}.{format}`, with the
+    /// `{number}`, `{hash}`, `{short_hash}`, `{date}` and `{format}` placeholders.
+    #[clap(long)]
+    pub output_template: Option<template::OutputTemplate>,
+
+    /// Indentation of the JSON snapshot files in spaces, 0 writing them on a single
+    /// line.
+    #[clap(long, global = true, default_value = "2")]
+    pub json_indent: usize,
+
+    /// Format of the snapshot file.
+    #[clap(long, arg_enum, default_value = "json")]
+    pub format: OutputFormat,
+
+    /// Randomize the order of the accounts in the snapshot file, deterministically for
+    /// a given `--seed`.
+    #[clap(long)]
+    pub shuffle: bool,
+
+    /// Seed of `--shuffle` and `--sample-weighted`, a random one is picked and
+    /// recorded if omitted.
+    #[clap(long)]
+    pub seed: Option<u64>,
+
+    /// Write this many new accounts picked at random with a probability proportional
+    /// to their balance to `sample_{block_number}.json`, for QA review, deterministically
+    /// for a given `--seed`.
+    #[clap(long)]
+    pub sample_weighted: Option<usize>,
+
+    /// Re-read this many accounts picked at random once the snapshot is taken and fail
+    /// if any balance differs, one storage read each.
+    #[clap(long)]
+    pub post_verify: Option<usize>,
+
+    /// Seed of `--post-verify`, a random one is picked and recorded if omitted.
+    #[clap(long, requires = "post_verify")]
+    pub post_verify_seed: Option<u64>,
+
+    /// Split the snapshot into parts of at most this many accounts, sorted by account id
+    /// and listed by a manifest. Only supported by `--format json`.
+    #[clap(long, conflicts_with_all = &["split_bytes", "stdout", "append", "shuffle"])]
+    pub split_count: Option<usize>,
+
+    /// Split the snapshot into parts of at most this size, e.g. `512M`, sorted by account
+    /// id and listed by a manifest. Only supported by `--format json`.
+    #[clap(long, conflicts_with_all = &["stdout", "append", "shuffle"])]
+    pub split_bytes: Option<split::ByteSize>,
+
+    /// Write the snapshot to stdout instead of a file, the status messages go to stderr.
+    #[clap(long, conflicts_with_all = &["append", "fsync", "skip_existing"])]
+    pub stdout: bool,
+
+    /// Write `balances_{block_number}.idx` next to the snapshot, mapping every account
+    /// to the byte offset of its record, only supported by `--format bin`.
+    #[clap(long, conflicts_with = "stdout")]
+    pub write_index: bool,
+
+    /// Append the CRC-32 of each record to its line, only supported by `--format ndjson`.
+    #[clap(long)]
+    pub per_record_checksum: bool,
+
+    /// Output of the `vesting` subcommand to write as the vesting section of
+    /// `--format subspace-genesis`.
+    #[clap(long)]
+    pub genesis_vesting: Option<PathBuf>,
+
+    /// Sync the snapshot file to disk before reporting success.
+    #[clap(long)]
+    pub fsync: bool,
+
+    /// Number of token decimals used to render the printed balances.
+    #[clap(long, global = true)]
+    pub decimals: Option<u8>,
+
+    /// Token symbol used to render the printed balances.
+    #[clap(long, global = true)]
+    pub symbol: Option<String>,
+
+    /// SS58 address prefix of the network.
+    #[clap(long, default_value = "42")]
+    pub ss58_prefix: u16,
+
+    /// Check that all the compiled-in special addresses use `--ss58-prefix`.
+    #[clap(long)]
+    pub verify_ss58_prefix: bool,
+
+    /// Check that every account id written re-parses to itself from its SS58 address,
+    /// both as written and with `--ss58-prefix`.
+    #[clap(long)]
+    pub verify_roundtrip: bool,
+
+    /// Save the node metadata and runtime version to this file on the first run, and
+    /// check the runtime version of the target block against it on subsequent runs.
+    #[clap(long)]
+    pub metadata_cache: Option<PathBuf>,
+
+    /// Decode the accounts against the metadata of `--metadata-cache` instead of the
+    /// types generated from `subspace_metadata.scale`, the way `--dynamic` does with the
+    /// metadata of the node.
+    #[clap(long, requires = "metadata_cache", conflicts_with = "asset_id")]
+    pub offline_decode: bool,
+
+    /// Proceed despite a failed safety check: overwrite an existing snapshot file,
+    /// append to a snapshot taken at a different block or without metadata, refresh a
+    /// stale `--metadata-cache`, or overwrite the files extracted by `unpack-verify`.
+    #[clap(long)]
+    pub force: bool,
+
+    /// Quietly skip the run if the snapshot file already exists.
+    #[clap(long, conflicts_with = "force")]
+    pub skip_existing: bool,
+
+    /// Record the warnings encountered as JSON lines to this file, including the
+    /// per-account ones.
+    #[clap(long)]
+    pub warnings_file: Option<PathBuf>,
+
+    /// Write the header of the block to `header_{block_number}.json` next to the
+    /// snapshot, both SCALE encoded and decoded.
+    #[clap(long)]
+    pub export_header: bool,
+
+    /// Bundle the snapshot file along with its metadata, the `--export-header` header
+    /// and the `--write-index` index into this gzipped tarball, listing their SHA-256 in
+    /// its `SHA256SUMS`. Check and extract it with `unpack-verify`.
+    #[clap(long, conflicts_with = "stdout")]
+    pub package: Option<PathBuf>,
+
+    /// Print all the warnings, including the per-account ones.
+    #[clap(long)]
+    pub print_warnings: bool,
+
+    /// Replace every account id in the snapshot with a salted hash, keeping the
+    /// balances and the order, to publish the distribution without the addresses.
+    #[clap(
+        long,
+        requires = "redact_salt",
+        conflicts_with_all = &["warnings_file", "print_warnings"]
+    )]
+    pub redact: bool,
+
+    /// Secret salt of `--redact`, the same salt maps an account to the same hash
+    /// across snapshots. Never recorded in the metadata.
+    #[clap(long, requires = "redact")]
+    #[serde(skip_serializing)]
+    pub redact_salt: Option<String>,
+
+    /// Print a JSON line with the accounts fetched so far, their running total
+    /// balance and the elapsed time every this many seconds during the iteration.
+    #[clap(long)]
+    pub heartbeat_secs: Option<u64>,
+
+    /// Overwrite this file every few seconds with a JSON object holding the accounts
+    /// processed, the elapsed time, the last storage key read and the phase of the run.
+    #[clap(long)]
+    pub progress_file: Option<PathBuf>,
+
+    /// Fetch at most this many storage pages per second across all the `--urls`, to
+    /// spare shared nodes.
+    #[clap(long)]
+    pub max_pages_per_sec: Option<f64>,
+}
+
+impl Cli {
+    fn formatter(&self) -> format::BalanceFormatter {
+        format::BalanceFormatter {
+            decimals: self.decimals,
+            symbol: self.symbol.clone(),
+        }
+    }
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum ZeroBalance {
+    /// Write them to the snapshot like any other account.
+    Keep,
+    /// Leave them out of the snapshot.
+    Skip,
+    /// Write them to the snapshot, emitting a `zero-balance` warning for each.
+    Warn,
+}
+
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+enum OutputFormat {
+    /// `balances_{block_number}.json`, an array of `[account_id, balance]`.
+    Json,
+    /// `balances_{block_number}.parquet`, requires the `parquet` feature.
+    Parquet,
+    /// `balances_{block_number}.bin`, a header followed by fixed size records.
+    Bin,
+    /// `balances_{block_number}.ndjson`, one `{account, balance}` object per line.
+    Ndjson,
+    /// `balances_{block_number}.canonical.json`, an object sorted by address with one
+    /// `"<ss58>": <balance>` member per line, for diffing snapshots under version control.
+    CanonicalJson,
+    /// `balances_{block_number}.debug.json`, an array of `{ss58, hex, balance}`
+    /// objects for manual analysis, not meant for the genesis.
+    DebugJson,
+    /// `balances_{block_number}.raw-top.json`, an object of hex encoded account storage
+    /// key to the full SCALE encoded `AccountInfo`, as in `genesis.raw.top`.
+    RawTop,
+    /// `balances_{block_number}.kv`, one `0x<key>=0x<value>` line per account with the
+    /// same storage writes as `raw-top`, for state surgery tools.
+    Kv,
+    /// `balances_{block_number}.subspace-genesis.json`, the balances and optionally
+    /// the vesting sections of the Subspace runtime `GenesisConfig`.
+    SubspaceGenesis,
+}
+
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Parquet => "parquet",
+            Self::Bin => "bin",
+            Self::Ndjson => "ndjson",
+            Self::CanonicalJson => "canonical.json",
+            Self::DebugJson => "debug.json",
+            Self::RawTop => "raw-top.json",
+            Self::Kv => "kv",
+            Self::SubspaceGenesis => "subspace-genesis.json",
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Serialize)]
+enum Command {
+    Diff(diff::DiffCmd),
+    Schema(schema::SchemaCmd),
+    /// Preview what a snapshot run would do, without iterating the account values.
+    Plan,
+    Trend(trend::TrendCmd),
+    /// Print `Balances::TotalIssuance` at the block, without iterating the accounts.
+    Issuance,
+    /// Run a snapshot against a local dev node into a temporary directory and check it.
+    Selftest,
+    Vesting(vesting::VestingCmd),
+    /// Print the supply still locked by the vesting schedules at the block, along with
+    /// the total issuance and the circulating supply.
+    LockedSupply,
+    Touched(touched::TouchedCmd),
+    Reconcile(reconcile::ReconcileCmd),
+    VerifyRecords(ndjson::VerifyRecordsCmd),
+    Merge(merge::MergeCmd),
+    Validate(validate::ValidateCmd),
+    ReconcileShards(shards::ReconcileShardsCmd),
+    UnpackVerify(package::UnpackVerifyCmd),
+    /// List the compiled-in accounts skipped by a snapshot, without connecting to a node.
+    Accounts,
+}
+
+/// Ensures all the `addresses` are encoded with the SS58 `prefix`.
+fn verify_ss58_prefix<'a>(addresses: impl Iterator<Item = &'a str>, prefix: u16) -> Result<()> {
+    let mismatches = addresses
+        .filter_map(
+            |address| match AccountId::from_ss58check_with_version(address) {
+                Ok((_, format)) if u16::from(format) == prefix => None,
+                Ok((_, format)) => Some(format!("{} (prefix {})", address, u16::from(format))),
+                Err(err) => Some(format!("{} ({:?})", address, err)),
+            },
+        )
+        .collect::<Vec<_>>();
+
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Addresses not using the SS58 prefix {}: {}",
+            prefix,
+            mismatches.join(", ")
+        ))
+    }
+}
+
+/// Ensures every account id decodes back from its SS58 address, with the default
+/// prefix the snapshot files use and with `prefix`.
+fn verify_roundtrip<'a>(
+    account_ids: impl Iterator<Item = &'a AccountId>,
+    prefix: u16,
+) -> Result<()> {
+    let failures = account_ids
+        .filter_map(|account_id| {
+            let default = account_id.to_ss58check();
+            let with_prefix = account_id.to_ss58check_with_version(prefix.into());
+            let default_ok = AccountId::from_ss58check(&default).ok().as_ref() == Some(account_id);
+            let with_prefix_ok = match AccountId::from_ss58check_with_version(&with_prefix) {
+                Ok((decoded, format)) => &decoded == account_id && u16::from(format) == prefix,
+                Err(_) => false,
+            };
+            (!default_ok || !with_prefix_ok).then(|| {
+                format!(
+                    "0x{} ({}, {} with prefix {})",
+                    hex::encode(account_id),
+                    default,
+                    with_prefix,
+                    prefix
+                )
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for failure in &failures {
+        status!("SS58 round-trip failed: {}", failure);
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(error::Error::Assertion(format!(
+            "{} account ids do not round-trip through SS58",
+            failures.len()
+        ))
+        .into())
+    }
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn main() {
+    if let Err(err) = run().await {
+        eprintln!("Error: {:?}", err);
+        std::process::exit(error::exit_code(&err));
+    }
+}
+
+/// Connection to the node(s) at the target block.
+struct Target {
+    urls: Vec<String>,
+    clients: Vec<Client<DefaultConfig>>,
+    api: RuntimeApi,
+    block_hash: BlockHash,
+    block_number: BlockNumber,
+    header: Header,
+    runtime_version: RuntimeVersion,
+    /// `spec_version` of the best block when the metadata of `api` was fetched.
+    metadata_spec_version: u32,
+}
 
-    let api = ClientBuilder::new()
-        .set_url(cli.url)
-        .build()
+/// Returns the hash of the canonical block `block_number`.
+async fn block_hash_at(
+    client: &Client<DefaultConfig>,
+    block_number: BlockNumber,
+) -> Result<BlockHash> {
+    client
+        .rpc()
+        .block_hash(Some(block_number.into()))
         .await?
-        .to_runtime_api::<subspace::RuntimeApi<DefaultConfig, SubstrateExtrinsicParams<DefaultConfig>>>();
+        .ok_or_else(|| {
+            error::Error::Connection(format!(
+                "Block hash for block number {} not found",
+                block_number
+            ))
+            .into()
+        })
+}
+
+/// Reads the hex encoded block hash of `--block-hash-file`, `0x` prefixed or not.
+fn read_block_hash_file(path: &Path) -> Result<BlockHash> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let content = content.trim();
+    let bytes = hex::decode(content.strip_prefix("0x").unwrap_or(content))
+        .map_err(|err| anyhow!("Malformed block hash in {}: {}", path.display(), err))?;
+    if bytes.len() != BlockHash::len_bytes() {
+        return Err(anyhow!(
+            "Malformed block hash in {}: expected {} bytes, got {}",
+            path.display(),
+            BlockHash::len_bytes(),
+            bytes.len()
+        ));
+    }
+    Ok(BlockHash::from_slice(&bytes))
+}
+
+/// Connects to the node at `url`, giving up after `--connect-timeout-secs`.
+async fn connect_client(cli: &Cli, url: &str) -> Result<Client<DefaultConfig>> {
+    let client = tokio::time::timeout(
+        Duration::from_secs(cli.connect_timeout_secs),
+        ClientBuilder::new().set_url(url).build::<DefaultConfig>(),
+    )
+    .await
+    .map_err(|_| {
+        error::Error::Connection(format!(
+            "could not connect to {} within {}s",
+            url, cli.connect_timeout_secs
+        ))
+    })??;
+    Ok(client)
+}
+
+/// Connections to the nodes, opened once for all the target blocks.
+struct Nodes {
+    urls: Vec<String>,
+    clients: Vec<Client<DefaultConfig>>,
+    api: RuntimeApi,
+    /// `spec_version` of the best block when the metadata of `api` was fetched.
+    metadata_spec_version: u32,
+}
+
+/// Connects to the nodes and resolves the target block.
+async fn connect(cli: &Cli) -> Result<Target> {
+    resolve_target(cli, &connect_nodes(cli).await?).await
+}
+
+/// Connects to the nodes, checking `--confirm-chain`.
+async fn connect_nodes(cli: &Cli) -> Result<Nodes> {
+    let urls = if cli.urls.is_empty() {
+        vec![cli.url.clone()]
+    } else {
+        cli.urls.clone()
+    };
+
+    let mut clients = Vec::with_capacity(urls.len());
+    for url in &urls {
+        let client = connect_client(cli, url).await?;
+        if let Some(expected) = &cli.confirm_chain {
+            let chain = client.rpc().system_chain().await?;
+            if &chain != expected {
+                return Err(error::Error::Connection(format!(
+                    "Node {} serves chain {:?}, not the {:?} of --confirm-chain",
+                    url, chain, expected
+                ))
+                .into());
+            }
+        }
+        clients.push(client);
+    }
+
+    let api = clients[0].clone().to_runtime_api::<RuntimeApi>();
+    let metadata_spec_version = api.client.rpc().runtime_version(None).await?.spec_version;
+
+    Ok(Nodes {
+        urls,
+        clients,
+        api,
+        metadata_spec_version,
+    })
+}
+
+/// Resolves the target block of `cli` on the connected `nodes`.
+async fn resolve_target(cli: &Cli, nodes: &Nodes) -> Result<Target> {
+    let Nodes {
+        urls,
+        clients,
+        api,
+        metadata_spec_version,
+    } = nodes;
 
     let maybe_block_hash = if let Some(block_number) = cli.block_number {
-        Some(
-            api.client
-                .rpc()
-                .block_hash(Some(block_number.into()))
-                .await?
-                .unwrap_or_else(|| {
-                    panic!("Block hash for block number {} not found", block_number)
-                }),
-        )
+        let block_hash = block_hash_at(&api.client, block_number).await?;
+        match cli.expected_block_hash {
+            Some(expected) if expected != block_hash => {
+                return Err(error::Error::Connection(format!(
+                    "Block #{} is {:?} on {}, not the {:?} of --expected-block-hash",
+                    block_number, block_hash, urls[0], expected
+                ))
+                .into())
+            }
+            _ => {}
+        }
+        Some(block_hash)
     } else {
         cli.block_hash
     };
 
     let block_hash = match maybe_block_hash {
         Some(hash) => hash,
-        None => api
-            .client
-            .rpc()
-            .block_hash(None)
-            .await?
-            .expect("Best block hash not found"),
+        None => api.client.rpc().block_hash(None).await?.ok_or_else(|| {
+            error::Error::Connection(format!("Best block hash not found on {}", urls[0]))
+        })?,
     };
 
-    let endowed = vec![
-        AccountId::from_ss58check("5CXTmJEusve5ixyJufqHThmy4qUrrm6FyLCR7QfE4bbyMTNC")
-            .expect("Sudo account must be valid; qed"),
-        sr25519::Pair::from_string("//Alice", None)
-            .expect("Could not generate a key pair")
-            .public()
-            .into(),
-        sr25519::Pair::from_string("//Bob", None)
+    let block_hash = match &cli.stop_at_event {
+        Some(event) => {
+            let start = match cli.block_number {
+                Some(block_number) => block_number,
+                None => *api
+                    .client
+                    .rpc()
+                    .header(Some(block_hash))
+                    .await?
+                    .ok_or_else(|| {
+                        error::Error::Connection(format!(
+                            "Header for block hash {} not found on {}",
+                            block_hash, urls[0]
+                        ))
+                    })?
+                    .number(),
+            };
+            let (block_number, block_hash) = search::find_event(
+                &api.client,
+                event,
+                start,
+                cli.search_window,
+                cli.search_backward,
+            )
+            .await?;
+            status!(
+                "Found event {} at block #{} ({:?})",
+                event,
+                block_number,
+                block_hash
+            );
+            block_hash
+        }
+        None => block_hash,
+    };
+
+    for (url, client) in urls.iter().zip(clients).skip(1) {
+        // With `--stop-at-event` the block number only starts the search.
+        if let Some(block_number) = cli.block_number.filter(|_| cli.stop_at_event.is_none()) {
+            let hash = client.rpc().block_hash(Some(block_number.into())).await?;
+            if hash != Some(block_hash) {
+                return Err(error::Error::Connection(format!(
+                    "Node {} disagrees on block #{}: {:?} != {:?}",
+                    url, block_number, hash, block_hash
+                ))
+                .into());
+            }
+        } else if client.rpc().header(Some(block_hash)).await?.is_none() {
+            return Err(error::Error::Connection(format!(
+                "Node {} does not know block {:?}",
+                url, block_hash
+            ))
+            .into());
+        }
+    }
+
+    let block_header = api
+        .client
+        .rpc()
+        .header(Some(block_hash))
+        .await?
+        .ok_or_else(|| {
+            error::Error::Connection(format!(
+                "Header for block hash {} not found on {}",
+                block_hash, urls[0]
+            ))
+        })?;
+
+    if !cli.no_probe {
+        for (url, client) in urls.iter().zip(clients) {
+            storage::probe_state(client, url, *block_header.number(), block_hash).await?;
+        }
+    }
+
+    let runtime_version = api.client.rpc().runtime_version(Some(block_hash)).await?;
+
+    Ok(Target {
+        urls: urls.clone(),
+        clients: clients.clone(),
+        api: api.client.clone().to_runtime_api::<RuntimeApi>(),
+        block_hash,
+        block_number: *block_header.number(),
+        header: block_header,
+        runtime_version,
+        metadata_spec_version: *metadata_spec_version,
+    })
+}
+
+/// Fails if two of the `(encoded, account_id)` entries of the `list` accounts are the
+/// same account, e.g. an address repeated with another SS58 prefix, which a comparison
+/// of the strings misses.
+fn check_distinct(list: &str, entries: &[(&str, AccountId)]) -> Result<()> {
+    let mut seen = std::collections::BTreeMap::new();
+    for (encoded, account_id) in entries {
+        if let Some(first) = seen.insert(account_id, encoded) {
+            return Err(error::Error::Assertion(format!(
+                "{} {} and {} are the same account 0x{}",
+                list,
+                first,
+                encoded,
+                hex::encode(account_id)
+            ))
+            .into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns the accounts present in several of the named `lists`, in account order,
+/// along with the names of the lists holding them.
+fn list_overlaps<'a>(
+    lists: &[(&'static str, &'a [AccountId])],
+) -> Vec<(&'a AccountId, Vec<&'static str>)> {
+    let mut holders = std::collections::BTreeMap::<_, Vec<_>>::new();
+    for &(name, accounts) in lists {
+        for account_id in accounts {
+            let names = holders.entry(account_id).or_default();
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    holders
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .collect()
+}
+
+/// Returns the endowed and the token grant accounts, which are not carried over.
+fn special_accounts() -> Result<(Vec<AccountId>, Vec<AccountId>)> {
+    let dev_account = |seed: &str| -> AccountId {
+        sr25519::Pair::from_string(seed, None)
             .expect("Could not generate a key pair")
             .public()
-            .into(),
+            .into()
+    };
+    let endowed = [
+        (
+            SUDO_ACCOUNT,
+            AccountId::from_ss58check(SUDO_ACCOUNT).expect("Sudo account must be valid; qed"),
+        ),
+        ("//Alice", dev_account("//Alice")),
+        ("//Bob", dev_account("//Bob")),
     ];
+    check_distinct("endowed accounts", &endowed)?;
+    let endowed = endowed
+        .into_iter()
+        .map(|(_, account_id)| account_id)
+        .collect();
 
     let token_grants = TOKEN_GRANTS
         .iter()
-        .filter_map(|address| AccountId::from_ss58check(address).ok())
+        .filter_map(|address| {
+            AccountId::from_ss58check(address)
+                .ok()
+                .map(|account_id| (*address, account_id))
+        })
         .collect::<Vec<_>>();
 
-    assert_eq!(token_grants.len(), TOKEN_GRANTS.len());
+    if token_grants.len() != TOKEN_GRANTS.len() {
+        return Err(error::Error::Assertion(format!(
+            "only {} of {} token grant addresses are valid",
+            token_grants.len(),
+            TOKEN_GRANTS.len()
+        ))
+        .into());
+    }
+    check_distinct("token grants", &token_grants)?;
+    let token_grants = token_grants
+        .into_iter()
+        .map(|(_, account_id)| account_id)
+        .collect();
 
-    let mut new_accounts = Vec::new();
+    Ok((endowed, token_grants))
+}
+
+/// Returns the default filtering policy, skipping the special accounts.
+fn account_filter() -> Result<SkipAccounts> {
+    let (endowed, token_grants) = special_accounts()?;
+    Ok(Selection::special(&endowed, &token_grants).filter)
+}
+
+/// The filtering policy of the new accounts, along with the special accounts the
+/// skipped balances are accounted to.
+struct Selection<F> {
+    filter: F,
+    endowed: HashSet<AccountId>,
+    token_grants: HashSet<AccountId>,
+}
+
+impl Selection<SkipAccounts> {
+    /// Returns the default policy, skipping the `endowed` and `token_grants` accounts.
+    fn special(endowed: &[AccountId], token_grants: &[AccountId]) -> Self {
+        Self {
+            filter: SkipAccounts::new(
+                endowed
+                    .iter()
+                    .chain(token_grants)
+                    .cloned()
+                    .map(<[u8; 32]>::from),
+            ),
+            endowed: endowed.iter().cloned().collect(),
+            token_grants: token_grants.iter().cloned().collect(),
+        }
+    }
+}
+
+/// Prints the compiled-in special accounts as SS58 with `--ss58-prefix` and as hex.
+fn list_special_accounts(cli: &Cli) -> Result<()> {
+    let (endowed, token_grants) = special_accounts()?;
+    let (sudo, dev) = endowed.split_at(1);
+
+    let sections = [
+        ("Sudo", sudo),
+        ("Dev (//Alice, //Bob)", dev),
+        ("Token grants", &token_grants[..]),
+    ];
+    for (name, accounts) in sections {
+        println!("{} ({}):", name, accounts.len());
+        for account_id in accounts {
+            println!(
+                "  {} 0x{}",
+                account_id.to_ss58check_with_version(cli.ss58_prefix.into()),
+                hex::encode(account_id)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the storage prefix of the accounts to read, those of `--asset-id` in
+/// `Assets::Account` or else `--account-storage`.
+fn account_prefix(cli: &Cli, client: &Client<DefaultConfig>) -> Result<Vec<u8>> {
+    match cli.asset_id {
+        Some(asset_id) => storage::resolve_asset_account(client, asset_id),
+        None => cli.account_storage.resolve(client),
+    }
+}
+
+/// Returns what the accounts must add up to, the supply of `--asset-id` or else
+/// `Balances::TotalIssuance`.
+async fn expected_issuance(
+    cli: &Cli,
+    client: &Client<DefaultConfig>,
+    block_hash: BlockHash,
+) -> Result<Balance> {
+    match cli.asset_id {
+        Some(asset_id) => Ok(storage::asset_details(client, block_hash, asset_id)
+            .await?
+            .supply),
+        None => Ok(client
+            .clone()
+            .to_runtime_api::<RuntimeApi>()
+            .storage()
+            .balances()
+            .total_issuance(Some(block_hash))
+            .await?),
+    }
+}
+
+/// Returns the `--format debug-json` entries.
+fn debug_entries(cli: &Cli, entries: &[Entry]) -> Vec<snapshot::DebugEntry> {
+    entries
+        .iter()
+        .map(|entry| snapshot::DebugEntry::new(entry, cli.ss58_prefix))
+        .collect()
+}
+
+/// Returns the path of the snapshot file at the block, named after `--output-template`
+/// if given.
+fn output_path(cli: &Cli, block_number: BlockNumber, block_hash: BlockHash) -> Result<PathBuf> {
+    let mut path = output_dir(cli)?;
+    path.push(match &cli.output_template {
+        Some(template) => template.render(block_number, block_hash, cli.format.extension())?,
+        None => format!("balances_{}.{}", block_number, cli.format.extension()),
+    });
+    Ok(path)
+}
+
+/// Returns the directory of the output files.
+fn output_dir(cli: &Cli) -> Result<PathBuf> {
+    Ok(match &cli.out_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::current_dir()?,
+    })
+}
+
+/// Returns the path of the `--export-header` file.
+fn header_path(cli: &Cli, block_number: BlockNumber) -> Result<PathBuf> {
+    Ok(output_dir(cli)?.join(format!("header_{}.json", block_number)))
+}
+
+/// Returns the path of the `--sample-weighted` file.
+fn sample_path(cli: &Cli, block_number: BlockNumber) -> Result<PathBuf> {
+    Ok(output_dir(cli)?.join(format!("sample_{}.json", block_number)))
+}
+
+async fn run() -> Result<()> {
+    let mut cli = Cli::parse();
+
+    let command = cli.command.take();
+
+    if cli.seed.is_some() && !cli.shuffle && cli.sample_weighted.is_none() {
+        return Err(anyhow!("--seed requires --shuffle or --sample-weighted"));
+    }
+    if (cli.shuffle || cli.sample_weighted.is_some()) && cli.seed.is_none() {
+        cli.seed = Some(rand::random());
+    }
+    if cli.post_verify.is_some() && cli.post_verify_seed.is_none() {
+        cli.post_verify_seed = Some(rand::random());
+    }
+
+    if let Some(path) = &cli.block_hash_file {
+        cli.block_hash = Some(read_block_hash_file(path)?);
+    }
+
+    match command {
+        Some(Command::Diff(cmd)) => return cmd.run(&cli).await,
+        Some(Command::Schema(cmd)) => return cmd.run(),
+        Some(Command::Reconcile(cmd)) => return cmd.run(),
+        Some(Command::Accounts) => return list_special_accounts(&cli),
+        Some(Command::VerifyRecords(cmd)) => return cmd.run(),
+        Some(Command::Validate(cmd)) => return cmd.run(),
+        Some(Command::ReconcileShards(cmd)) => return cmd.run(&cli),
+        Some(Command::UnpackVerify(cmd)) => return cmd.run(&cli),
+        Some(Command::Trend(cmd)) => {
+            let api = connect_client(&cli, &cli.url)
+                .await?
+                .to_runtime_api::<RuntimeApi>();
+            return cmd.run(&api).await;
+        }
+        Some(Command::Selftest) => return selftest::run(&cli.url).await,
+        Some(Command::Merge(cmd)) => return cmd.run(&cli).await,
+        Some(
+            Command::Plan
+            | Command::Issuance
+            | Command::Vesting(_)
+            | Command::LockedSupply
+            | Command::Touched(_),
+        )
+        | None => {}
+    }
+
+    if !cli.block_numbers.is_empty() {
+        if command.is_some() {
+            return Err(anyhow!("--block-numbers only applies to snapshot runs"));
+        }
+        // A single connection to each node serves all the blocks.
+        let nodes = connect_nodes(&cli).await?;
+        for block_number in std::mem::take(&mut cli.block_numbers) {
+            cli.block_number = Some(block_number);
+            let target = resolve_target(&cli, &nodes).await?;
+            snapshot(&cli, &target).await?;
+        }
+        return Ok(());
+    }
+
+    let target = connect(&cli).await?;
+
+    match command {
+        Some(Command::Plan) => return plan::run(&cli, &target).await,
+        Some(Command::Issuance) => return issuance(&cli, &target).await,
+        Some(Command::Vesting(cmd)) => return cmd.run(&cli, &target).await,
+        Some(Command::LockedSupply) => return locked::run(&cli, &target).await,
+        Some(Command::Touched(cmd)) => return cmd.run(&cli, &target).await,
+        _ => {}
+    }
 
-    let mut iter = api
+    snapshot(&cli, &target).await
+}
+
+async fn issuance(cli: &Cli, target: &Target) -> Result<()> {
+    let total_issuance = target
+        .api
         .storage()
-        .system()
-        .account_iter(Some(block_hash))
+        .balances()
+        .total_issuance(Some(target.block_hash))
         .await?;
 
+    let formatter = cli.formatter();
+
+    println!(
+        "Total issuance at block #{} ({:?}): {}",
+        target.block_number,
+        target.block_hash,
+        formatter.display(total_issuance)
+    );
+
+    Ok(())
+}
+
+/// Fails with a clear error if the iteration at the block found no account at all,
+/// e.g. on a pruned node, which would otherwise surface as an issuance mismatch.
+fn ensure_accounts(
+    entries: &[pager::AccountEntry],
+    block_number: BlockNumber,
+    block_hash: BlockHash,
+) -> Result<()> {
+    if entries.is_empty() {
+        return Err(error::Error::NoAccounts {
+            block_number,
+            block_hash,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// The new accounts of a snapshot, along with where the rest of the iterated balances
+/// went.
+struct Classified {
+    new_accounts: Vec<Entry>,
+    /// Free plus reserved balance of all the iterated accounts.
+    total_issuance: Balance,
+    accounting: accounting::Accounting,
+    out_of_range: usize,
+    below_existential_deposit: usize,
+    zero_balance: usize,
+    rounded_off: Balance,
+    rounded_up: Balance,
+    not_migrated: Vec<AccountId>,
+    raw_values: raw::RawValues,
+}
+
+/// Sorts the iterated `entries` into the new accounts and the accounting buckets,
+/// applying the balance options of `cli` and the filter of `selection`, the accounts
+/// of `too_new` being left out.
+///
+/// The balance of a redirected account is added to its target once computed, like
+/// [`apply_filter`](subspace_regenesis_tool::apply_filter) does.
+fn classify_accounts(
+    cli: &Cli,
+    entries: Vec<pager::AccountEntry>,
+    selection: &Selection<impl AccountFilter>,
+    too_new: &BTreeSet<AccountId>,
+    existential_deposit: Option<Balance>,
+    keep_raw_values: bool,
+    warnings: &mut warnings::Warnings,
+) -> Result<Classified> {
+    let mut new_accounts = Vec::new();
+    let mut redirected = Vec::new();
+
     let mut total_issuance = 0;
+    let mut out_of_range = 0usize;
+    let mut accounting = accounting::Accounting::default();
+    let mut below_existential_deposit = 0usize;
+    let mut zero_balance = 0usize;
+    let mut rounded_off: Balance = 0;
+    let mut rounded_up: Balance = 0;
 
-    while let Some((key, account)) = iter.next().await? {
-        let pubkey = &hex::encode(&key.0)[STORAGE_PREFIX_LEN + BLAKE_HASH_LEN..];
-        let account_id = pubkey
-            .parse::<AccountId>()
-            .map_err(|err| anyhow!("{}", err))?;
+    let mut not_migrated = Vec::new();
+    let mut raw_values = raw::RawValues::default();
 
-        let total = account.data.free + account.data.reserved;
+    for pager::AccountEntry {
+        account_id,
+        total,
+        info: account,
+        migrated,
+        flags,
+        ..
+    } in entries
+    {
+        // The buckets split this total, they can't overflow once it doesn't.
+        total_issuance = total_issuance
+            .checked_add(total)
+            .ok_or_else(|| anyhow!("Total balance of all accounts overflows"))?;
 
-        total_issuance += total;
+        if migrated == Some(false) {
+            not_migrated.push(account_id.clone());
+        }
 
-        if token_grants.contains(&account_id) || endowed.contains(&account_id) {
-            // Vesting and endowed accounts are ignored.
+        let disposition = selection.filter.classify(account_id.as_ref(), total);
+        if disposition == Disposition::Skip {
+            if selection.token_grants.contains(&account_id) {
+                // Vesting accounts are ignored.
+                accounting.grants += total;
+            } else if selection.endowed.contains(&account_id) {
+                // Endowed accounts are ignored.
+                accounting.endowed += total;
+            } else {
+                return Err(anyhow!(
+                    "Account {} is skipped but is neither a token grant nor endowed",
+                    account_id
+                ));
+            }
             continue;
         } else {
-            // New accounts must have the free balance only.
-            assert_eq!(total, account.data.free);
-            new_accounts.push((account_id, total));
+            // New accounts must have the free balance only, unless the reserves are
+            // carried over as well.
+            if cli.balance_kind.is_strict() && total != account.data.free {
+                return Err(error::Error::Assertion(format!(
+                    "new account {} has a reserved balance of {}",
+                    account_id, account.data.reserved
+                ))
+                .into());
+            }
+            let exported = cli.balance_kind.exported(&account.data)?;
+            if cli.balance_min.map_or(false, |min| exported < min)
+                || cli.balance_max.map_or(false, |max| exported > max)
+            {
+                out_of_range += 1;
+                accounting.out_of_range += total;
+                warnings.emit(warnings::Warning::OutOfRange {
+                    account: account_id,
+                    balance: exported,
+                })?;
+                continue;
+            }
+            if too_new.contains(&account_id) {
+                accounting.too_new += total;
+                warnings.emit(warnings::Warning::TooNew {
+                    account: account_id,
+                    balance: exported,
+                })?;
+                continue;
+            }
+            accounting.new_accounts += exported;
+            accounting.withheld += total - exported;
+            if exported != total {
+                warnings.emit(warnings::Warning::Withheld {
+                    account: account_id.clone(),
+                    total,
+                    exported,
+                })?;
+            }
+            let balance = match &cli.balance_expr {
+                Some(expr) => expr.eval(exported)?,
+                None => exported,
+            };
+            let balance = match cli.round_to {
+                Some(unit) => {
+                    let rounded = cli.rounding.round(balance, unit).ok_or_else(|| {
+                        anyhow!("Rounding {} to a multiple of {} overflows", balance, unit)
+                    })?;
+                    let overflow = || anyhow!("Total rounding remainder overflows");
+                    if rounded < balance {
+                        rounded_off = rounded_off
+                            .checked_add(balance - rounded)
+                            .ok_or_else(overflow)?;
+                    } else {
+                        rounded_up = rounded_up
+                            .checked_add(rounded - balance)
+                            .ok_or_else(overflow)?;
+                    }
+                    rounded
+                }
+                None => balance,
+            };
+            if total == 0 {
+                zero_balance += 1;
+                match cli.zero_balance {
+                    ZeroBalance::Keep => {}
+                    ZeroBalance::Skip => continue,
+                    ZeroBalance::Warn => warnings.emit(warnings::Warning::ZeroBalance {
+                        account: account_id.clone(),
+                    })?,
+                }
+            }
+            if let Disposition::Redirect(target) = disposition {
+                redirected.push((AccountId::from(target), balance));
+                continue;
+            }
+            if keep_raw_values {
+                raw_values.insert(&account_id, &account, flags)?;
+            }
+            new_accounts.push(Entry(account_id, balance));
         }
     }
 
-    let expected_total_issuance = api
-        .storage()
-        .balances()
-        .total_issuance(Some(block_hash))
-        .await?;
+    if !redirected.is_empty() {
+        let mut index = new_accounts
+            .iter()
+            .enumerate()
+            .map(|(i, Entry(account_id, _))| (account_id.clone(), i))
+            .collect::<HashMap<_, _>>();
+        for (target, balance) in redirected {
+            match index.get(&target) {
+                Some(&i) => {
+                    let existing = &mut new_accounts[i].1;
+                    *existing = existing.checked_add(balance).ok_or_else(|| {
+                        anyhow!(
+                            "Balance of {} overflows with the redirected balances",
+                            target
+                        )
+                    })?;
+                }
+                None => {
+                    index.insert(target.clone(), new_accounts.len());
+                    new_accounts.push(Entry(target, balance));
+                }
+            }
+        }
+    }
+
+    // Checked once the zero balances are skipped and the redirected balances added up.
+    if let Some(existential_deposit) = existential_deposit {
+        for Entry(account_id, balance) in &new_accounts {
+            if *balance < existential_deposit {
+                below_existential_deposit += 1;
+                warnings.emit(warnings::Warning::BelowExistentialDeposit {
+                    account: account_id.clone(),
+                    balance: *balance,
+                    existential_deposit,
+                })?;
+            }
+        }
+    }
 
-    assert_eq!(total_issuance, expected_total_issuance);
+    Ok(Classified {
+        new_accounts,
+        total_issuance,
+        accounting,
+        out_of_range,
+        below_existential_deposit,
+        zero_balance,
+        rounded_off,
+        rounded_up,
+        not_migrated,
+        raw_values,
+    })
+}
 
-    let block_header = api
-        .client
-        .rpc()
-        .header(Some(block_hash))
-        .await?
-        .unwrap_or_else(|| panic!("Header for block hash {} not found", block_hash));
+async fn snapshot(cli: &Cli, target: &Target) -> Result<()> {
+    let Target {
+        clients,
+        api,
+        block_hash,
+        block_number,
+        runtime_version,
+        metadata_spec_version,
+        ..
+    } = target;
+    let block_hash = *block_hash;
+    let block_number = *block_number;
 
-    println!(
+    if cli.stdout {
+        output::reserve_stdout();
+    }
+
+    if cli.round_to == Some(0) {
+        return Err(anyhow!("--round-to must be greater than 0"));
+    }
+    if matches!(cli.remainder, rounding::Remainder::Collect) && cli.dust_account.is_none() {
+        return Err(anyhow!("--remainder collect requires --dust-account"));
+    }
+
+    if cli.per_record_checksum && !matches!(cli.format, OutputFormat::Ndjson) {
+        return Err(anyhow!(
+            "--per-record-checksum is only supported by --format ndjson"
+        ));
+    }
+    if cli.write_index && !matches!(cli.format, OutputFormat::Bin) {
+        return Err(anyhow!("--write-index is only supported by --format bin"));
+    }
+    if cli.package.is_some() && (cli.split_count.is_some() || cli.split_bytes.is_some()) {
+        return Err(anyhow!(
+            "--package is not supported along with --split-count or --split-bytes"
+        ));
+    }
+    if cli.post_verify.is_some() {
+        verify::check_options(cli)?;
+    }
+    let raw_format = match cli.format {
+        OutputFormat::RawTop => Some("raw-top"),
+        OutputFormat::Kv => Some("kv"),
+        _ => None,
+    };
+    let keep_raw_values = raw_format.is_some();
+    if let Some(format) = raw_format {
+        if cli.asset_id.is_some() || cli.dynamic || cli.offline_decode {
+            return Err(anyhow!(
+                "--format {} writes System::Account values with the generated types, \
+                 it can't be combined with --asset-id, --dynamic or --offline-decode",
+                format
+            ));
+        }
+        if let Some(option) = verify::incompatible_option(cli) {
+            return Err(anyhow!(
+                "--format {} writes the on-chain accounts, it can't be combined with {}",
+                format,
+                option
+            ));
+        }
+    }
+    let genesis_vesting = match &cli.genesis_vesting {
+        Some(_) if !matches!(cli.format, OutputFormat::SubspaceGenesis) => {
+            return Err(anyhow!(
+                "--genesis-vesting is only supported by --format subspace-genesis"
+            ))
+        }
+        Some(path) => Some(subspace_regenesis_tool::load_vesting::<AccountId>(path)?),
+        None => None,
+    };
+    buckets::check_edges(&cli.bucket_edges)?;
+    let targets = cli
+        .target_file
+        .as_deref()
+        .map(targets::Targets::load)
+        .transpose()?;
+
+    let split = match (cli.split_count, cli.split_bytes) {
+        (Some(0), _) => return Err(anyhow!("--split-count must be greater than 0")),
+        (Some(count), _) => Some(split::Split::Count(count)),
+        (_, Some(size)) => Some(split::Split::Bytes(size.0)),
+        (None, None) => None,
+    };
+    if split.is_some() && !matches!(cli.format, OutputFormat::Json) {
+        return Err(anyhow!(
+            "--split-count and --split-bytes are only supported by --format json"
+        ));
+    }
+
+    let path = output_path(cli, block_number, block_hash)?;
+    // A split snapshot is only complete once its manifest is written.
+    let existing_path = match split {
+        Some(_) => split::manifest_path(&path),
+        None => path.clone(),
+    };
+
+    // Extending the `--append` snapshot in place is the point of appending.
+    let appending_in_place = cli.append.as_ref().map_or(false, |base_path| {
+        path.exists() && std::fs::canonicalize(base_path).ok() == std::fs::canonicalize(&path).ok()
+    });
+
+    if existing_path.exists() && !appending_in_place && !cli.stdout && !cli.summary_only {
+        if cli.skip_existing {
+            status!("{} already exists, skipping", existing_path.display());
+            return Ok(());
+        }
+        if !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it or --skip-existing to skip",
+                existing_path.display()
+            ));
+        }
+    }
+
+    // The files written along the snapshot are not overwritten silently either.
+    let mut sidecar_paths = Vec::new();
+    if cli.sample_weighted.is_some() {
+        sidecar_paths.push(sample_path(cli, block_number)?);
+    }
+    if cli.export_header && !cli.summary_only {
+        sidecar_paths.push(header_path(cli, block_number)?);
+    }
+    if let Some(package_path) = cli.package.as_ref().filter(|_| !cli.summary_only) {
+        sidecar_paths.push(package_path.clone());
+    }
+    if !appending_in_place && !cli.stdout && !cli.summary_only {
+        sidecar_paths.push(snapshot::metadata_path(&path));
+        if cli.write_index && matches!(cli.format, OutputFormat::Bin) {
+            sidecar_paths.push(snapshot::index_path(&path));
+        }
+    }
+    if let Some(existing_path) = sidecar_paths.iter().find(|path| path.exists()) {
+        if !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it",
+                existing_path.display()
+            ));
+        }
+    }
+
+    let metadata_cache = match &cli.metadata_cache {
+        Some(cache_path) => Some(match cache::MetadataCache::load(cache_path)? {
+            Some(cached) if cached.spec_version == runtime_version.spec_version => cached,
+            Some(cached) if !cli.force => {
+                return Err(anyhow!(
+                    "Metadata cache {} is stale: spec_version {} != {} at block {:?}, pass --force to refresh it",
+                    cache_path.display(),
+                    cached.spec_version,
+                    runtime_version.spec_version,
+                    block_hash
+                ))
+            }
+            _ => {
+                let cache = cache::MetadataCache::new(
+                    api.client.metadata().runtime_metadata(),
+                    *metadata_spec_version,
+                    runtime_version.spec_version,
+                    runtime_version.transaction_version,
+                )?;
+                cache.save(cache_path)?;
+                status!("Metadata has been cached to {}", cache_path.display());
+                cache
+            }
+        }),
+        None => None,
+    };
+
+    let mut metadata = snapshot::Metadata {
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        spec_version: runtime_version.spec_version,
+        block_number,
+        block_hash,
+        options: serde_json::to_value(cli)?,
+        resume: None,
+        sha256: None,
+        fingerprint: None,
+        merkle_root: None,
+        partial: false,
+        skipped_undecodable: 0,
+        accounts: None,
+        total: None,
+    };
+
+    // Effective configuration of this run, persisted in the snapshot metadata as well.
+    status!("{}", serde_json::to_string(&metadata)?);
+
+    if cli.verify_ss58_prefix {
+        verify_ss58_prefix(
+            std::iter::once(SUDO_ACCOUNT).chain(TOKEN_GRANTS.iter().copied()),
+            cli.ss58_prefix,
+        )?;
+    }
+
+    let (endowed, token_grants) = special_accounts()?;
+    let selection = Selection::special(&endowed, &token_grants);
+
+    let formatter = cli.formatter();
+    let mut warnings = warnings::Warnings::open(
+        cli.warnings_file.as_deref(),
+        cli.print_warnings,
+        formatter.clone(),
+    )?;
+
+    // An account in both special lists would only be counted as a grant, and the
+    // `--dust-account` remainder of a special account would be counted twice. The
+    // compiled-in lists are covered by a unit test, the dust account is only known here.
+    let overlapping = list_overlaps(&[
+        ("token grants", token_grants.as_slice()),
+        ("endowed", endowed.as_slice()),
+        (
+            "dust account",
+            cli.dust_account
+                .as_ref()
+                .map_or(&[][..], std::slice::from_ref),
+        ),
+    ]);
+    for (account_id, lists) in &overlapping {
+        warnings.emit(warnings::Warning::SpecialAccountOverlap {
+            account: (*account_id).clone(),
+            lists: lists.clone(),
+        })?;
+    }
+    if cli.strict && !overlapping.is_empty() {
+        return Err(error::Error::Assertion(format!(
+            "accounts present in several special lists: {}",
+            overlapping
+                .iter()
+                .map(|(account_id, _)| account_id.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))
+        .into());
+    }
+
+    // Read upfront, a zero total issuance almost certainly means a wrong target.
+    let expected_total_issuance = expected_issuance(cli, &api.client, block_hash).await?;
+    if expected_total_issuance == 0 {
+        let chain = api.client.rpc().system_chain().await?;
+        warnings.emit(warnings::Warning::ZeroIssuance {
+            chain: chain.clone(),
+            spec_version: runtime_version.spec_version,
+            block_number,
+        })?;
+        if cli.strict {
+            return Err(error::Error::Assertion(format!(
+                "total issuance is zero at block #{} ({:?}) of {}, spec_version {}",
+                block_number, block_hash, chain, runtime_version.spec_version
+            ))
+            .into());
+        }
+    }
+
+    let prefix = account_prefix(cli, &api.client)?;
+    let extra_flags = cli.asset_id.is_none() && cli.account_storage.has_extra_flags(&api.client)?;
+    if cli.report_flags && !extra_flags {
+        status!(
+            "Runtime AccountData has no flags field, every account uses the same balance model"
+        );
+    }
+
+    let decoder = match &metadata_cache {
+        Some(cache) if cli.offline_decode => {
+            status!("Decoding the accounts against the cached metadata");
+            Some(Arc::new(dynamic::AccountDecoder::from_metadata(
+                &cli.account_storage,
+                &cache.runtime_metadata()?,
+            )?))
+        }
+        _ if cli.dynamic => {
+            status!("Decoding the accounts against the metadata of the node");
+            Some(Arc::new(dynamic::AccountDecoder::new(
+                &cli.account_storage,
+                &api.client,
+            )?))
+        }
+        _ => None,
+    };
+
+    let limiter = match cli.max_pages_per_sec {
+        Some(rate) => {
+            let limiter = ratelimit::RateLimiter::new(rate)?;
+            status!(
+                "Rate limited to {} pages per second, i.e. up to {} accounts per second",
+                rate,
+                rate * f64::from(pager::PAGE_SIZE)
+            );
+            Some(Arc::new(limiter))
+        }
+        None => None,
+    };
+
+    if cli.heartbeat_secs == Some(0) {
+        return Err(anyhow!("--heartbeat-secs must be greater than 0"));
+    }
+    let progress = (cli.heartbeat_secs.is_some() || cli.progress_file.is_some())
+        .then(|| Arc::new(heartbeat::Progress::default()));
+    let heartbeat = cli
+        .heartbeat_secs
+        .zip(progress.clone())
+        .map(|(secs, progress)| heartbeat::spawn(progress, secs));
+    let progress_file = cli
+        .progress_file
+        .clone()
+        .zip(progress.clone())
+        .map(|(path, progress)| heartbeat::ProgressFile::spawn(progress, path))
+        .transpose()?;
+
+    let pinned = cli.block_hash.is_some() || cli.block_number.is_some();
+    upgrade::check(
+        *metadata_spec_version,
+        runtime_version.spec_version,
+        block_hash,
+        pinned,
+    )?;
+
+    let shards = clients
+        .iter()
+        .enumerate()
+        .map(|(index, client)| {
+            let (start_key, end_key) = pager::shard_range(&prefix, index, clients.len());
+            let mut pager = pager::AccountPager::with_range(
+                client.clone(),
+                &prefix,
+                block_hash,
+                start_key,
+                end_key,
+            );
+            if let Some(limiter) = &limiter {
+                pager = pager.with_rate_limit(limiter.clone());
+            }
+            if cli.skip_undecodable {
+                pager = pager.skip_undecodable();
+            }
+            if cli.asset_id.is_some() {
+                pager = pager.asset_accounts();
+            }
+            if extra_flags {
+                pager = pager.extra_flags();
+            }
+            if let Some(decoder) = &decoder {
+                pager = pager.dynamic(decoder.clone());
+            }
+            if !pinned {
+                pager = pager.check_runtime(*metadata_spec_version);
+            }
+            let progress = progress.clone();
+            tokio::spawn(async move {
+                let mut entries = Vec::new();
+                {
+                    let mut accounts = Box::pin(pager::account_entries(&mut pager));
+                    while let Some(entry) = accounts.try_next().await? {
+                        if let Some(progress) = &progress {
+                            progress.record(entry.total, &entry.key.0);
+                        }
+                        entries.push(entry);
+                    }
+                }
+                Ok::<_, anyhow::Error>((entries, pager.skipped().to_vec()))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut results = Vec::with_capacity(shards.len());
+    for shard in shards {
+        results.push(shard.await);
+    }
+
+    // The iteration is over, successful or not.
+    if let Some(heartbeat) = heartbeat {
+        heartbeat.abort();
+    }
+    if let Some(progress_file) = &progress_file {
+        progress_file.set_phase(heartbeat::Phase::Checking)?;
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    for result in results {
+        let (shard_entries, shard_skipped) = result??;
+        entries.extend(shard_entries);
+        skipped.extend(shard_skipped);
+    }
+
+    // The state of a fixed block can't change, a node serving a moving state would
+    // make the balances inconsistent with each other.
+    let final_total_issuance = expected_issuance(cli, &api.client, block_hash).await?;
+    if final_total_issuance != expected_total_issuance {
+        return Err(error::Error::Assertion(format!(
+            "total issuance at block #{} ({:?}) changed from {} to {} during the iteration, \
+             the node does not serve the state of this block",
+            block_number, block_hash, expected_total_issuance, final_total_issuance
+        ))
+        .into());
+    }
+
+    for (key, error) in &skipped {
+        warnings.emit(warnings::Warning::Undecodable {
+            key: format!("0x{}", hex::encode(&key.0)),
+            error: error.clone(),
+        })?;
+    }
+    metadata.partial = !skipped.is_empty();
+    metadata.skipped_undecodable = skipped.len();
+
+    ensure_accounts(&entries, block_number, block_hash)?;
+
+    // The shards are in key order, the last entry holds the final key reached.
+    metadata.resume = entries.last().map(|entry| snapshot::ResumeToken {
+        last_key: format!("0x{}", hex::encode(&entry.key.0)),
+        block_hash,
+    });
+
+    let scanned = entries.len();
+
+    let existential_deposit = match cli.existential_deposit {
+        Some(existential_deposit) => Some(existential_deposit),
+        None => match cli.asset_id {
+            Some(asset_id) => Some(
+                storage::asset_details(&api.client, block_hash, asset_id)
+                    .await?
+                    .min_balance,
+            ),
+            None => storage::existential_deposit(&api.client)?,
+        },
+    };
+
+    let too_new = match cli.min_age_block {
+        Some(reference) => {
+            let reference_number =
+                age::reference_block_number(&api.client, reference, block_number).await?;
+            let candidates = entries
+                .iter()
+                .filter(|entry| {
+                    selection
+                        .filter
+                        .classify(entry.account_id.as_ref(), entry.total)
+                        != Disposition::Skip
+                })
+                .map(|entry| entry.account_id.clone())
+                .collect::<Vec<_>>();
+            status!(
+                "Looking up {} accounts at block #{} ({:?}) for --min-age-block",
+                candidates.len(),
+                reference_number,
+                reference
+            );
+            age::absent_at(clients, &prefix, reference, candidates).await?
+        }
+        None => Default::default(),
+    };
+
+    let Classified {
+        mut new_accounts,
+        total_issuance,
+        accounting,
+        out_of_range,
+        below_existential_deposit,
+        zero_balance,
+        rounded_off,
+        rounded_up,
+        not_migrated,
+        raw_values,
+    } = classify_accounts(
+        cli,
+        entries,
+        &selection,
+        &too_new,
+        existential_deposit,
+        keep_raw_values,
+        &mut warnings,
+    )?;
+
+    if cli.round_to.is_some() {
+        match (cli.remainder, &cli.dust_account) {
+            (rounding::Remainder::Collect, Some(dust_account)) => {
+                let remainder = rounded_off.checked_sub(rounded_up).ok_or_else(|| {
+                    anyhow!(
+                        "Rounding added {} more than it removed, there is no remainder to collect",
+                        rounded_up - rounded_off
+                    )
+                })?;
+                match new_accounts
+                    .iter_mut()
+                    .find(|Entry(account_id, _)| account_id == dust_account)
+                {
+                    Some(Entry(_, balance)) => {
+                        *balance = balance
+                            .checked_add(remainder)
+                            .ok_or_else(|| anyhow!("Balance of the dust account overflows"))?
+                    }
+                    None => new_accounts.push(Entry(dust_account.clone(), remainder)),
+                }
+                status!(
+                    "Rounding remainder: {} removed, {} added, {} collected into {}",
+                    formatter.display(rounded_off),
+                    formatter.display(rounded_up),
+                    formatter.display(remainder),
+                    dust_account
+                );
+            }
+            _ => status!(
+                "Rounding remainder: {} removed, {} added, the net remainder is burnt",
+                formatter.display(rounded_off),
+                formatter.display(rounded_up)
+            ),
+        }
+    }
+
+    if cli.strict && !cli.allow_zero {
+        let zero_accounts = new_accounts
+            .iter()
+            .filter(|Entry(_, balance)| *balance == 0)
+            .map(|Entry(account_id, _)| account_id.to_string())
+            .collect::<Vec<_>>();
+        if !zero_accounts.is_empty() {
+            return Err(error::Error::Assertion(format!(
+                "{} new accounts have a zero balance, pass --allow-zero to keep them: {}",
+                zero_accounts.len(),
+                zero_accounts.join(", ")
+            ))
+            .into());
+        }
+    }
+
+    let accounted = accounting.total()?;
+    if accounted != total_issuance {
+        return Err(error::Error::Assertion(format!(
+            "accounting buckets add up to {} instead of {}, this is a bug",
+            accounted, total_issuance
+        ))
+        .into());
+    }
+
+    // The sum of all accounts includes the inactive funds, the check is against the
+    // total issuance either way, the active issuance is only informative.
+    let inactive_issuance = match cli.asset_id {
+        Some(_) => None,
+        None => storage::inactive_issuance(&api.client, block_hash).await?,
+    };
+
+    if total_issuance != expected_total_issuance {
+        accounting.print_mismatch(
+            scanned,
+            expected_total_issuance,
+            inactive_issuance,
+            &formatter,
+        )?;
+        // The run is about to stop, the table is not printed further down.
+        if !cli.no_issuance_check {
+            accounting.print(expected_total_issuance, &formatter)?;
+        }
+    }
+
+    if cli.no_issuance_check {
+        if total_issuance != expected_total_issuance {
+            warnings.emit(warnings::Warning::IssuanceMismatch {
+                iterated: total_issuance,
+                expected: expected_total_issuance,
+            })?;
+        }
+    } else if total_issuance != expected_total_issuance {
+        return Err(error::Error::IssuanceMismatch {
+            iterated: total_issuance,
+            expected: expected_total_issuance,
+        }
+        .into());
+    }
+
+    let new_accounts = match &cli.overrides_file {
+        Some(overrides_path) => {
+            let mut new_accounts = new_accounts;
+            let (before, after) = overrides::Overrides::load(overrides_path)?.apply(
+                &mut new_accounts,
+                cli.allow_new_overrides,
+                &formatter,
+            )?;
+            status!(
+                "Overrides of {} changed the new issuance by {}{}",
+                overrides_path.display(),
+                if after >= before { "+" } else { "-" },
+                formatter.display(after.abs_diff(before))
+            );
+            new_accounts
+        }
+        None => new_accounts,
+    };
+
+    let new_accounts = match &cli.append {
+        Some(base_path) => {
+            match snapshot::load_metadata(base_path)? {
+                Some(base) if base.block_hash == block_hash => {}
+                Some(base) if !cli.force => {
+                    return Err(anyhow!(
+                        "{} was taken at block {:?} instead of {:?}, pass --force to merge anyway",
+                        base_path.display(),
+                        base.block_hash,
+                        block_hash
+                    ))
+                }
+                None if !cli.force => {
+                    return Err(anyhow!(
+                    "No metadata found for {}, pass --force to merge without checking its block",
+                    base_path.display()
+                ))
+                }
+                _ => {}
+            }
+            snapshot::merge(snapshot::load(base_path)?, new_accounts, cli.append_mode)?
+        }
+        None => new_accounts,
+    };
+
+    let new_accounts = match &cli.baseline {
+        Some(baseline_path) => {
+            let baseline = snapshot::load_map(baseline_path)?;
+            let (new_accounts, deltas) = diff::subtract_baseline(new_accounts, &baseline);
+            status!(
+                "Balances relative to {}: {} decreased and clamped at zero, {} baseline accounts gone",
+                baseline_path.display(),
+                deltas.decreased,
+                deltas.gone
+            );
+            new_accounts
+        }
+        None => new_accounts,
+    };
+
+    let new_accounts = match cli.seed {
+        Some(seed) if cli.shuffle => {
+            let mut new_accounts = new_accounts;
+            new_accounts.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+            new_accounts
+        }
+        _ => new_accounts,
+    };
+
+    if cli.target_balance_bits < Balance::BITS {
+        let max = Balance::MAX >> (Balance::BITS - cli.target_balance_bits);
+        if let Some(Entry(account_id, balance)) =
+            new_accounts.iter().find(|Entry(_, balance)| *balance > max)
+        {
+            return Err(error::Error::Assertion(format!(
+                "balance {} of {} does not fit into {} bits",
+                balance, account_id, cli.target_balance_bits
+            ))
+            .into());
+        }
+        let total = new_accounts
+            .iter()
+            .try_fold(0 as Balance, |total, Entry(_, balance)| {
+                total.checked_add(*balance)
+            })
+            .filter(|total| *total <= max);
+        if total.is_none() {
+            return Err(error::Error::Assertion(format!(
+                "total balance of the snapshot does not fit into {} bits",
+                cli.target_balance_bits
+            ))
+            .into());
+        }
+    }
+
+    let new_accounts = match &cli.redact_salt {
+        Some(salt) if cli.redact => {
+            // The last key would reveal an account.
+            metadata.resume = None;
+            snapshot::redact(new_accounts, salt.as_bytes())
+        }
+        _ => new_accounts,
+    };
+
+    if cli.verify_roundtrip {
+        verify_roundtrip(
+            new_accounts.iter().map(|Entry(account_id, _)| account_id),
+            cli.ss58_prefix,
+        )?;
+        status!(
+            "All {} account ids round-trip through SS58",
+            new_accounts.len()
+        );
+    }
+
+    status!(
         "State of balances at block #{:?} ({:?})",
-        block_header.number(),
+        block_number,
         block_hash
     );
-    println!("Total new accounts: {}", new_accounts.len());
-    println!(
-        "Total new issuance: {}",
-        new_accounts
-            .iter()
-            .map(|(_, balance)| balance)
-            .sum::<Balance>()
-    );
+    status!("Total new accounts: {}", new_accounts.len());
+    if cli.balance_min.is_some() || cli.balance_max.is_some() {
+        status!("Accounts outside of the balance range: {}", out_of_range);
+    }
+    match cli.zero_balance {
+        ZeroBalance::Skip => status!("Accounts skipped with a zero balance: {}", zero_balance),
+        ZeroBalance::Keep | ZeroBalance::Warn => {
+            status!("Accounts kept with a zero balance: {}", zero_balance)
+        }
+    }
+    if cli.min_age_block.is_some() {
+        status!(
+            "Accounts excluded as too new for --min-age-block: {}",
+            too_new.len()
+        );
+    }
+    if cli.report_flags && extra_flags {
+        for account_id in &not_migrated {
+            status!("Not migrated to the new balance model: {}", account_id);
+        }
+        status!(
+            "Accounts not migrated to the new balance model: {}",
+            not_migrated.len()
+        );
+    }
+    if cli.skip_undecodable {
+        status!(
+            "Undecodable accounts skipped: {}{}",
+            skipped.len(),
+            if skipped.is_empty() {
+                ""
+            } else {
+                ", the snapshot is partial"
+            }
+        );
+    }
+    match existential_deposit {
+        Some(existential_deposit) => status!(
+            "New accounts below the existential deposit {}: {}",
+            formatter.display(existential_deposit),
+            below_existential_deposit
+        ),
+        None => status!("Runtime has no Balances::ExistentialDeposit to check the new accounts"),
+    }
+    let new_issuance = new_accounts
+        .iter()
+        .map(|Entry(_, balance)| balance)
+        .sum::<Balance>();
+    status!("Total new issuance: {}", formatter.display(new_issuance));
+    metadata.accounts = Some(new_accounts.len());
+    metadata.total = Some(new_issuance);
+    let merkle_root = hex::encode(snapshot::merkle_root(&new_accounts));
+    status!("Merkle root: 0x{}", merkle_root);
+    metadata.merkle_root = Some(merkle_root);
 
-    let mut path = std::env::current_dir()?;
-    path.push(format!("balances_{}.json", block_header.number()));
+    if let Some(buckets_file) = &cli.buckets_file {
+        let buckets = buckets::bucketize(&new_accounts, &cli.bucket_edges)?;
+        buckets::write(buckets_file, &buckets)?;
+        status!(
+            "{} balance buckets have been written to {}",
+            buckets.len(),
+            buckets_file.display()
+        );
+    }
 
-    let file = std::fs::OpenOptions::new()
-        .create(true)
-        .write(true)
-        .open(&path)?;
+    if let Some((count, seed)) = cli.sample_weighted.zip(cli.seed) {
+        let sample = sample::weighted(&new_accounts, count, seed);
+        let sample_path = sample_path(cli, block_number)?;
+        let mut writer = output::FileWriter::create(&sample_path)?;
+        serde_json::to_writer_pretty(&mut writer, &sample)?;
+        writer.finish(cli.fsync)?;
+        status!(
+            "Balance weighted sample of {} accounts (seed {}) has been written to {}",
+            sample.len(),
+            seed,
+            sample_path.display()
+        );
+    }
 
-    serde_json::to_writer_pretty(&file, &new_accounts)?;
+    if let Some(targets) = &targets {
+        let criteria = targets.check(&new_accounts, existential_deposit, &formatter)?;
+        status!("Targets:");
+        for criterion in &criteria {
+            status!(
+                "  {:<6}{}: {} (target {})",
+                if criterion.pass { "PASS" } else { "FAIL" },
+                criterion.name,
+                criterion.actual,
+                criterion.target
+            );
+        }
+        let failed = criteria.iter().filter(|criterion| !criterion.pass).count();
+        if failed > 0 && cli.strict {
+            return Err(error::Error::Assertion(format!(
+                "{} of {} targets are not met",
+                failed,
+                criteria.len()
+            ))
+            .into());
+        }
+        status!(
+            "Targets met: {} of {}",
+            criteria.len() - failed,
+            criteria.len()
+        );
+    }
 
-    println!(
-        "Snapshot has been successfully written to {}",
-        path.display()
+    accounting.print(expected_total_issuance, &formatter)?;
+
+    let warning_count = warnings.finish()?;
+    if let Some(warnings_file) = &cli.warnings_file {
+        status!(
+            "{} warnings have been written to {}",
+            warning_count,
+            warnings_file.display()
+        );
+    }
+
+    match inactive_issuance {
+        Some(inactive) => status!(
+            "Inactive issuance: {}, active issuance: {}",
+            formatter.display(inactive),
+            formatter.display(expected_total_issuance.saturating_sub(inactive))
+        ),
+        None if cli.asset_id.is_none() => {
+            status!("Runtime does not track Balances::InactiveIssuance")
+        }
+        None => {}
+    }
+
+    if cli.post_verify.is_some() {
+        verify::post_verify(
+            cli,
+            &api.client,
+            &prefix,
+            block_hash,
+            extra_flags,
+            &new_accounts,
+        )
+        .await?;
+    }
+
+    if cli.summary_only {
+        status!("Summary only, no snapshot has been written");
+        if let Some(progress_file) = &progress_file {
+            progress_file.set_phase(heartbeat::Phase::Done)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(progress_file) = &progress_file {
+        progress_file.set_phase(heartbeat::Phase::Writing)?;
+    }
+
+    if cli.export_header {
+        let header_path = header_path(cli, block_number)?;
+        let mut writer = output::FileWriter::create(&header_path)?;
+        serde_json::to_writer_pretty(&mut writer, &snapshot::HeaderExport::new(&target.header))?;
+        writer.finish(cli.fsync)?;
+        status!("Block header has been written to {}", header_path.display());
+    }
+
+    if cli.stdout {
+        let written: Result<()> = match cli.format {
+            OutputFormat::Json => {
+                let stdout = std::io::stdout();
+                let mut writer = std::io::BufWriter::new(stdout.lock());
+                output::write_json(&mut writer, &new_accounts, cli.json_indent)?;
+                writeln!(writer)?;
+                writer.flush()?;
+                Ok(())
+            }
+            OutputFormat::Ndjson => {
+                let stdout = std::io::stdout();
+                ndjson::write(
+                    std::io::BufWriter::new(stdout.lock()),
+                    &new_accounts,
+                    cli.per_record_checksum,
+                )
+            }
+            OutputFormat::CanonicalJson => {
+                let stdout = std::io::stdout();
+                canonical::write(std::io::BufWriter::new(stdout.lock()), &new_accounts)
+            }
+            OutputFormat::DebugJson => {
+                let stdout = std::io::stdout();
+                let mut writer = std::io::BufWriter::new(stdout.lock());
+                output::write_json(
+                    &mut writer,
+                    &debug_entries(cli, &new_accounts),
+                    cli.json_indent,
+                )?;
+                writeln!(writer)?;
+                writer.flush()?;
+                Ok(())
+            }
+            OutputFormat::RawTop => {
+                let stdout = std::io::stdout();
+                raw::write_top(
+                    std::io::BufWriter::new(stdout.lock()),
+                    &raw_values.storage_writes(&prefix, &new_accounts)?,
+                    cli.json_indent,
+                )
+            }
+            OutputFormat::Kv => {
+                let stdout = std::io::stdout();
+                raw::write_kv(
+                    std::io::BufWriter::new(stdout.lock()),
+                    &raw_values.storage_writes(&prefix, &new_accounts)?,
+                )
+            }
+            OutputFormat::SubspaceGenesis => {
+                let stdout = std::io::stdout();
+                genesis::write(
+                    std::io::BufWriter::new(stdout.lock()),
+                    &new_accounts,
+                    genesis_vesting.as_deref(),
+                    cli.json_indent,
+                )
+            }
+            OutputFormat::Parquet => Err(anyhow!("Parquet output cannot be written to stdout")),
+            OutputFormat::Bin => {
+                let stdout = std::io::stdout();
+                binary::write(
+                    std::io::BufWriter::new(stdout.lock()),
+                    &new_accounts,
+                    block_hash,
+                    None,
+                )?;
+                Ok(())
+            }
+        };
+        written?;
+        if let Some(progress_file) = &progress_file {
+            progress_file.set_phase(heartbeat::Phase::Done)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(split) = split {
+        let manifest = split::write(
+            &path,
+            new_accounts,
+            split,
+            block_number,
+            block_hash,
+            cli.json_indent,
+            cli.fsync,
+        )?;
+        let metadata_path = snapshot::metadata_path(&path);
+        serde_json::to_writer_pretty(std::fs::File::create(&metadata_path)?, &metadata)?;
+        status!(
+            "Snapshot has been successfully split into {} parts listed by {} ({} bytes)",
+            manifest.parts.len(),
+            split::manifest_path(&path).display(),
+            manifest.parts.iter().map(|part| part.bytes).sum::<u64>()
+        );
+        if let Some(progress_file) = &progress_file {
+            progress_file.set_phase(heartbeat::Phase::Done)?;
+        }
+        return Ok(());
+    }
+
+    let bytes_written = match cli.format {
+        OutputFormat::Json => {
+            let mut writer = output::FileWriter::create(&path)?;
+            output::write_json(&mut writer, &new_accounts, cli.json_indent)?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::Ndjson => {
+            let mut writer = output::FileWriter::create(&path)?;
+            ndjson::write(&mut writer, &new_accounts, cli.per_record_checksum)?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::CanonicalJson => {
+            let mut writer = output::FileWriter::create(&path)?;
+            canonical::write(&mut writer, &new_accounts)?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::DebugJson => {
+            let mut writer = output::FileWriter::create(&path)?;
+            output::write_json(
+                &mut writer,
+                &debug_entries(cli, &new_accounts),
+                cli.json_indent,
+            )?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::RawTop => {
+            let mut writer = output::FileWriter::create(&path)?;
+            raw::write_top(
+                &mut writer,
+                &raw_values.storage_writes(&prefix, &new_accounts)?,
+                cli.json_indent,
+            )?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::Kv => {
+            let mut writer = output::FileWriter::create(&path)?;
+            raw::write_kv(
+                &mut writer,
+                &raw_values.storage_writes(&prefix, &new_accounts)?,
+            )?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::SubspaceGenesis => {
+            let mut writer = output::FileWriter::create(&path)?;
+            genesis::write(
+                &mut writer,
+                &new_accounts,
+                genesis_vesting.as_deref(),
+                cli.json_indent,
+            )?;
+            writer.finish(cli.fsync)?
+        }
+        OutputFormat::Bin => {
+            let mut writer = output::FileWriter::create(&path)?;
+            let mut index = cli
+                .write_index
+                .then(|| output::FileWriter::create(&snapshot::index_path(&path)))
+                .transpose()?;
+            binary::write(
+                &mut writer,
+                &new_accounts,
+                block_hash,
+                index.as_mut().map(|index| index as &mut dyn Write),
+            )?;
+            if let Some(index) = index {
+                index.finish(cli.fsync)?;
+            }
+            writer.finish(cli.fsync)?
+        }
+        #[cfg(feature = "parquet")]
+        OutputFormat::Parquet => {
+            columnar::write(&path, &new_accounts, &metadata)?;
+            if cli.fsync {
+                std::fs::File::open(&path)?.sync_all()?;
+            }
+            std::fs::metadata(&path)?.len()
+        }
+        #[cfg(not(feature = "parquet"))]
+        OutputFormat::Parquet => {
+            return Err(anyhow!(
+                "Parquet output requires building with `--features parquet`"
+            ))
+        }
+    };
+
+    let sha256 = output::sha256_file(&path)?;
+    let fingerprint = snapshot::fingerprint(block_number, block_hash, new_accounts.len(), &sha256);
+    metadata.sha256 = Some(sha256);
+    metadata.fingerprint = Some(fingerprint.clone());
+
+    let metadata_path = snapshot::metadata_path(&path);
+    serde_json::to_writer_pretty(std::fs::File::create(&metadata_path)?, &metadata)?;
+
+    status!(
+        "Snapshot has been successfully written to {} ({} bytes)",
+        path.display(),
+        bytes_written
     );
+    status!("Fingerprint: {}", fingerprint);
+
+    if let Some(package_path) = &cli.package {
+        let mut files = vec![path.clone(), metadata_path];
+        if cli.export_header {
+            files.push(header_path(cli, block_number)?);
+        }
+        if cli.write_index {
+            files.push(snapshot::index_path(&path));
+        }
+        package::write(package_path, &files)?;
+        status!(
+            "Snapshot has been packaged with {} other files into {}",
+            files.len() - 1,
+            package_path.display()
+        );
+    }
+    if let Some(progress_file) = &progress_file {
+        progress_file.set_phase(heartbeat::Phase::Done)?;
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_accounts() {
+        let block_hash = BlockHash::repeat_byte(1);
+        let err = ensure_accounts(&[], 100, block_hash).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<error::Error>(),
+            Some(error::Error::NoAccounts {
+                block_number: 100,
+                ..
+            })
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("No accounts found at block #100"));
+        assert_eq!(error::exit_code(&err), 4);
+
+        ensure_accounts(&fixtures::entries(), 100, block_hash).unwrap();
+    }
+
+    #[test]
+    fn ss58_roundtrip() {
+        let mut account_ids = fixtures::entries()
+            .into_iter()
+            .map(|entry| entry.account_id)
+            .collect::<Vec<_>>();
+        // Including the extremes of the 32 bytes, under the prefixes of both SS58 lengths
+        // up to the largest one.
+        account_ids.extend([AccountId::new([0; 32]), AccountId::new([0xff; 32])]);
+        for prefix in [0, 2, 42, 2254, 16383] {
+            verify_roundtrip(account_ids.iter(), prefix).unwrap();
+        }
+    }
+
+    #[test]
+    fn duplicate_accounts_across_ss58_prefixes() {
+        let account_id = AccountId::new([1; 32]);
+        let other = AccountId::new([2; 32]);
+        let encoded =
+            [0u16, 2254].map(|prefix| account_id.to_ss58check_with_version(prefix.into()));
+
+        let err = check_distinct(
+            "token grants",
+            &[
+                (encoded[0].as_str(), account_id.clone()),
+                (&other.to_ss58check(), other.clone()),
+                (encoded[1].as_str(), account_id.clone()),
+            ],
+        )
+        .unwrap_err();
+        // Both encodings are reported.
+        let message = err.to_string();
+        assert!(message.contains(&encoded[0]) && message.contains(&encoded[1]));
+        assert_eq!(error::exit_code(&err), 4);
+
+        check_distinct(
+            "token grants",
+            &[
+                (encoded[0].as_str(), account_id.clone()),
+                (&other.to_ss58check(), other),
+            ],
+        )
+        .unwrap();
+        // The compiled-in lists are distinct.
+        special_accounts().unwrap();
+    }
+
+    #[test]
+    fn block_numbers_select_several_blocks() {
+        let cli =
+            Cli::try_parse_from(["subspace-regenesis-tool", "--block-numbers", "100,200"]).unwrap();
+        assert_eq!(cli.block_numbers, [100, 200]);
+        assert!(Cli::try_parse_from([
+            "subspace-regenesis-tool",
+            "--block-numbers",
+            "100,200",
+            "--block-number",
+            "100",
+        ])
+        .is_err());
+    }
+
+    #[test]
+    fn special_lists_do_not_overlap() {
+        let (endowed, token_grants) = special_accounts().unwrap();
+        assert!(list_overlaps(&[
+            ("token grants", token_grants.as_slice()),
+            ("endowed", endowed.as_slice()),
+        ])
+        .is_empty());
+
+        let account = |byte: u8| AccountId::new([byte; 32]);
+        let grants = [account(1), account(2)];
+        let endowed = [account(2), account(3)];
+        let dust = [account(3)];
+        assert_eq!(
+            list_overlaps(&[
+                ("token grants", &grants[..]),
+                ("endowed", &endowed[..]),
+                ("dust account", &dust[..]),
+            ]),
+            [
+                (&account(2), vec!["token grants", "endowed"]),
+                (&account(3), vec!["endowed", "dust account"]),
+            ]
+        );
+    }
+}
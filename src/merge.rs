@@ -0,0 +1,212 @@
+//! Merge of the balances of several networks into a single snapshot.
+
+use crate::dynamic::AccountDecoder;
+use crate::snapshot::{self, AppendMode, Entry, Snapshot};
+use crate::{
+    account_filter, block_hash_at, connect_client, error, output, output_dir, pager, storage,
+    Balance, BlockNumber, Cli, RuntimeApi,
+};
+use anyhow::{anyhow, Error, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Arc;
+use subspace_regenesis_tool::{AccountFilter, Disposition};
+
+/// A network to snapshot, given as `<url>@<block_number>`.
+#[derive(Clone, Debug)]
+pub struct Source {
+    url: String,
+    block_number: BlockNumber,
+}
+
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (url, block_number) = s
+            .rsplit_once('@')
+            .ok_or_else(|| anyhow!("Expected <url>@<block_number>, got {}", s))?;
+        Ok(Self {
+            url: url.to_string(),
+            block_number: block_number
+                .parse()
+                .map_err(|err| anyhow!("Invalid block number in {}: {}", s, err))?,
+        })
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.url, self.block_number)
+    }
+}
+
+impl Serialize for Source {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Snapshot several networks, each at its own block, into a single JSON file summing
+/// the balances of the accounts present on more than one.
+#[derive(Parser, Debug, Serialize)]
+pub struct MergeCmd {
+    /// A network to snapshot as `<url>@<block_number>`, at least two.
+    #[clap(long = "source", required = true, number_of_values = 1)]
+    pub sources: Vec<Source>,
+
+    /// Path of the merged snapshot, `balances_merged.json` in `--out-dir` by default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl MergeCmd {
+    pub async fn run(self, cli: &Cli) -> Result<()> {
+        if self.sources.len() < 2 {
+            return Err(anyhow!("merge needs at least two --source"));
+        }
+
+        let path = match self.output {
+            Some(path) => path,
+            None => output_dir(cli)?.join("balances_merged.json"),
+        };
+        if path.exists() && !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ));
+        }
+
+        let filter = account_filter()?;
+        let formatter = cli.formatter();
+
+        let mut merged = Snapshot::new();
+        let mut source_accounts = 0;
+        let mut first_ss58_prefix = None;
+
+        for source in &self.sources {
+            let client = connect_client(cli, &source.url).await?;
+            let block_hash = block_hash_at(&client, source.block_number).await?;
+            if !cli.no_probe {
+                storage::probe_state(&client, &source.url, source.block_number, block_hash).await?;
+            }
+
+            // The accounts are matched by their raw public key, which only makes sense
+            // if the networks share their account format.
+            let ss58_prefix = storage::ss58_prefix(&client)?;
+            match &first_ss58_prefix {
+                None => first_ss58_prefix = Some((source, ss58_prefix)),
+                Some((first, first_prefix)) if *first_prefix != ss58_prefix => {
+                    let message = format!(
+                        "{} uses the SS58 prefix {:?} but {} uses {:?}",
+                        first, first_prefix, source, ss58_prefix
+                    );
+                    if !cli.force {
+                        return Err(anyhow!(
+                            "{}, pass --force to merge the accounts by public key anyway",
+                            message
+                        ));
+                    }
+                    status!("Warning: {}", message);
+                }
+                Some(_) => {}
+            }
+
+            let prefix = account_prefix(cli, &client)?;
+            let mut pager =
+                pager::AccountPager::with_range(client.clone(), &prefix, block_hash, None, None);
+            if cli.asset_id.is_some() {
+                pager = pager.asset_accounts();
+            }
+            if cli.asset_id.is_none() && cli.account_storage.has_extra_flags(&client)? {
+                pager = pager.extra_flags();
+            }
+            if cli.dynamic {
+                // Each network is decoded against its own metadata.
+                let decoder = AccountDecoder::new(&cli.account_storage, &client)?;
+                pager = pager.dynamic(Arc::new(decoder));
+            }
+
+            let mut entries = Snapshot::new();
+            let mut total_balance: Balance = 0;
+            let mut exported_total: Balance = 0;
+            while let Some(entry) = pager.next_account().await? {
+                total_balance = total_balance
+                    .checked_add(entry.total)
+                    .ok_or_else(|| anyhow!("Total balance of {} overflows", source))?;
+                if filter.classify(entry.account_id.as_ref(), entry.total) == Disposition::Skip {
+                    continue;
+                }
+                // As for a single network, the reserves are not dropped silently.
+                if cli.balance_kind.is_strict() && entry.total != entry.info.data.free {
+                    return Err(error::Error::Assertion(format!(
+                        "account {} of {} has a reserved balance of {}",
+                        entry.account_id, source, entry.info.data.reserved
+                    ))
+                    .into());
+                }
+                let exported = cli.balance_kind.exported(&entry.info.data)?;
+                exported_total = exported_total
+                    .checked_add(exported)
+                    .ok_or_else(|| anyhow!("Exported balance of {} overflows", source))?;
+                entries.push(Entry(entry.account_id, exported));
+            }
+
+            let total_issuance = expected_issuance(cli, &client, block_hash).await?;
+            if total_balance != total_issuance {
+                let message = format!(
+                    "{}: total balance of all accounts {} does not match the total issuance {}",
+                    source,
+                    formatter.display(total_balance),
+                    formatter.display(total_issuance)
+                );
+                if !cli.no_issuance_check {
+                    return Err(anyhow!(
+                        "{}, pass --no-issuance-check to merge anyway",
+                        message
+                    ));
+                }
+                status!("Warning: {}", message);
+            }
+
+            status!(
+                "Source {} ({:?}): {} accounts, total {}",
+                source,
+                block_hash,
+                entries.len(),
+                formatter.display(exported_total)
+            );
+
+            source_accounts += entries.len();
+            merged = snapshot::merge(merged, entries, AppendMode::Add)?;
+        }
+
+        let merged_total = merged
+            .iter()
+            .try_fold(0, |total: Balance, Entry(_, balance)| {
+                total.checked_add(*balance)
+            })
+            .ok_or_else(|| anyhow!("Merged total balance overflows"))?;
+
+        let mut writer = output::FileWriter::create(&path)?;
+        output::write_json(&mut writer, &merged, cli.json_indent)?;
+        let bytes_written = writer.finish(cli.fsync)?;
+
+        status!(
+            "Accounts present on several sources: {}",
+            source_accounts - merged.len()
+        );
+        status!("Merged total: {}", formatter.display(merged_total));
+        status!(
+            "Merged snapshot of {} accounts has been written to {} ({} bytes)",
+            merged.len(),
+            path.display(),
+            bytes_written
+        );
+
+        Ok(())
+    }
+}
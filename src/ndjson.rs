@@ -0,0 +1,148 @@
+//! Newline delimited JSON snapshots, optionally with a checksum per record.
+//!
+//! Each line is `{"account":"<ss58>","balance":<u128>}`, with `--per-record-checksum`
+//! an `"h"` field is appended holding the hex encoded CRC-32 of the line without it, so
+//! that a corrupted record can be located without hashing the whole file.
+
+use crate::snapshot::{Entry, Snapshot};
+use crate::{error, AccountId, Balance};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize, Deserialize)]
+struct Record {
+    account: String,
+    balance: Balance,
+}
+
+/// A `Record` as read back, `h` being absent without `--per-record-checksum`.
+#[derive(Deserialize)]
+struct CheckedRecord {
+    account: String,
+    balance: Balance,
+    h: Option<String>,
+}
+
+/// CRC-32 (IEEE) of `data`.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// Writes one line per entry, with its checksum if `checksum` is set.
+pub fn write(mut writer: impl Write, entries: &[Entry], checksum: bool) -> Result<()> {
+    for Entry(account_id, balance) in entries {
+        let line = serde_json::to_string(&Record {
+            account: account_id.to_string(),
+            balance: *balance,
+        })?;
+        if checksum {
+            // Splice the checksum in before the closing brace.
+            writeln!(
+                writer,
+                "{},\"h\":\"{:08x}\"}}",
+                &line[..line.len() - 1],
+                crc32(line.as_bytes())
+            )?;
+        } else {
+            writeln!(writer, "{}", line)?;
+        }
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Parses a line, checking its checksum if it has one.
+fn parse_line(line: &str) -> Result<Entry> {
+    let CheckedRecord {
+        account,
+        balance,
+        h,
+    } = serde_json::from_str(line)?;
+    let record = Record { account, balance };
+    if let Some(h) = h {
+        let expected = format!("{:08x}", crc32(serde_json::to_string(&record)?.as_bytes()));
+        if h != expected {
+            return Err(anyhow!("checksum {} does not match {}", h, expected));
+        }
+    }
+    let account_id = record
+        .account
+        .parse::<AccountId>()
+        .map_err(|err| anyhow!("invalid account {}: {:?}", record.account, err))?;
+    Ok(Entry(account_id, record.balance))
+}
+
+/// Reads a snapshot, failing on the first corrupted record.
+pub fn read(path: &Path) -> Result<Snapshot> {
+    entries(path)?.collect()
+}
+
+/// Reads the records of a snapshot one line at a time.
+pub fn entries(path: &Path) -> Result<impl Iterator<Item = Result<Entry>>> {
+    let file = std::fs::File::open(path)?;
+    let path = path.to_path_buf();
+    Ok(BufReader::new(file)
+        .lines()
+        .enumerate()
+        .map(move |(index, line)| {
+            parse_line(&line?)
+                .map_err(|err| anyhow!("Line {} of {}: {}", index + 1, path.display(), err))
+        }))
+}
+
+/// Check the per-record checksums of a `--format ndjson` snapshot.
+#[derive(Parser, Debug, Serialize)]
+pub struct VerifyRecordsCmd {
+    /// The snapshot to check.
+    pub path: PathBuf,
+}
+
+impl VerifyRecordsCmd {
+    pub fn run(self) -> Result<()> {
+        let file = std::fs::File::open(&self.path)?;
+        let mut records = 0;
+        let mut unchecked = 0;
+        let mut failures = 0;
+        for (index, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            records += 1;
+            if !line.contains("\"h\":") {
+                unchecked += 1;
+            }
+            if let Err(err) = parse_line(&line) {
+                println!("Line {}: {}", index + 1, err);
+                failures += 1;
+            }
+        }
+
+        println!(
+            "Records: {}, without checksum: {}, failed: {}",
+            records, unchecked, failures
+        );
+
+        if failures > 0 {
+            return Err(error::Error::Assertion(format!(
+                "{} records of {} are corrupted",
+                failures,
+                self.path.display()
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+}
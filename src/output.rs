@@ -0,0 +1,81 @@
+//! Buffered snapshot file writer.
+
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+pub use subspace_regenesis_tool::{reserve_stdout, stdout_reserved};
+
+/// Buffered file writer which counts the bytes written.
+///
+/// The buffer hands the data over to the file whenever it fills up, so the snapshot
+/// reaches the OS as it is written rather than all at once on exit.
+pub struct FileWriter {
+    inner: BufWriter<File>,
+    written: u64,
+}
+
+impl FileWriter {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Self {
+            inner: BufWriter::new(File::create(path)?),
+            written: 0,
+        })
+    }
+
+    /// Flushes the remaining buffer, syncs the file to disk if `fsync` is set and
+    /// returns the total number of bytes written.
+    pub fn finish(mut self, fsync: bool) -> io::Result<u64> {
+        self.inner.flush()?;
+        if fsync {
+            self.inner.get_ref().sync_all()?;
+        }
+        Ok(self.written)
+    }
+}
+
+impl Write for FileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes `value` as JSON indented by `indent` spaces per level, `--json-indent`, or
+/// on a single line if `indent` is 0.
+pub fn write_json<W: Write, T: Serialize + ?Sized>(
+    writer: W,
+    value: &T,
+    indent: usize,
+) -> serde_json::Result<()> {
+    if indent == 0 {
+        return serde_json::to_writer(writer, value);
+    }
+    let indent = vec![b' '; indent];
+    let mut serializer =
+        serde_json::Serializer::with_formatter(writer, PrettyFormatter::with_indent(&indent));
+    value.serialize(&mut serializer)
+}
+
+/// Returns the SHA-256 of the file at `path`, hex encoded.
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1024 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
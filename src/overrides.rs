@@ -0,0 +1,177 @@
+//! Manual balance corrections of `--overrides-file`.
+//!
+//! Each line is `address,balance` where the balance is either the new value, or an
+//! adjustment when prefixed with `+` or `-`. Blank lines and lines starting with `#`
+//! are ignored.
+
+use crate::format::BalanceFormatter;
+use crate::snapshot::{Entry, Snapshot};
+use crate::{AccountId, Balance};
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy)]
+enum Change {
+    Set(Balance),
+    Add(Balance),
+    Sub(Balance),
+}
+
+impl Change {
+    fn apply(self, account_id: &AccountId, balance: Balance) -> Result<Balance> {
+        match self {
+            Self::Set(new) => Some(new),
+            Self::Add(amount) => balance.checked_add(amount),
+            Self::Sub(amount) => balance.checked_sub(amount),
+        }
+        .ok_or_else(|| {
+            anyhow!(
+                "Override of {} overflows its balance {}",
+                account_id,
+                balance
+            )
+        })
+    }
+}
+
+/// Overrides read from a file, in file order.
+pub struct Overrides(Vec<(AccountId, Change)>);
+
+impl Overrides {
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut overrides = Vec::new();
+        let mut seen = BTreeMap::new();
+        for (index, line) in content.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parse = || -> Result<(AccountId, Change)> {
+                let (address, balance) = line
+                    .split_once(',')
+                    .ok_or_else(|| anyhow!("expected `address,balance`"))?;
+                let account_id = address
+                    .trim()
+                    .parse::<AccountId>()
+                    .map_err(|err| anyhow!("invalid account {}: {:?}", address.trim(), err))?;
+                let balance = balance.trim();
+                let change = match balance.strip_prefix('+') {
+                    Some(amount) => Change::Add(amount.parse()?),
+                    None => match balance.strip_prefix('-') {
+                        Some(amount) => Change::Sub(amount.parse()?),
+                        None => Change::Set(balance.parse()?),
+                    },
+                };
+                Ok((account_id, change))
+            };
+            let (account_id, change) = parse()
+                .map_err(|err| anyhow!("Line {} of {}: {}", index + 1, path.display(), err))?;
+            if let Some(first) = seen.insert(account_id.clone(), index + 1) {
+                return Err(anyhow!(
+                    "Line {} of {}: {} is already overridden on line {}",
+                    index + 1,
+                    path.display(),
+                    account_id,
+                    first
+                ));
+            }
+            overrides.push((account_id, change));
+        }
+
+        Ok(Self(overrides))
+    }
+
+    /// Applies the overrides to `snapshot`, logging every one of them, and returns the
+    /// total balances before and after.
+    ///
+    /// Overriding an account absent from the snapshot fails unless `allow_new` is set,
+    /// it is then appended.
+    pub fn apply(
+        &self,
+        snapshot: &mut Snapshot,
+        allow_new: bool,
+        formatter: &BalanceFormatter,
+    ) -> Result<(Balance, Balance)> {
+        let index = snapshot
+            .iter()
+            .enumerate()
+            .map(|(i, Entry(account_id, _))| (account_id.clone(), i))
+            .collect::<BTreeMap<_, _>>();
+
+        let mut before: Balance = 0;
+        let mut after: Balance = 0;
+        for (account_id, change) in &self.0 {
+            let (old, new) = match index.get(account_id) {
+                Some(&i) => {
+                    let old = snapshot[i].1;
+                    let new = change.apply(account_id, old)?;
+                    snapshot[i].1 = new;
+                    (Some(old), new)
+                }
+                None if allow_new => {
+                    let new = change.apply(account_id, 0)?;
+                    snapshot.push(Entry(account_id.clone(), new));
+                    (None, new)
+                }
+                None => {
+                    return Err(anyhow!(
+                        "Override of {} targets an account absent from the snapshot, pass \
+                         --allow-new-overrides to add it",
+                        account_id
+                    ))
+                }
+            };
+            match old {
+                Some(old) => status!(
+                    "Override {}: {} -> {}",
+                    account_id,
+                    formatter.display(old),
+                    formatter.display(new)
+                ),
+                None => status!(
+                    "Override {}: new account with {}",
+                    account_id,
+                    formatter.display(new)
+                ),
+            }
+            let overflow = || anyhow!("Total of the overridden balances overflows");
+            before = before.checked_add(old.unwrap_or(0)).ok_or_else(overflow)?;
+            after = after.checked_add(new).ok_or_else(overflow)?;
+        }
+
+        Ok((before, after))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_are_checked() {
+        let account = |byte: u8| AccountId::new([byte; 32]);
+        let formatter = BalanceFormatter::default();
+
+        let mut snapshot = vec![Entry(account(1), 10), Entry(account(2), 20)];
+        let overrides = Overrides(vec![
+            (account(1), Change::Add(5)),
+            (account(2), Change::Set(1)),
+            (account(3), Change::Set(7)),
+        ]);
+        assert_eq!(
+            overrides.apply(&mut snapshot, true, &formatter).unwrap(),
+            (30, 13)
+        );
+
+        let mut snapshot = vec![Entry(account(1), 1), Entry(account(2), 2)];
+        let overrides = Overrides(vec![
+            (account(1), Change::Set(Balance::MAX)),
+            (account(2), Change::Set(1)),
+        ]);
+        assert!(overrides.apply(&mut snapshot, false, &formatter).is_err());
+    }
+}
@@ -0,0 +1,310 @@
+//! Single file bundle of a snapshot and its provenance files, see `--package`.
+//!
+//! The bundle is a gzipped tarball of plain files along with a `SHA256SUMS` listing
+//! the SHA-256 of every other file, in the format of `sha256sum`.
+
+use crate::{error, output, output_dir, Cli};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// Name of the checksum list in the bundle.
+const SUMS_NAME: &str = "SHA256SUMS";
+
+/// Writes `files` to the bundle at `path` under their file names, along with their
+/// checksums.
+pub fn write(path: &Path, files: &[PathBuf]) -> Result<()> {
+    let mut builder =
+        tar::Builder::new(GzEncoder::new(File::create(path)?, Compression::default()));
+    let mut sums = String::new();
+    for file in files {
+        let name = file
+            .file_name()
+            .ok_or_else(|| anyhow!("{} is not a file", file.display()))?
+            .to_string_lossy()
+            .into_owned();
+        sums.push_str(&format!("{}  {}\n", output::sha256_file(file)?, name));
+        builder.append_path_with_name(file, &name)?;
+    }
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(sums.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, SUMS_NAME, sums.as_bytes())?;
+
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Extracts the bundle at `path` into `dir` once every file checks out against
+/// `SHA256SUMS`, returning the names of the files extracted.
+///
+/// The files are extracted and checked in a staging directory under `dir`, then moved
+/// in place, so nothing is written to `dir` when the check fails. Existing files are
+/// only overwritten with `force`.
+pub fn unpack_verify(path: &Path, dir: &Path, force: bool) -> Result<Vec<String>> {
+    std::fs::create_dir_all(dir)?;
+    // On the same file system as `dir`, for the files to be moved by a rename.
+    let staging = dir.join(format!(".unpack-verify-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&staging);
+    std::fs::create_dir(&staging)?;
+
+    let unpacked = extract_verified(path, &staging).and_then(|names| {
+        for name in &names {
+            let target = dir.join(name);
+            if target.exists() && !force {
+                return Err(anyhow!(
+                    "{} already exists, pass --force to overwrite it",
+                    target.display()
+                ));
+            }
+        }
+        for name in &names {
+            std::fs::rename(staging.join(name), dir.join(name))?;
+        }
+        Ok(names)
+    });
+    std::fs::remove_dir_all(&staging)?;
+    unpacked
+}
+
+/// Extracts the bundle at `path` into the empty directory `dir` and checks every file
+/// against `SHA256SUMS`.
+fn extract_verified(path: &Path, dir: &Path) -> Result<Vec<String>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(File::open(path)?));
+    let mut names = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.into_owned();
+        // Only plain files at the top, nothing can land outside of `dir`.
+        let name = match (entry_path.file_name(), entry_path.parent()) {
+            (Some(name), Some(parent))
+                if parent.as_os_str().is_empty() && entry.header().entry_type().is_file() =>
+            {
+                name.to_string_lossy().into_owned()
+            }
+            _ => {
+                return Err(anyhow!(
+                    "Unexpected entry {} in {}",
+                    entry_path.display(),
+                    path.display()
+                ))
+            }
+        };
+        entry.unpack(dir.join(&name))?;
+        names.push(name);
+    }
+
+    if !names.iter().any(|name| name == SUMS_NAME) {
+        return Err(anyhow!("{} holds no {}", path.display(), SUMS_NAME));
+    }
+    let sums = std::fs::read_to_string(dir.join(SUMS_NAME))?
+        .lines()
+        .map(|line| {
+            line.split_once("  ")
+                .map(|(sha256, name)| (name.to_string(), sha256.to_string()))
+                .ok_or_else(|| anyhow!("Malformed {} line: {}", SUMS_NAME, line))
+        })
+        .collect::<Result<BTreeMap<_, _>>>()?;
+
+    let mut problems = Vec::new();
+    for name in names.iter().filter(|name| *name != SUMS_NAME) {
+        match sums.get(name) {
+            Some(expected) => {
+                let actual = output::sha256_file(&dir.join(name))?;
+                if &actual != expected {
+                    problems.push(format!(
+                        "{} has SHA-256 {} instead of {}",
+                        name, actual, expected
+                    ));
+                }
+            }
+            None => problems.push(format!("{} is not listed in {}", name, SUMS_NAME)),
+        }
+    }
+    for name in sums.keys().filter(|name| !names.contains(name)) {
+        problems.push(format!("{} is listed in {} but missing", name, SUMS_NAME));
+    }
+
+    for problem in &problems {
+        println!("{}", problem);
+    }
+    if !problems.is_empty() {
+        return Err(error::Error::Assertion(format!(
+            "{} fails verification with {} problems",
+            path.display(),
+            problems.len()
+        ))
+        .into());
+    }
+
+    Ok(names)
+}
+
+/// Extract a `--package` bundle and check its files against their checksums.
+#[derive(Parser, Debug, Serialize)]
+pub struct UnpackVerifyCmd {
+    /// The bundle to extract.
+    pub path: PathBuf,
+
+    /// Directory to extract the files into, `--out-dir` by default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl UnpackVerifyCmd {
+    pub fn run(self, cli: &Cli) -> Result<()> {
+        let dir = match self.output {
+            Some(dir) => dir,
+            None => output_dir(cli)?,
+        };
+        let names = unpack_verify(&self.path, &dir, cli.force)?;
+        for name in &names {
+            println!("{}", dir.join(name).display());
+        }
+        println!(
+            "Extracted {} files from {} into {}, all checksums match",
+            names.len(),
+            self.path.display(),
+            dir.display()
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns an empty scratch directory for the test `name`.
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("regenesis-package-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a bundle of the given entries, as they are.
+    fn write_raw(path: &Path, entries: &[(&str, &str)]) {
+        let mut builder = tar::Builder::new(GzEncoder::new(
+            File::create(path).unwrap(),
+            Compression::default(),
+        ));
+        for (name, data) in entries {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, name, data.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+    }
+
+    fn is_assertion(err: &anyhow::Error) -> bool {
+        matches!(
+            err.downcast_ref::<error::Error>(),
+            Some(error::Error::Assertion(_))
+        )
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = scratch_dir("round-trip");
+        let files = [
+            dir.join("balances_100.json"),
+            dir.join("balances_100.meta.json"),
+        ];
+        std::fs::write(&files[0], b"[]").unwrap();
+        std::fs::write(&files[1], b"{}").unwrap();
+        let bundle = dir.join("balances_100.tar.gz");
+        write(&bundle, &files).unwrap();
+
+        let unpacked = dir.join("unpacked");
+        let names = unpack_verify(&bundle, &unpacked, false).unwrap();
+        assert_eq!(
+            names,
+            ["balances_100.json", "balances_100.meta.json", SUMS_NAME]
+        );
+        for file in &files {
+            let name = file.file_name().unwrap();
+            assert_eq!(
+                std::fs::read(unpacked.join(name)).unwrap(),
+                std::fs::read(file).unwrap()
+            );
+        }
+
+        // Existing files are only overwritten with `force`.
+        std::fs::write(unpacked.join("balances_100.json"), b"[1]").unwrap();
+        assert!(unpack_verify(&bundle, &unpacked, false).is_err());
+        assert_eq!(
+            std::fs::read(unpacked.join("balances_100.json")).unwrap(),
+            b"[1]"
+        );
+        unpack_verify(&bundle, &unpacked, true).unwrap();
+        assert_eq!(
+            std::fs::read(unpacked.join("balances_100.json")).unwrap(),
+            b"[]"
+        );
+        assert_eq!(std::fs::read_dir(&unpacked).unwrap().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_bad_bundles() {
+        let dir = scratch_dir("bad");
+        let bundle = dir.join("bundle.tar.gz");
+        let sha256 = |data: &str| {
+            let path = dir.join("data");
+            std::fs::write(&path, data).unwrap();
+            output::sha256_file(&path).unwrap()
+        };
+
+        let tampered = format!("{}  a.json\n", sha256("[1]"));
+        write_raw(
+            &bundle,
+            &[("a.json", "[2]"), (SUMS_NAME, tampered.as_str())],
+        );
+        let err = unpack_verify(&bundle, &dir.join("tampered"), false).unwrap_err();
+        assert!(is_assertion(&err));
+        // Nothing is left behind by a failed check, not even the staging directory.
+        assert_eq!(std::fs::read_dir(dir.join("tampered")).unwrap().count(), 0);
+
+        let sums = format!("{}  a.json\n", sha256("[1]"));
+        write_raw(
+            &bundle,
+            &[
+                ("a.json", "[1]"),
+                ("b.json", "[]"),
+                (SUMS_NAME, sums.as_str()),
+            ],
+        );
+        let err = unpack_verify(&bundle, &dir.join("unlisted"), false).unwrap_err();
+        assert!(is_assertion(&err));
+
+        let sums = format!("{}  a.json\n{}  b.json\n", sha256("[1]"), sha256("[]"));
+        write_raw(&bundle, &[("a.json", "[1]"), (SUMS_NAME, sums.as_str())]);
+        let err = unpack_verify(&bundle, &dir.join("missing"), false).unwrap_err();
+        assert!(is_assertion(&err));
+
+        write_raw(&bundle, &[("a.json", "[1]")]);
+        assert!(unpack_verify(&bundle, &dir.join("no-sums"), false).is_err());
+
+        // Nothing lands outside of the target directory.
+        write_raw(&bundle, &[("nested/a.json", "[1]")]);
+        assert!(unpack_verify(&bundle, &dir.join("nested"), false).is_err());
+        assert!(!dir.join("nested").join("nested").exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
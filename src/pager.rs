@@ -0,0 +1,396 @@
+//! Paginated iteration over the account storage, `System::Account` by default.
+
+use crate::dynamic::AccountDecoder;
+use crate::ratelimit::RateLimiter;
+use crate::storage::{decode_account_entry, decode_asset_account, normalize_extra_flags};
+use crate::{AccountId, AccountInfo, Balance, BlockHash};
+use anyhow::{anyhow, Result};
+use codec::Decode;
+use futures::Stream;
+use std::borrow::BorrowMut;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use subxt::sp_core::storage::{StorageData, StorageKey};
+use subxt::{BasicError, Client, DefaultConfig};
+
+/// Number of accounts fetched per page.
+pub const PAGE_SIZE: u32 = 512;
+/// The page size is not halved below this on too large responses.
+const MIN_PAGE_SIZE: u32 = 8;
+/// Number of pages between two reads of the runtime version, see
+/// [`AccountPager::check_runtime`].
+pub const RUNTIME_CHECK_PAGES: u64 = 64;
+
+/// The runtime of the best block changed during the iteration.
+#[derive(Debug)]
+pub struct RuntimeUpgraded {
+    pub spec_version: u32,
+    pub new_spec_version: u32,
+    pub block_hash: BlockHash,
+}
+
+impl fmt::Display for RuntimeUpgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "runtime upgraded during snapshot from spec_version {} to {} while reading block \
+             {:?}; results invalid, please re-run at a fixed block hash selected with \
+             --block-hash",
+            self.spec_version, self.new_spec_version, self.block_hash
+        )
+    }
+}
+
+impl std::error::Error for RuntimeUpgraded {}
+
+/// Returns the key range of shard `index` out of `count` under `prefix`.
+///
+/// The keyspace is split on the first byte of the `blake2_128` hash following the
+/// storage prefix, the start is exclusive and the end is exclusive, `None` meaning
+/// unbounded.
+pub fn shard_range(
+    prefix: &[u8],
+    index: usize,
+    count: usize,
+) -> (Option<StorageKey>, Option<Vec<u8>>) {
+    let bound = |n: usize| {
+        let byte = n * 256 / count;
+        (byte < 256).then(|| {
+            let mut key = prefix.to_vec();
+            key.push(byte as u8);
+            key
+        })
+    };
+    let start = if index == 0 { None } else { bound(index) };
+    (start.map(StorageKey), bound(index + 1))
+}
+
+/// Counts the accounts under `prefix` at `block_hash` by paging through the storage
+/// keys only.
+pub async fn count_accounts(
+    client: &Client<DefaultConfig>,
+    prefix: &[u8],
+    block_hash: BlockHash,
+) -> Result<usize> {
+    let prefix = StorageKey(prefix.to_vec());
+    let mut start_key = None;
+    let mut count = 0;
+    loop {
+        let keys = client
+            .rpc()
+            .storage_keys_paged(Some(prefix.clone()), PAGE_SIZE, start_key, Some(block_hash))
+            .await?;
+        count += keys.len();
+        if keys.len() < PAGE_SIZE as usize {
+            return Ok(count);
+        }
+        start_key = keys.last().cloned();
+    }
+}
+
+/// Keys and values of a fetched page.
+struct Page {
+    /// Whether no key follows this page.
+    exhausted: bool,
+    last_key: Option<StorageKey>,
+    values: Vec<(StorageKey, StorageData)>,
+}
+
+/// Whether `err` is the node refusing a response exceeding its maximum size.
+fn is_response_too_large(err: &BasicError) -> bool {
+    let message = err.to_string().to_lowercase();
+    ["too large", "too big", "exceeded max limit"]
+        .iter()
+        .any(|pattern| message.contains(pattern))
+}
+
+/// A decoded account storage entry.
+pub struct AccountEntry {
+    pub key: StorageKey,
+    pub account_id: AccountId,
+    /// Free plus reserved balance.
+    pub total: Balance,
+    pub info: AccountInfo,
+    /// Whether the account is migrated to the newer balance model, if the runtime has
+    /// one.
+    pub migrated: Option<bool>,
+    /// The `ExtraFlags` of the newer layout, normalized away from `info`.
+    pub flags: Option<Balance>,
+}
+
+/// Iterator over the accounts at a fixed block, fetched page by page.
+pub struct AccountPager {
+    client: Client<DefaultConfig>,
+    block_hash: BlockHash,
+    prefix: StorageKey,
+    /// Last key fetched, the next page starts right after it.
+    start_key: Option<StorageKey>,
+    /// Keys from this one on are out of range.
+    end_key: Option<Vec<u8>>,
+    buffer: VecDeque<(StorageKey, AccountInfo)>,
+    exhausted: bool,
+    limiter: Option<Arc<RateLimiter>>,
+    /// Halved whenever a response is too large for the node.
+    page_size: u32,
+    skip_undecodable: bool,
+    /// Whether the values are `Assets::Account` entries rather than `AccountInfo`.
+    asset_accounts: bool,
+    /// Whether the `AccountData` has the newer layout with `flags`.
+    extra_flags: bool,
+    /// Decoder of the values from the node metadata, with `--dynamic`.
+    dynamic: Option<Arc<AccountDecoder>>,
+    /// Keys of the values skipped with `skip_undecodable`, with their decoding error.
+    skipped: Vec<(StorageKey, String)>,
+    /// `spec_version` the best block must keep, with `check_runtime`.
+    spec_version: Option<u32>,
+    /// Number of pages fetched so far.
+    pages: u64,
+}
+
+impl AccountPager {
+    pub fn with_range(
+        client: Client<DefaultConfig>,
+        prefix: &[u8],
+        block_hash: BlockHash,
+        start_key: Option<StorageKey>,
+        end_key: Option<Vec<u8>>,
+    ) -> Self {
+        Self {
+            client,
+            block_hash,
+            prefix: StorageKey(prefix.to_vec()),
+            start_key,
+            end_key,
+            buffer: VecDeque::new(),
+            exhausted: false,
+            limiter: None,
+            page_size: PAGE_SIZE,
+            skip_undecodable: false,
+            asset_accounts: false,
+            extra_flags: false,
+            dynamic: None,
+            skipped: Vec::new(),
+            spec_version: None,
+            pages: 0,
+        }
+    }
+
+    /// Decodes the values as `Assets::Account` entries, see [`decode_asset_account`].
+    pub fn asset_accounts(mut self) -> Self {
+        self.asset_accounts = true;
+        self
+    }
+
+    /// Reads the values with the newer `AccountData` layout, see
+    /// [`AccountStorage::has_extra_flags`](crate::storage::AccountStorage::has_extra_flags).
+    pub fn extra_flags(mut self) -> Self {
+        self.extra_flags = true;
+        self
+    }
+
+    /// Decodes the values with `decoder` instead of the generated `AccountInfo`.
+    pub fn dynamic(mut self, decoder: Arc<AccountDecoder>) -> Self {
+        self.dynamic = Some(decoder);
+        self
+    }
+
+    /// Skips the values failing to decode instead of failing, see [`Self::skipped`].
+    pub fn skip_undecodable(mut self) -> Self {
+        self.skip_undecodable = true;
+        self
+    }
+
+    /// Returns the keys of the values skipped so far, with their decoding error.
+    pub fn skipped(&self) -> &[(StorageKey, String)] {
+        &self.skipped
+    }
+
+    /// Re-reads the runtime version of the best block every [`RUNTIME_CHECK_PAGES`]
+    /// pages, failing with [`RuntimeUpgraded`] once it is no longer `spec_version`.
+    pub fn check_runtime(mut self, spec_version: u32) -> Self {
+        self.spec_version = Some(spec_version);
+        self
+    }
+
+    /// Paces the page fetches with `limiter`, possibly shared with other pagers.
+    pub fn with_rate_limit(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    pub async fn next(&mut self) -> Result<Option<(StorageKey, AccountInfo)>> {
+        loop {
+            if let Some(entry) = self.buffer.pop_front() {
+                return Ok(Some(entry));
+            }
+
+            if self.exhausted {
+                return Ok(None);
+            }
+
+            if let Some(spec_version) = self.spec_version {
+                if self.pages > 0 && self.pages % RUNTIME_CHECK_PAGES == 0 {
+                    let new_spec_version =
+                        self.client.rpc().runtime_version(None).await?.spec_version;
+                    if new_spec_version != spec_version {
+                        return Err(RuntimeUpgraded {
+                            spec_version,
+                            new_spec_version,
+                            block_hash: self.block_hash,
+                        }
+                        .into());
+                    }
+                }
+            }
+
+            let page = loop {
+                // Every attempt is paced, the retries with a smaller page included.
+                if let Some(limiter) = &self.limiter {
+                    limiter.acquire().await;
+                }
+                match self.fetch_page().await {
+                    Ok(page) => break page,
+                    Err(err) if self.page_size > MIN_PAGE_SIZE && is_response_too_large(&err) => {
+                        self.page_size = (self.page_size / 2).max(MIN_PAGE_SIZE);
+                        status!(
+                            "Page response too large ({}), retrying with {} accounts per page",
+                            err,
+                            self.page_size
+                        );
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            };
+
+            self.pages += 1;
+            self.exhausted = page.exhausted;
+
+            if page.last_key.is_none() {
+                return Ok(None);
+            }
+            self.start_key = page.last_key;
+
+            let mut values = page.values;
+
+            // Keep the storage order regardless of the order in the response.
+            values.sort_by(|a, b| a.0.cmp(&b.0));
+
+            for (key, data) in values {
+                let decoded = match &self.dynamic {
+                    Some(decoder) => decoder.decode(&data.0),
+                    None if self.asset_accounts => {
+                        decode_asset_account(&data.0).map_err(Into::into)
+                    }
+                    None => AccountInfo::decode(&mut data.0.as_slice()).map_err(Into::into),
+                };
+                match decoded {
+                    Ok(account) => self.buffer.push_back((key, account)),
+                    Err(err) if self.skip_undecodable => self.skipped.push((key, err.to_string())),
+                    Err(err) => {
+                        return Err(anyhow!(
+                            "Failed to decode the value of 0x{}: {}, pass --skip-undecodable to skip it",
+                            hex::encode(&key.0),
+                            err
+                        ))
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fetches the page of at most `page_size` keys following `start_key` along with
+    /// their values.
+    async fn fetch_page(&self) -> Result<Page, BasicError> {
+        let mut keys = self
+            .client
+            .rpc()
+            .storage_keys_paged(
+                Some(self.prefix.clone()),
+                self.page_size,
+                self.start_key.clone(),
+                Some(self.block_hash),
+            )
+            .await?;
+
+        let mut exhausted = keys.len() < self.page_size as usize;
+
+        if let Some(end_key) = &self.end_key {
+            if let Some(pos) = keys.iter().position(|key| &key.0 >= end_key) {
+                keys.truncate(pos);
+                exhausted = true;
+            }
+        }
+
+        if keys.is_empty() {
+            return Ok(Page {
+                exhausted: true,
+                last_key: None,
+                values: Vec::new(),
+            });
+        }
+
+        let change_sets = self
+            .client
+            .rpc()
+            .query_storage_at(&keys, Some(self.block_hash))
+            .await?;
+
+        let values = change_sets
+            .into_iter()
+            .flat_map(|change_set| change_set.changes)
+            .filter_map(|(key, maybe_data)| maybe_data.map(|data| (key, data)))
+            .collect();
+
+        Ok(Page {
+            exhausted,
+            last_key: keys.pop(),
+            values,
+        })
+    }
+
+    /// Returns the next account decoded, without any filtering.
+    pub async fn next_account(&mut self) -> Result<Option<AccountEntry>> {
+        match self.next().await? {
+            Some((key, info)) => {
+                account_entry(key, &self.prefix.0, info, self.extra_flags).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+/// Returns the accounts left in `pager` as a stream, decoded by
+/// [`AccountPager::next_account`] without any filtering.
+///
+/// The pager is taken by value or by mutable reference, to read e.g.
+/// [`AccountPager::skipped`] once the stream is over.
+pub fn account_entries<P: BorrowMut<AccountPager>>(
+    pager: P,
+) -> impl Stream<Item = Result<AccountEntry>> {
+    futures::stream::try_unfold(pager, |mut pager| async move {
+        let entry = pager.borrow_mut().next_account().await?;
+        Ok(entry.map(|entry| (entry, pager)))
+    })
+}
+
+/// Decodes the account of `key` under `prefix`, `extra_flags` telling whether `info`
+/// has the newer `AccountData` layout.
+pub fn account_entry(
+    key: StorageKey,
+    prefix: &[u8],
+    mut info: AccountInfo,
+    extra_flags: bool,
+) -> Result<AccountEntry> {
+    let flags = extra_flags.then(|| info.data.fee_frozen);
+    let migrated = extra_flags.then(|| normalize_extra_flags(&mut info.data));
+    let (account_id, total) = decode_account_entry(&key.0, prefix, &info)?;
+    Ok(AccountEntry {
+        key,
+        account_id,
+        total,
+        info,
+        migrated,
+        flags,
+    })
+}
@@ -0,0 +1,56 @@
+//! Preview of a snapshot run.
+
+use crate::{account_prefix, expected_issuance, output_path, pager, special_accounts, Cli, Target};
+use anyhow::Result;
+
+/// Prints what a snapshot run with the same options would do.
+///
+/// Only the total issuance and the storage keys of the accounts are read, the account
+/// values are not fetched.
+pub async fn run(cli: &Cli, target: &Target) -> Result<()> {
+    let total_issuance = expected_issuance(cli, &target.clients[0], target.block_hash).await?;
+
+    let prefix = account_prefix(cli, &target.clients[0])?;
+    let accounts = pager::count_accounts(&target.clients[0], &prefix, target.block_hash).await?;
+
+    let (endowed, token_grants) = special_accounts()?;
+
+    let formatter = cli.formatter();
+
+    println!("Nodes: {}", target.urls.join(", "));
+    println!(
+        "Block: #{} ({:?}), spec_version {}",
+        target.block_number, target.block_hash, target.runtime_version.spec_version
+    );
+    println!("Total issuance: {}", formatter.display(total_issuance));
+    println!("Accounts: {}", accounts);
+    println!(
+        "Skipped: up to {} endowed and {} token grant accounts",
+        endowed.len(),
+        token_grants.len()
+    );
+    println!("Balance kind: {:?}", cli.balance_kind);
+    if cli.balance_min.is_some() || cli.balance_max.is_some() {
+        println!(
+            "Balance range: {} ..= {}",
+            cli.balance_min.unwrap_or(0),
+            cli.balance_max
+                .map_or_else(|| "unbounded".to_string(), |max| max.to_string())
+        );
+    }
+    if let Some(expr) = &cli.balance_expr {
+        println!("Balance expression: {}", serde_json::to_string(expr)?);
+    }
+    if let Some(base_path) = &cli.append {
+        println!("Append to: {} ({:?})", base_path.display(), cli.append_mode);
+    }
+    println!("Format: {:?}", cli.format);
+    let path = output_path(cli, target.block_number, target.block_hash)?;
+    println!("Output: {}", path.display());
+    println!(
+        "Metadata: {}",
+        crate::snapshot::metadata_path(&path).display()
+    );
+
+    Ok(())
+}
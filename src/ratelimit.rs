@@ -0,0 +1,42 @@
+//! Global pacing of the storage page fetches.
+
+use anyhow::{anyhow, Result};
+use tokio::sync::Mutex;
+use tokio::time::{sleep_until, Duration, Instant};
+
+/// Token bucket holding a single token, refilled every `interval`.
+///
+/// Shared by all the pagers of a run, so that concurrent shards respect the rate
+/// together rather than each on its own.
+#[derive(Debug)]
+pub struct RateLimiter {
+    interval: Duration,
+    /// When the next token becomes available.
+    next: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: f64) -> Result<Self> {
+        if !(per_sec.is_finite() && per_sec > 0.0) {
+            return Err(anyhow!(
+                "Rate limit must be a positive number, got {}",
+                per_sec
+            ));
+        }
+        Ok(Self {
+            interval: Duration::from_secs_f64(1.0 / per_sec),
+            next: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Waits until a token is available and takes it.
+    pub async fn acquire(&self) {
+        // The lock is held while sleeping, the waiters are served in turn.
+        let mut next = self.next.lock().await;
+        let now = Instant::now();
+        if *next > now {
+            sleep_until(*next).await;
+        }
+        *next = (*next).max(now) + self.interval;
+    }
+}
@@ -0,0 +1,117 @@
+//! Snapshot as raw account storage writes, see `--format raw-top` and `--format kv`.
+
+use crate::snapshot::Entry;
+use crate::storage::{
+    account_key, decode_account_entry, encode_account_value, normalize_extra_flags,
+};
+use crate::{output, AccountId, AccountInfo, Balance};
+use anyhow::{anyhow, Result};
+use codec::Decode;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// SCALE encoded account storage values of the accounts of a snapshot.
+#[derive(Default)]
+pub struct RawValues(BTreeMap<AccountId, Vec<u8>>);
+
+impl RawValues {
+    /// Encodes `info` as read from the chain, and checks that the value decodes back to
+    /// the same account.
+    pub fn insert(
+        &mut self,
+        account_id: &AccountId,
+        info: &AccountInfo,
+        flags: Option<Balance>,
+    ) -> Result<()> {
+        let value = encode_account_value(info, flags);
+
+        let mut decoded = AccountInfo::decode(&mut value.as_slice())?;
+        if flags.is_some() {
+            normalize_extra_flags(&mut decoded.data);
+        }
+        let fields = |info: &AccountInfo| {
+            (
+                info.nonce,
+                info.consumers,
+                info.providers,
+                info.sufficients,
+                info.data.free,
+                info.data.reserved,
+                info.data.misc_frozen,
+                info.data.fee_frozen,
+            )
+        };
+        if fields(&decoded) != fields(info) {
+            return Err(anyhow!(
+                "Encoded account {} decodes back to {:?} instead of {:?}",
+                account_id,
+                fields(&decoded),
+                fields(info)
+            ));
+        }
+
+        self.0.insert(account_id.clone(), value);
+        Ok(())
+    }
+
+    /// Returns the `(key, value)` storage writes of `entries` under the account storage
+    /// `prefix`, sorted by key, checking that every key decodes back to its account.
+    pub fn storage_writes(
+        &self,
+        prefix: &[u8],
+        entries: &[Entry],
+    ) -> Result<BTreeMap<Vec<u8>, &[u8]>> {
+        entries
+            .iter()
+            .map(|Entry(account_id, _)| {
+                let value = self
+                    .0
+                    .get(account_id)
+                    .ok_or_else(|| anyhow!("No on-chain account to write for {}", account_id))?;
+                let key = account_key(prefix, account_id);
+                let info = AccountInfo::decode(&mut value.as_slice())?;
+                let (decoded, _) = decode_account_entry(&key, prefix, &info)?;
+                if &decoded != account_id {
+                    return Err(anyhow!(
+                        "Storage key 0x{} decodes to {} instead of {}",
+                        hex::encode(&key),
+                        decoded,
+                        account_id
+                    ));
+                }
+                Ok((key, value.as_slice()))
+            })
+            .collect()
+    }
+}
+
+/// Writes the storage writes as a JSON object of `0x` prefixed hex key to value, the
+/// shape of the `genesis.raw.top` section of a raw chain spec.
+pub fn write_top<W: Write>(
+    mut writer: W,
+    writes: &BTreeMap<Vec<u8>, &[u8]>,
+    indent: usize,
+) -> Result<()> {
+    let top = writes
+        .iter()
+        .map(|(key, value)| {
+            (
+                format!("0x{}", hex::encode(key)),
+                format!("0x{}", hex::encode(value)),
+            )
+        })
+        .collect::<BTreeMap<_, _>>();
+    output::write_json(&mut writer, &top, indent)?;
+    writeln!(writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Writes the storage writes as `0x<key>=0x<value>` hex lines, sorted by key.
+pub fn write_kv<W: Write>(mut writer: W, writes: &BTreeMap<Vec<u8>, &[u8]>) -> Result<()> {
+    for (key, value) in writes {
+        writeln!(writer, "0x{}=0x{}", hex::encode(key), hex::encode(value))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
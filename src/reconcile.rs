@@ -0,0 +1,132 @@
+//! Reconciliation of a snapshot against the genesis of the new chain.
+
+use crate::{error, snapshot, AccountId, Balance};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Check that a chain spec endows every account of a snapshot with its balance.
+#[derive(Parser, Debug, Serialize)]
+pub struct ReconcileCmd {
+    /// The snapshot the genesis was built from.
+    pub snapshot: PathBuf,
+
+    /// The non-raw chain spec of the new chain.
+    pub chain_spec: PathBuf,
+
+    /// Snapshot of the accounts added to the genesis separately, e.g. the token grants
+    /// and the endowed accounts, their balances add up with those of `snapshot`.
+    #[clap(long)]
+    pub extras: Option<PathBuf>,
+}
+
+#[derive(Deserialize)]
+struct ChainSpec {
+    genesis: Genesis,
+}
+
+#[derive(Deserialize)]
+struct Genesis {
+    runtime: Runtime,
+}
+
+#[derive(Deserialize)]
+struct Runtime {
+    balances: BalancesConfig,
+}
+
+#[derive(Deserialize)]
+struct BalancesConfig {
+    balances: Vec<(AccountId, Balance)>,
+}
+
+impl ReconcileCmd {
+    pub fn run(self) -> Result<()> {
+        let mut expected = snapshot::load_map(&self.snapshot)?;
+        if let Some(extras) = &self.extras {
+            for (account_id, balance) in snapshot::load_map(extras)? {
+                let entry = expected.entry(account_id).or_default();
+                *entry = entry
+                    .checked_add(balance)
+                    .ok_or_else(|| anyhow!("Expected balance overflows"))?;
+            }
+        }
+
+        let file = std::fs::File::open(&self.chain_spec).map_err(|err| {
+            anyhow!(
+                "Failed to open chain spec {}: {}",
+                self.chain_spec.display(),
+                err
+            )
+        })?;
+        let chain_spec: ChainSpec = serde_json::from_reader(std::io::BufReader::new(file))
+            .map_err(|err| {
+                anyhow!(
+                    "Failed to parse the balances of chain spec {}: {}",
+                    self.chain_spec.display(),
+                    err
+                )
+            })?;
+
+        let mut genesis = BTreeMap::new();
+        for (account_id, balance) in chain_spec.genesis.runtime.balances.balances {
+            if genesis.insert(account_id.clone(), balance).is_some() {
+                return Err(anyhow!(
+                    "Account {} is endowed more than once in {}",
+                    account_id,
+                    self.chain_spec.display()
+                ));
+            }
+        }
+
+        let mut missing = 0;
+        let mut mismatched = 0;
+        for (account_id, &balance) in &expected {
+            match genesis.get(account_id) {
+                None => {
+                    println!("missing {} {}", account_id, balance);
+                    missing += 1;
+                }
+                Some(&genesis_balance) if genesis_balance != balance => {
+                    println!(
+                        "mismatched {} {} != {}",
+                        account_id, genesis_balance, balance
+                    );
+                    mismatched += 1;
+                }
+                Some(_) => {}
+            }
+        }
+
+        let mut extra = 0;
+        for (account_id, balance) in &genesis {
+            if !expected.contains_key(account_id) {
+                println!("extra {} {}", account_id, balance);
+                extra += 1;
+            }
+        }
+
+        println!(
+            "Expected: {}, genesis: {}, missing: {}, extra: {}, mismatched: {}",
+            expected.len(),
+            genesis.len(),
+            missing,
+            extra,
+            mismatched
+        );
+
+        if missing + extra + mismatched > 0 {
+            return Err(error::Error::Assertion(format!(
+                "genesis of {} does not match the snapshot",
+                self.chain_spec.display()
+            ))
+            .into());
+        }
+
+        println!("Genesis matches the snapshot");
+
+        Ok(())
+    }
+}
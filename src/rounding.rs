@@ -0,0 +1,37 @@
+//! Rounding of the exported balances to a multiple of a unit.
+
+use crate::Balance;
+use clap::ArgEnum;
+use serde::Serialize;
+
+/// Direction of `--round-to`.
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Rounding {
+    /// Round down to the multiple of the unit below.
+    Down,
+    /// Round to the nearest multiple of the unit, halves are rounded up.
+    Nearest,
+}
+
+/// What becomes of the amounts removed by `--round-to`.
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Remainder {
+    /// The net remainder is credited to `--dust-account`.
+    Collect,
+    /// The net remainder is left out of the snapshot.
+    Burn,
+}
+
+impl Rounding {
+    /// Rounds `balance` to a multiple of `unit`, `None` on overflow.
+    pub fn round(self, balance: Balance, unit: Balance) -> Option<Balance> {
+        let rem = balance.checked_rem(unit)?;
+        match self {
+            Self::Down => Some(balance - rem),
+            Self::Nearest if rem >= unit - unit / 2 => (balance - rem).checked_add(unit),
+            Self::Nearest => Some(balance - rem),
+        }
+    }
+}
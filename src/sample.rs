@@ -0,0 +1,153 @@
+//! Balance weighted random sample of the new accounts, see `--sample-weighted`.
+
+use crate::snapshot::Entry;
+use crate::{AccountId, Balance};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::Serialize;
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+#[derive(Debug, Serialize)]
+pub struct Sampled {
+    pub account: AccountId,
+    pub balance: Balance,
+    /// Share of the total balance of the new accounts, the weight the account was
+    /// sampled with.
+    pub weight: f64,
+}
+
+/// A-Res key of an entry, `ln(u) / weight`, the entries with the largest keys make up
+/// the sample.
+struct Key(f64, usize);
+
+impl PartialEq for Key {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Key {}
+
+impl PartialOrd for Key {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Key {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+/// Picks up to `count` of `entries` without replacement, each with a probability
+/// proportional to its balance, deterministically for a given `seed`.
+///
+/// This is the A-Res reservoir algorithm of Efraimidis and Spirakis, the keys are
+/// taken as logarithms so that the large balances don't underflow them. Accounts with
+/// a zero balance are never picked. The sample is in decreasing balance order.
+pub fn weighted(entries: &[Entry], count: usize, seed: u64) -> Vec<Sampled> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut reservoir = BinaryHeap::with_capacity(count + 1);
+    for (index, Entry(_, balance)) in entries.iter().enumerate() {
+        if *balance == 0 || count == 0 {
+            continue;
+        }
+        // In (0, 1], so that the logarithm is finite.
+        let u = 1.0 - rng.gen::<f64>();
+        let key = Key(u.ln() / *balance as f64, index);
+        if reservoir.len() < count {
+            reservoir.push(Reverse(key));
+        } else if reservoir.peek().map_or(false, |Reverse(min)| key > *min) {
+            reservoir.pop();
+            reservoir.push(Reverse(key));
+        }
+    }
+
+    let total = entries
+        .iter()
+        .map(|Entry(_, balance)| *balance as f64)
+        .sum::<f64>();
+    let mut sample = reservoir
+        .into_iter()
+        .map(|Reverse(Key(_, index))| {
+            let Entry(account, balance) = &entries[index];
+            Sampled {
+                account: account.clone(),
+                balance: *balance,
+                weight: *balance as f64 / total,
+            }
+        })
+        .collect::<Vec<_>>();
+    sample.sort_by(|a, b| {
+        b.balance
+            .cmp(&a.balance)
+            .then_with(|| a.account.cmp(&b.account))
+    });
+    sample
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(balances: &[Balance]) -> Vec<Entry> {
+        balances
+            .iter()
+            .enumerate()
+            .map(|(i, balance)| Entry(AccountId::new([i as u8; 32]), *balance))
+            .collect()
+    }
+
+    fn pairs(sample: &[Sampled]) -> Vec<(AccountId, Balance)> {
+        sample
+            .iter()
+            .map(|sampled| (sampled.account.clone(), sampled.balance))
+            .collect()
+    }
+
+    #[test]
+    fn deterministic_for_a_seed() {
+        let entries = entries(&[5, 1, 0, 7, 3, 9, 2]);
+        assert_eq!(
+            pairs(&weighted(&entries, 3, 42)),
+            pairs(&weighted(&entries, 3, 42))
+        );
+    }
+
+    #[test]
+    fn never_picks_zero_balances() {
+        let entries = entries(&[5, 0, 1, 0]);
+        let sample = weighted(&entries, entries.len(), 42);
+        assert_eq!(pairs(&sample).len(), 2);
+        assert!(sample.iter().all(|sampled| sampled.balance > 0));
+        assert!(weighted(&entries, 0, 42).is_empty());
+        assert!(weighted(&[], 3, 42).is_empty());
+    }
+
+    #[test]
+    fn ordered_with_their_share() {
+        let entries = entries(&[1, 4, 2, 3]);
+        let sample = weighted(&entries, entries.len(), 7);
+        assert_eq!(
+            sample
+                .iter()
+                .map(|sampled| sampled.balance)
+                .collect::<Vec<_>>(),
+            [4, 3, 2, 1]
+        );
+        assert!((sample[0].weight - 0.4).abs() < 1e-12);
+    }
+
+    #[test]
+    fn favours_large_balances() {
+        let mut balances = vec![1; 10];
+        balances.push(1_000_000);
+        let entries = entries(&balances);
+        let picked = (0..100)
+            .filter(|seed| weighted(&entries, 1, *seed)[0].balance == 1_000_000)
+            .count();
+        assert!(picked >= 95, "picked {} times out of 100", picked);
+    }
+}
@@ -0,0 +1,36 @@
+//! JSON schema of the snapshot formats.
+
+use crate::snapshot::{Metadata, Snapshot, FORMAT_VERSION};
+use anyhow::Result;
+use clap::Parser;
+use schemars::schema_for;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Print the JSON schema of the snapshot formats.
+#[derive(Parser, Debug, Serialize)]
+pub struct SchemaCmd {
+    /// Write the schema to the file instead of printing it.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl SchemaCmd {
+    pub fn run(self) -> Result<()> {
+        let schema = serde_json::json!({
+            "format_version": FORMAT_VERSION,
+            "snapshot": schema_for!(Snapshot),
+            "metadata": schema_for!(Metadata),
+        });
+
+        match self.output {
+            Some(path) => {
+                serde_json::to_writer_pretty(std::fs::File::create(&path)?, &schema)?;
+                println!("Schema has been written to {}", path.display());
+            }
+            None => println!("{}", serde_json::to_string_pretty(&schema)?),
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Search of the block holding a given event.
+
+use crate::{block_hash_at, BlockHash, BlockNumber};
+use anyhow::{anyhow, Error, Result};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use subxt::sp_core::hashing::twox_128;
+use subxt::sp_core::storage::StorageKey;
+use subxt::{Client, DefaultConfig, EventsDecoder};
+
+/// An event name, written as `Pallet.Event`.
+#[derive(Debug, Clone)]
+pub struct EventName {
+    pallet: String,
+    variant: String,
+}
+
+impl FromStr for EventName {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('.') {
+            Some((pallet, variant)) if !pallet.is_empty() && !variant.is_empty() => Ok(Self {
+                pallet: pallet.to_string(),
+                variant: variant.to_string(),
+            }),
+            _ => Err(anyhow!("Expected `Pallet.Event`, got `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for EventName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.pallet, self.variant)
+    }
+}
+
+impl Serialize for EventName {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Returns the first block holding `event` in the `window` blocks from `start` on, or
+/// up to `start` when searching `backward`, `start` included.
+pub async fn find_event(
+    client: &Client<DefaultConfig>,
+    event: &EventName,
+    start: BlockNumber,
+    window: BlockNumber,
+    backward: bool,
+) -> Result<(BlockNumber, BlockHash)> {
+    let decoder = EventsDecoder::<DefaultConfig>::new(client.metadata().clone());
+    let key = StorageKey([twox_128(b"System"), twox_128(b"Events")].concat());
+
+    let block_numbers: Box<dyn Iterator<Item = BlockNumber>> = if backward {
+        Box::new((start.saturating_sub(window)..=start).rev())
+    } else {
+        Box::new(start..=start.saturating_add(window))
+    };
+
+    for block_number in block_numbers {
+        let block_hash = block_hash_at(client, block_number).await?;
+        let data = match client.rpc().storage(&key, Some(block_hash)).await? {
+            Some(data) => data,
+            None => continue,
+        };
+        let events = decoder.decode_events(&mut data.0.as_slice())?;
+        if events
+            .iter()
+            .any(|(_, raw)| raw.pallet == event.pallet && raw.variant == event.variant)
+        {
+            return Ok((block_number, block_hash));
+        }
+    }
+
+    Err(anyhow!(
+        "Event {} not found within {} blocks {} block #{}",
+        event,
+        window,
+        if backward { "before" } else { "after" },
+        start
+    ))
+}
@@ -0,0 +1,71 @@
+//! End-to-end smoke test against a local development node.
+
+use crate::{connect, output_path, snapshot, Cli};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use subxt::sp_core::{crypto::AccountId32 as AccountId, sr25519, Pair};
+
+/// Runs a snapshot of the dev node at `url` into a temporary directory and checks the
+/// result can be read back.
+pub async fn run(url: &str) -> Result<()> {
+    let dir = std::env::temp_dir().join(format!("regenesis-selftest-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+
+    match check(url, &dir).await {
+        Ok(()) => {
+            std::fs::remove_dir_all(&dir)?;
+            println!("Self-test passed");
+            Ok(())
+        }
+        Err(err) => {
+            println!("Self-test failed, output kept in {}", dir.display());
+            Err(err)
+        }
+    }
+}
+
+async fn check(url: &str, dir: &std::path::Path) -> Result<()> {
+    let out_dir = dir.to_string_lossy().into_owned();
+    let cli = Cli::try_parse_from([
+        "subspace-regenesis-tool",
+        "--url",
+        url,
+        "--out-dir",
+        out_dir.as_str(),
+    ])?;
+
+    let target = connect(&cli).await?;
+
+    for seed in ["//Alice", "//Bob"] {
+        let account_id: AccountId = sr25519::Pair::from_string(seed, None)
+            .expect("Could not generate a key pair")
+            .public()
+            .into();
+        let account = target
+            .api
+            .storage()
+            .system()
+            .account(&account_id, Some(target.block_hash))
+            .await?;
+        if account.data.free == 0 {
+            return Err(anyhow!(
+                "Dev account {} ({}) has no balance, is this a dev node?",
+                seed,
+                account_id
+            ));
+        }
+    }
+
+    // Fails unless the issuance check passes.
+    snapshot(&cli, &target).await?;
+
+    let path = output_path(&cli, target.block_number, target.block_hash)?;
+    let entries = crate::snapshot::load(&path)?;
+    println!(
+        "Read back {} accounts from {}",
+        entries.len(),
+        path.display()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,208 @@
+//! Merge of snapshots taken separately over parts of the account keyspace.
+
+use crate::snapshot::{self, Entry, Snapshot};
+use crate::storage::account_storage_key;
+use crate::{error, output, output_dir, AccountId, Balance, BlockHash, BlockNumber, Cli};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Merge shard snapshots of the same block into a single snapshot, checking that no
+/// account appears in several of them.
+#[derive(Parser, Debug, Serialize)]
+pub struct ReconcileShardsCmd {
+    /// The shard snapshots, in keyspace order.
+    #[clap(required = true, min_values = 2)]
+    pub shards: Vec<PathBuf>,
+
+    /// Path of the merged snapshot, `balances_{block_number}.json` in `--out-dir` by
+    /// default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct ShardReport {
+    path: PathBuf,
+    accounts: usize,
+    total: Balance,
+}
+
+/// Written next to the merged snapshot as `{name}.shards.json`.
+#[derive(Debug, Serialize)]
+struct Report {
+    block_number: Option<BlockNumber>,
+    block_hash: Option<BlockHash>,
+    shards: Vec<ShardReport>,
+    accounts: usize,
+    total: Balance,
+}
+
+fn total(entries: &[Entry]) -> Result<Balance> {
+    entries
+        .iter()
+        .try_fold(0 as Balance, |total, Entry(_, balance)| {
+            total.checked_add(*balance)
+        })
+        .ok_or_else(|| anyhow!("Total balance overflows"))
+}
+
+impl ReconcileShardsCmd {
+    pub fn run(self, cli: &Cli) -> Result<()> {
+        let mut block: Option<(BlockNumber, BlockHash)> = None;
+        let mut shards = Vec::with_capacity(self.shards.len());
+        for path in &self.shards {
+            match snapshot::load_metadata(path)? {
+                Some(metadata) => {
+                    match block {
+                        Some((_, block_hash)) if block_hash != metadata.block_hash => {
+                            return Err(anyhow!(
+                                "{} was taken at block {:?} but {} at block {:?}",
+                                path.display(),
+                                metadata.block_hash,
+                                self.shards[0].display(),
+                                block_hash
+                            ))
+                        }
+                        Some(_) => {}
+                        None => block = Some((metadata.block_number, metadata.block_hash)),
+                    }
+                    let entries = snapshot::load(path)?;
+                    if let Some(expected) = metadata.total {
+                        let actual = total(&entries)?;
+                        if actual != expected {
+                            return Err(anyhow!(
+                                "{} holds a total of {} but its metadata records {}",
+                                path.display(),
+                                actual,
+                                expected
+                            ));
+                        }
+                    }
+                    shards.push(entries);
+                }
+                None if !cli.force => {
+                    return Err(anyhow!(
+                    "No metadata found for {}, pass --force to merge without checking its block",
+                    path.display()
+                ))
+                }
+                None => shards.push(snapshot::load(path)?),
+            }
+        }
+
+        // Shards cut from the keyspace cover disjoint ranges of storage keys.
+        let ranges = shards
+            .iter()
+            .map(|entries| {
+                let keys = entries
+                    .iter()
+                    .map(|Entry(account_id, _)| account_storage_key(account_id));
+                (keys.clone().min(), keys.max())
+            })
+            .collect::<Vec<_>>();
+        for (i, a) in ranges.iter().enumerate() {
+            for (j, b) in ranges.iter().enumerate().skip(i + 1) {
+                if let ((Some(a_min), Some(a_max)), (Some(b_min), Some(b_max))) = (a, b) {
+                    if a_min <= b_max && b_min <= a_max {
+                        status!(
+                            "Warning: {} and {} cover overlapping key ranges",
+                            self.shards[i].display(),
+                            self.shards[j].display()
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut owners = BTreeMap::<&AccountId, usize>::new();
+        let mut duplicates = Vec::new();
+        let mut reports = Vec::with_capacity(shards.len());
+        for (i, entries) in shards.iter().enumerate() {
+            for Entry(account_id, _) in entries {
+                if let Some(first) = owners.insert(account_id, i) {
+                    duplicates.push((account_id, first, i));
+                }
+            }
+            reports.push(ShardReport {
+                path: self.shards[i].clone(),
+                accounts: entries.len(),
+                total: total(entries)?,
+            });
+        }
+
+        for (account_id, first, second) in &duplicates {
+            println!(
+                "{} is in both {} and {}",
+                account_id,
+                self.shards[*first].display(),
+                self.shards[*second].display()
+            );
+        }
+        if !duplicates.is_empty() {
+            return Err(error::Error::Assertion(format!(
+                "{} accounts appear in several shards",
+                duplicates.len()
+            ))
+            .into());
+        }
+
+        let merged: Snapshot = shards.into_iter().flatten().collect();
+        let report = Report {
+            block_number: block.map(|(block_number, _)| block_number),
+            block_hash: block.map(|(_, block_hash)| block_hash),
+            accounts: merged.len(),
+            total: reports
+                .iter()
+                .try_fold(0 as Balance, |sum, shard| sum.checked_add(shard.total))
+                .ok_or_else(|| anyhow!("Total balance overflows"))?,
+            shards: reports,
+        };
+
+        let path = match (self.output, block) {
+            (Some(path), _) => path,
+            (None, Some((block_number, _))) => {
+                output_dir(cli)?.join(format!("balances_{}.json", block_number))
+            }
+            (None, None) => {
+                return Err(anyhow!(
+                    "The block of the shards is unknown without metadata, pass --output"
+                ))
+            }
+        };
+        if path.exists() && !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ));
+        }
+
+        let mut writer = output::FileWriter::create(&path)?;
+        output::write_json(&mut writer, &merged, cli.json_indent)?;
+        writer.finish(cli.fsync)?;
+
+        let report_path = path.with_extension("shards.json");
+        serde_json::to_writer_pretty(std::fs::File::create(&report_path)?, &report)?;
+
+        let formatter = cli.formatter();
+        for shard in &report.shards {
+            println!(
+                "{}: {} accounts, total {}",
+                shard.path.display(),
+                shard.accounts,
+                formatter.display(shard.total)
+            );
+        }
+        println!(
+            "Merged {} accounts, total {}, into {} (report in {})",
+            report.accounts,
+            formatter.display(report.total),
+            path.display(),
+            report_path.display()
+        );
+
+        Ok(())
+    }
+}
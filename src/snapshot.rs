@@ -0,0 +1,532 @@
+//! Snapshot file handling.
+
+use crate::{binary, canonical, ndjson, split, AccountId, Balance, BlockHash, BlockNumber, Header};
+use anyhow::{anyhow, Context, Result};
+use clap::ArgEnum;
+use codec::Encode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Seek};
+use std::path::{Path, PathBuf};
+use subxt::sp_core::crypto::Ss58Codec;
+use subxt::sp_core::hashing::blake2_256;
+use subxt::sp_runtime::traits::Header as HeaderT;
+
+/// Version of the snapshot formats, bumped whenever they change.
+pub const FORMAT_VERSION: u32 = 5;
+
+/// A snapshot entry, serialized as `[account_id, balance]`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Entry(
+    /// SS58 encoded account id.
+    #[schemars(with = "String")]
+    pub AccountId,
+    pub Balance,
+);
+
+/// Balances snapshot as it is written to `balances_{block_number}.json`.
+pub type Snapshot = Vec<Entry>;
+
+/// Verbose snapshot entry of `--format debug-json`, meant for manual analysis only.
+#[derive(Debug, Serialize)]
+pub struct DebugEntry {
+    /// Account id encoded with `--ss58-prefix`.
+    pub ss58: String,
+    /// `0x` prefixed hex encoded account id.
+    pub hex: String,
+    pub balance: Balance,
+}
+
+impl DebugEntry {
+    pub fn new(Entry(account_id, balance): &Entry, ss58_prefix: u16) -> Self {
+        Self {
+            ss58: account_id.to_ss58check_with_version(ss58_prefix.into()),
+            hex: format!("0x{}", hex::encode(account_id)),
+            balance: *balance,
+        }
+    }
+}
+
+/// Provenance of a snapshot, written next to it as `balances_{block_number}.meta.json`.
+///
+/// Together with the options the snapshot can be reproduced from its own record.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Metadata {
+    pub tool_version: String,
+    pub spec_version: u32,
+    pub block_number: BlockNumber,
+    #[schemars(with = "String")]
+    pub block_hash: BlockHash,
+    /// All the resolved command line options.
+    pub options: serde_json::Value,
+    /// Where the iteration of the account storage stopped, absent before it ran.
+    #[serde(default)]
+    pub resume: Option<ResumeToken>,
+    /// Hex encoded SHA-256 of the snapshot file, absent before it is written.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    /// Short line identifying the snapshot, see [`fingerprint`].
+    #[serde(default)]
+    pub fingerprint: Option<String>,
+    /// Hex encoded Merkle root of the accounts, see [`merkle_root`], absent before they
+    /// are collected.
+    #[serde(default)]
+    pub merkle_root: Option<String>,
+    /// Whether some accounts are missing from the snapshot, see `skipped_undecodable`.
+    #[serde(default)]
+    pub partial: bool,
+    /// Number of account values skipped by `--skip-undecodable`.
+    #[serde(default)]
+    pub skipped_undecodable: usize,
+    /// Number of accounts in the snapshot, absent before it is written.
+    #[serde(default)]
+    pub accounts: Option<usize>,
+    /// Sum of the balances in the snapshot, absent before it is written.
+    #[serde(default)]
+    pub total: Option<Balance>,
+}
+
+/// Returns a short line identifying a snapshot, e.g. for comparing runs over a ticket:
+/// the block number, the short block hash, the account count and the start of the
+/// SHA-256 of the file.
+pub fn fingerprint(
+    block_number: BlockNumber,
+    block_hash: BlockHash,
+    accounts: usize,
+    sha256: &str,
+) -> String {
+    format!(
+        "#{} 0x{} {} accounts sha256:{}",
+        block_number,
+        hex::encode(&block_hash.as_bytes()[..4]),
+        accounts,
+        &sha256[..8]
+    )
+}
+
+/// Returns the Merkle root of `entries`, in their order.
+///
+/// The leaves are the `blake2_256` of the account id followed by the little endian
+/// `u128` balance, each parent the `blake2_256` of its two children, the last node of
+/// an odd level being carried up as is. The root of no entry is all zeros.
+pub fn merkle_root(entries: &[Entry]) -> [u8; 32] {
+    let mut level = entries
+        .iter()
+        .map(|Entry(account_id, balance)| {
+            let account_id: &[u8] = account_id.as_ref();
+            blake2_256(&[account_id, &balance.to_le_bytes()[..]].concat())
+        })
+        .collect::<Vec<_>>();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => blake2_256(&[*left, *right].concat()),
+                [single] => *single,
+                _ => unreachable!("Chunks of at most 2 nodes; qed"),
+            })
+            .collect();
+    }
+    level.first().copied().unwrap_or_default()
+}
+
+/// Last `System::Account` storage key processed by a run, from which a follow-up run
+/// can continue.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ResumeToken {
+    /// `0x` prefixed hex encoded full storage key.
+    pub last_key: String,
+    /// Block the key was read at, a follow-up run must query the same block.
+    #[schemars(with = "String")]
+    pub block_hash: BlockHash,
+}
+
+/// Header of the snapshot block, written as `header_{block_number}.json` so that the
+/// block can be confirmed independently.
+#[derive(Debug, Serialize)]
+pub struct HeaderExport {
+    pub number: BlockNumber,
+    pub hash: BlockHash,
+    pub parent_hash: BlockHash,
+    pub state_root: BlockHash,
+    pub extrinsics_root: BlockHash,
+    /// `0x` prefixed hex of the SCALE encoded header, digest included.
+    pub encoded: String,
+}
+
+impl HeaderExport {
+    pub fn new(header: &Header) -> Self {
+        Self {
+            number: *header.number(),
+            hash: header.hash(),
+            parent_hash: *header.parent_hash(),
+            state_root: *header.state_root(),
+            extrinsics_root: *header.extrinsics_root(),
+            encoded: format!("0x{}", hex::encode(header.encode())),
+        }
+    }
+}
+
+/// Last extensions of the snapshot files, left out of the names of their sidecar files.
+const SNAPSHOT_EXTENSIONS: &[&str] = &["json", "ndjson", "bin", "kv", "parquet"];
+
+/// Returns the path of the file with `extension` belonging to the snapshot at `path`,
+/// named after the file name of the snapshot without its snapshot extension, e.g.
+/// `snap-1.2.meta.json` for both `snap-1.2.json` and `snap-1.2`.
+fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let stem = match name.rsplit_once('.') {
+        Some((stem, last)) if SNAPSHOT_EXTENSIONS.contains(&last) => stem,
+        _ => name.as_str(),
+    };
+    path.with_file_name(format!("{}.{}", stem, extension))
+}
+
+/// Returns the path of the metadata file belonging to the snapshot at `path`.
+pub fn metadata_path(path: &Path) -> PathBuf {
+    sidecar_path(path, "meta.json")
+}
+
+/// Returns the path of the index file belonging to the snapshot at `path`.
+pub fn index_path(path: &Path) -> PathBuf {
+    sidecar_path(path, "idx")
+}
+
+/// Replaces every account id with `blake2_256(salt ++ account_id)`, keeping the
+/// balances and the order.
+pub fn redact(snapshot: Snapshot, salt: &[u8]) -> Snapshot {
+    snapshot
+        .into_iter()
+        .map(|Entry(account_id, balance)| {
+            let account_id: &[u8] = account_id.as_ref();
+            Entry(
+                AccountId::new(blake2_256(&[salt, account_id].concat())),
+                balance,
+            )
+        })
+        .collect()
+}
+
+/// Reads the metadata of the snapshot at `path`, if any.
+pub fn load_metadata(path: &Path) -> Result<Option<Metadata>> {
+    let metadata_path = metadata_path(path);
+    if !metadata_path.exists() {
+        return Ok(None);
+    }
+    let file = std::fs::File::open(&metadata_path)?;
+    let metadata = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to parse {}", metadata_path.display()))?;
+    Ok(Some(metadata))
+}
+
+/// Reads a snapshot previously written by this tool, either JSON, NDJSON or binary.
+pub fn load(path: &Path) -> Result<Snapshot> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+
+    let mut magic = [0u8; 4];
+    let is_binary = file.read_exact(&mut magic).is_ok() && magic == binary::MAGIC;
+    file.rewind()?;
+
+    if path
+        .to_str()
+        .map_or(false, |path| path.ends_with(".canonical.json"))
+    {
+        return canonical::read(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()));
+    }
+
+    if split::is_manifest(path) {
+        return split::read(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()));
+    }
+
+    if path
+        .extension()
+        .map_or(false, |extension| extension == "ndjson")
+    {
+        return ndjson::read(path)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()));
+    }
+
+    if is_binary {
+        let len = file.metadata()?.len();
+        let (_, snapshot) = binary::read(std::io::BufReader::new(file), len)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        return Ok(snapshot);
+    }
+
+    let snapshot = serde_json::from_reader(std::io::BufReader::new(file))
+        .with_context(|| format!("Failed to parse snapshot {}", path.display()))?;
+    Ok(snapshot)
+}
+
+/// Reads the entries of a snapshot one at a time, in file order, see [`load`].
+///
+/// JSON, NDJSON and binary snapshots are streamed, the canonical and split ones, which
+/// are not in storage order anyway, are read in full first.
+pub fn entries(path: &Path) -> Result<Box<dyn Iterator<Item = Result<Entry>>>> {
+    if path
+        .to_str()
+        .map_or(false, |path| path.ends_with(".canonical.json"))
+        || split::is_manifest(path)
+    {
+        return Ok(Box::new(
+            load(path)?.into_iter().map(Ok::<_, anyhow::Error>),
+        ));
+    }
+
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("Failed to open snapshot {}", path.display()))?;
+    let mut magic = [0u8; 4];
+    let is_binary = file.read_exact(&mut magic).is_ok() && magic == binary::MAGIC;
+    file.rewind()?;
+
+    let entries: Box<dyn Iterator<Item = Result<Entry>>> = if path
+        .extension()
+        .map_or(false, |extension| extension == "ndjson")
+    {
+        Box::new(ndjson::entries(path)?)
+    } else if is_binary {
+        let len = file.metadata()?.len();
+        let (_, records) = binary::records(BufReader::new(file), len)
+            .with_context(|| format!("Failed to read snapshot {}", path.display()))?;
+        Box::new(records)
+    } else {
+        Box::new(JsonEntries {
+            reader: BufReader::new(file),
+            started: false,
+            done: false,
+        })
+    };
+    let path = path.to_path_buf();
+    Ok(Box::new(entries.map(move |entry| {
+        entry.with_context(|| format!("Failed to read snapshot {}", path.display()))
+    })))
+}
+
+/// Entries of a JSON snapshot, parsed one at a time.
+struct JsonEntries<R> {
+    reader: R,
+    /// Whether the opening bracket has been read.
+    started: bool,
+    done: bool,
+}
+
+impl<R: BufRead> JsonEntries<R> {
+    /// Skips the whitespace, then returns the next byte without consuming it.
+    fn peek(&mut self) -> Result<Option<u8>> {
+        loop {
+            let byte = match self.reader.fill_buf()?.first() {
+                Some(&byte) => byte,
+                None => return Ok(None),
+            };
+            if !byte.is_ascii_whitespace() {
+                return Ok(Some(byte));
+            }
+            self.reader.consume(1);
+        }
+    }
+
+    /// Skips the whitespace, then consumes the next byte.
+    fn next_byte(&mut self) -> Result<Option<u8>> {
+        let byte = self.peek()?;
+        if byte.is_some() {
+            self.reader.consume(1);
+        }
+        Ok(byte)
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry>> {
+        if !self.started {
+            self.started = true;
+            if self.next_byte()? != Some(b'[') {
+                return Err(anyhow!("Expected a JSON array of entries"));
+            }
+            if self.peek()? == Some(b']') {
+                self.reader.consume(1);
+                return self.end();
+            }
+        } else {
+            match self.next_byte()? {
+                Some(b',') => {}
+                Some(b']') => return self.end(),
+                _ => return Err(anyhow!("Expected `,` or `]` after an entry")),
+            }
+        }
+        // An entry ends with its closing bracket, nothing past it is read.
+        let entry =
+            Entry::deserialize(&mut serde_json::Deserializer::from_reader(&mut self.reader))?;
+        Ok(Some(entry))
+    }
+
+    fn end(&mut self) -> Result<Option<Entry>> {
+        if self.next_byte()?.is_some() {
+            return Err(anyhow!("Trailing characters after the JSON array"));
+        }
+        Ok(None)
+    }
+}
+
+impl<R: BufRead> Iterator for JsonEntries<R> {
+    type Item = Result<Entry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        match self.next_entry() {
+            Ok(Some(entry)) => Some(Ok(entry)),
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// Reads a snapshot into a map keyed by account, duplicate accounts are rejected.
+pub fn load_map(path: &Path) -> Result<BTreeMap<AccountId, Balance>> {
+    let mut map = BTreeMap::new();
+    for Entry(account_id, balance) in load(path)? {
+        if map.insert(account_id.clone(), balance).is_some() {
+            return Err(anyhow!(
+                "Account {} appears more than once in {}",
+                account_id,
+                path.display()
+            ));
+        }
+    }
+    Ok(map)
+}
+
+/// How to combine an account present in both snapshots when appending.
+#[derive(ArgEnum, Clone, Copy, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppendMode {
+    /// The newly queried balance replaces the existing one.
+    Replace,
+    /// The newly queried balance is added to the existing one.
+    Add,
+}
+
+/// Merges `new` into `base`, the accounts of `base` keep their position and the
+/// accounts only in `new` are appended in order.
+pub fn merge(mut base: Snapshot, new: Snapshot, mode: AppendMode) -> Result<Snapshot> {
+    let mut index = base
+        .iter()
+        .enumerate()
+        .map(|(i, Entry(account_id, _))| (account_id.clone(), i))
+        .collect::<BTreeMap<_, _>>();
+
+    for Entry(account_id, balance) in new {
+        match index.get(&account_id) {
+            Some(&i) => {
+                let existing = &mut base[i].1;
+                *existing = match mode {
+                    AppendMode::Replace => balance,
+                    AppendMode::Add => existing.checked_add(balance).ok_or_else(|| {
+                        anyhow!("Balance of {} overflows when appending", account_id)
+                    })?,
+                };
+            }
+            None => {
+                index.insert(account_id.clone(), base.len());
+                base.push(Entry(account_id, balance));
+            }
+        }
+    }
+
+    Ok(base)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecar_paths() {
+        let dir = Path::new("out");
+        for (snapshot, metadata) in [
+            ("balances_100.json", "balances_100.meta.json"),
+            (
+                "balances_100.canonical.json",
+                "balances_100.canonical.meta.json",
+            ),
+            ("balances_100.ndjson", "balances_100.meta.json"),
+            ("snap-1.2.json", "snap-1.2.meta.json"),
+            ("snap-1.2", "snap-1.2.meta.json"),
+            ("snap", "snap.meta.json"),
+        ] {
+            assert_eq!(metadata_path(&dir.join(snapshot)), dir.join(metadata));
+        }
+        assert_eq!(
+            index_path(&dir.join("snap-1.2.bin")),
+            dir.join("snap-1.2.idx")
+        );
+    }
+
+    fn json_entries(json: &str) -> Result<Vec<Entry>> {
+        JsonEntries {
+            reader: json.as_bytes(),
+            started: false,
+            done: false,
+        }
+        .collect()
+    }
+
+    #[test]
+    fn json_entries_are_streamed() {
+        let entries = (1..=3u8)
+            .map(|byte| Entry(AccountId::new([byte; 32]), u128::from(byte) << 100))
+            .collect::<Snapshot>();
+        for indent in [0, 2] {
+            let mut json = Vec::new();
+            crate::output::write_json(&mut json, &entries, indent).unwrap();
+            let json = String::from_utf8(json).unwrap();
+            let read = json_entries(&format!("{}\n", json)).unwrap();
+            assert_eq!(
+                serde_json::to_string(&read).unwrap(),
+                serde_json::to_string(&entries).unwrap()
+            );
+        }
+
+        assert!(json_entries(" [ ] ").unwrap().is_empty());
+        assert!(json_entries("").is_err());
+        assert!(json_entries("{}").is_err());
+        assert!(json_entries("[] []").is_err());
+        assert!(json_entries("[[\"x\", 1]]").is_err());
+        let address = AccountId::new([1; 32]).to_ss58check();
+        assert!(json_entries(&format!("[[\"{}\", 1] [\"{}\", 2]]", address, address)).is_err());
+        assert!(json_entries(&format!("[[\"{}\", 1],", address)).is_err());
+    }
+
+    #[test]
+    fn merkle_root_of_odd_levels() {
+        let entries = (1..=3u8)
+            .map(|byte| Entry(AccountId::new([byte; 32]), u128::from(byte)))
+            .collect::<Snapshot>();
+        let leaf = |Entry(account_id, balance): &Entry| {
+            let account_id: &[u8] = account_id.as_ref();
+            blake2_256(&[account_id, &balance.to_le_bytes()[..]].concat())
+        };
+        let leaves = entries.iter().map(leaf).collect::<Vec<_>>();
+
+        assert_eq!(merkle_root(&[]), [0; 32]);
+        assert_eq!(merkle_root(&entries[..1]), leaves[0]);
+        // The third leaf is carried up to pair with the parent of the first two.
+        let parent = blake2_256(&[leaves[0], leaves[1]].concat());
+        assert_eq!(
+            merkle_root(&entries),
+            blake2_256(&[parent, leaves[2]].concat())
+        );
+    }
+}
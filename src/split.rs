@@ -0,0 +1,193 @@
+//! Snapshot split across several JSON files, listed by a manifest.
+
+use crate::output::{self, FileWriter};
+use crate::snapshot::{Entry, Snapshot};
+use crate::{AccountId, BlockHash, BlockNumber};
+use anyhow::{anyhow, Error, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Size in bytes with an optional `K`, `M` or `G` binary suffix, e.g. `512M`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ByteSize(pub u64);
+
+impl FromStr for ByteSize {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (digits, shift) = match s.char_indices().last() {
+            Some((i, 'K' | 'k')) => (&s[..i], 10),
+            Some((i, 'M' | 'm')) => (&s[..i], 20),
+            Some((i, 'G' | 'g')) => (&s[..i], 30),
+            _ => (s, 0),
+        };
+        let size = digits
+            .parse::<u64>()
+            .ok()
+            .and_then(|size| size.checked_mul(1 << shift))
+            .filter(|&size| size > 0)
+            .ok_or_else(|| anyhow!("Invalid size {}, expected e.g. 1048576, 512K or 64M", s))?;
+        Ok(Self(size))
+    }
+}
+
+/// How to cut the snapshot into parts.
+#[derive(Clone, Copy, Debug)]
+pub enum Split {
+    /// At most this many accounts per part.
+    Count(usize),
+    /// At most this many bytes per part, unless a single account exceeds it.
+    Bytes(u64),
+}
+
+/// Part of a split snapshot as listed in the manifest.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Part {
+    /// File name of the part, relative to the manifest.
+    pub file: String,
+    pub accounts: usize,
+    /// Smallest account id of the part.
+    pub first: AccountId,
+    /// Largest account id of the part.
+    pub last: AccountId,
+    pub bytes: u64,
+    pub sha256: String,
+}
+
+/// Manifest written to `balances_{block_number}.manifest.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub block_number: BlockNumber,
+    pub block_hash: BlockHash,
+    pub accounts: usize,
+    pub parts: Vec<Part>,
+}
+
+/// Returns the path of the manifest of the snapshot at `path`.
+pub fn manifest_path(path: &Path) -> PathBuf {
+    path.with_extension("manifest.json")
+}
+
+/// Returns whether `path` names a manifest rather than a snapshot.
+pub fn is_manifest(path: &Path) -> bool {
+    path.to_str()
+        .map_or(false, |path| path.ends_with(".manifest.json"))
+}
+
+/// Size of `entry` in an array indented by `indent`, with its indentation and
+/// separator.
+fn entry_size(entry: &Entry, indent: usize) -> Result<u64> {
+    let mut json = Vec::new();
+    output::write_json(&mut json, entry, indent)?;
+    let lines = json.split(|&byte| byte == b'\n').count();
+    Ok((json.len() + indent * lines + 2) as u64)
+}
+
+/// Sorts `entries` by account id and writes them as self-contained JSON arrays next
+/// to `path`, then writes the manifest listing them.
+pub fn write(
+    path: &Path,
+    mut entries: Snapshot,
+    split: Split,
+    block_number: BlockNumber,
+    block_hash: BlockHash,
+    json_indent: usize,
+    fsync: bool,
+) -> Result<Manifest> {
+    entries.sort_by(|Entry(a, _), Entry(b, _)| a.cmp(b));
+
+    let mut chunks = Vec::new();
+    match split {
+        Split::Count(count) => chunks.extend(entries.chunks(count)),
+        Split::Bytes(limit) => {
+            // The brackets of the array.
+            const OVERHEAD: u64 = 3;
+            let mut start = 0;
+            let mut size = OVERHEAD;
+            for (i, entry) in entries.iter().enumerate() {
+                let entry_size = entry_size(entry, json_indent)?;
+                if size + entry_size > limit && i > start {
+                    chunks.push(&entries[start..i]);
+                    start = i;
+                    size = OVERHEAD;
+                }
+                size += entry_size;
+            }
+            if start < entries.len() {
+                chunks.push(&entries[start..]);
+            }
+        }
+    }
+
+    let mut parts = Vec::with_capacity(chunks.len());
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        let part_path = path.with_extension(format!("part{}.json", i));
+        let mut writer = FileWriter::create(&part_path)?;
+        output::write_json(&mut writer, chunk, json_indent)?;
+        let bytes = writer.finish(fsync)?;
+        parts.push(Part {
+            file: part_path
+                .file_name()
+                .expect("Part path is built from a file name; qed")
+                .to_string_lossy()
+                .into_owned(),
+            accounts: chunk.len(),
+            first: chunk[0].0.clone(),
+            last: chunk[chunk.len() - 1].0.clone(),
+            bytes,
+            sha256: output::sha256_file(&part_path)?,
+        });
+    }
+
+    let manifest = Manifest {
+        block_number,
+        block_hash,
+        accounts: entries.len(),
+        parts,
+    };
+    let mut writer = FileWriter::create(&manifest_path(path))?;
+    serde_json::to_writer_pretty(&mut writer, &manifest)?;
+    writer.finish(fsync)?;
+
+    Ok(manifest)
+}
+
+/// Reads back the parts listed by the manifest at `path`, checking their digests.
+pub fn read(path: &Path) -> Result<Snapshot> {
+    let manifest: Manifest =
+        serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut snapshot = Snapshot::with_capacity(manifest.accounts);
+    for part in &manifest.parts {
+        let part_path = dir.join(&part.file);
+        if output::sha256_file(&part_path)? != part.sha256 {
+            return Err(anyhow!(
+                "{} does not match its digest in the manifest",
+                part_path.display()
+            ));
+        }
+        let entries: Snapshot =
+            serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(&part_path)?))?;
+        if entries.len() != part.accounts {
+            return Err(anyhow!(
+                "{} holds {} accounts instead of {}",
+                part_path.display(),
+                entries.len(),
+                part.accounts
+            ));
+        }
+        snapshot.extend(entries);
+    }
+
+    if snapshot.len() != manifest.accounts {
+        return Err(anyhow!(
+            "Parts hold {} accounts instead of {}",
+            snapshot.len(),
+            manifest.accounts
+        ));
+    }
+
+    Ok(snapshot)
+}
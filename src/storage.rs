@@ -0,0 +1,522 @@
+//! Raw account storage encoding, `System::Account` unless `--account-storage` says otherwise.
+
+use crate::subspace::runtime_types::{frame_system, pallet_balances::AccountData};
+use crate::{AccountId, AccountInfo, Balance, BlockHash, BlockNumber};
+use anyhow::{anyhow, Context, Error, Result};
+use codec::{Decode, Encode};
+use frame_metadata::{RuntimeMetadataV14, StorageEntryMetadata, StorageEntryType, StorageHasher};
+use scale_info::{form::PortableForm, TypeDef};
+use serde::{Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+use subxt::sp_core::hashing::{blake2_128, twox_128};
+use subxt::sp_core::storage::StorageKey;
+use subxt::{Client, DefaultConfig};
+
+/// Length of the `blake2_128` hash of the account id in the key.
+const BLAKE_HASH_LEN: usize = 16;
+/// Length of the account id.
+const ACCOUNT_ID_LEN: usize = 32;
+
+/// Returns the storage prefix of `System::Account`.
+pub fn account_storage_prefix() -> Vec<u8> {
+    [twox_128(b"System"), twox_128(b"Account")].concat()
+}
+
+/// A storage map of `AccountInfo` values keyed by `blake2_128_concat(account_id)`,
+/// written as `Pallet.Item`.
+#[derive(Debug, Clone)]
+pub struct AccountStorage {
+    pallet: String,
+    item: String,
+}
+
+impl AccountStorage {
+    /// Resolves the storage entry through the metadata and returns its prefix.
+    ///
+    /// The entry must be a map with a single `Blake2_128Concat` hasher, its values are
+    /// expected to decode as `AccountInfo`.
+    pub fn resolve(&self, client: &Client<DefaultConfig>) -> Result<Vec<u8>> {
+        let metadata = client.metadata();
+        let (prefix, entry) = self.entry(metadata.runtime_metadata())?;
+
+        match &entry.ty {
+            StorageEntryType::Map { hashers, .. }
+                if matches!(hashers.as_slice(), [StorageHasher::Blake2_128Concat]) => {}
+            StorageEntryType::Map { .. } => {
+                return Err(anyhow!(
+                    "Storage {} is not keyed by a single Blake2_128Concat hasher",
+                    self
+                ))
+            }
+            StorageEntryType::Plain(_) => return Err(anyhow!("Storage {} is not a map", self)),
+        }
+
+        Ok([twox_128(prefix.as_bytes()), twox_128(self.item.as_bytes())].concat())
+    }
+
+    /// Returns whether the `AccountData` of the values carries the `flags` field of the
+    /// newer balance model, where `reserved` is followed by a single `frozen` balance and
+    /// the `ExtraFlags`.
+    ///
+    /// Both layouts have the same size, so the generated `AccountInfo` decodes either
+    /// way, with `misc_frozen` holding `frozen` and `fee_frozen` holding the flags.
+    pub fn has_extra_flags(&self, client: &Client<DefaultConfig>) -> Result<bool> {
+        let metadata = client.metadata();
+        let runtime = metadata.runtime_metadata();
+        let value = self.value_type(runtime)?;
+
+        let field = |id: u32, name: &str| match runtime.types.resolve(id)?.type_def() {
+            TypeDef::Composite(composite) => composite
+                .fields()
+                .iter()
+                .find(|field| field.name().map_or(false, |field| field == name))
+                .map(|field| field.ty().id()),
+            _ => None,
+        };
+
+        Ok(field(value, "data")
+            .and_then(|data| field(data, "flags"))
+            .is_some())
+    }
+
+    /// Returns the id of the value type of the map in the type registry.
+    pub fn value_type(&self, runtime: &RuntimeMetadataV14) -> Result<u32> {
+        let (_, entry) = self.entry(runtime)?;
+        match &entry.ty {
+            StorageEntryType::Map { value, .. } => Ok(value.id()),
+            StorageEntryType::Plain(_) => Err(anyhow!("Storage {} is not a map", self)),
+        }
+    }
+
+    /// Returns the storage prefix of the pallet and the metadata of the entry.
+    fn entry<'a>(
+        &self,
+        runtime: &'a RuntimeMetadataV14,
+    ) -> Result<(&'a str, &'a StorageEntryMetadata<PortableForm>)> {
+        let storage = runtime
+            .pallets
+            .iter()
+            .find(|pallet| pallet.name == self.pallet)
+            .and_then(|pallet| pallet.storage.as_ref())
+            .ok_or_else(|| anyhow!("Pallet {} has no storage in the metadata", self.pallet))?;
+
+        let entry = storage
+            .entries
+            .iter()
+            .find(|entry| entry.name == self.item)
+            .ok_or_else(|| anyhow!("Storage {} not found in the metadata", self))?;
+
+        Ok((&storage.prefix, entry))
+    }
+}
+
+impl FromStr for AccountStorage {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('.') {
+            Some((pallet, item)) if !pallet.is_empty() && !item.is_empty() => Ok(Self {
+                pallet: pallet.to_string(),
+                item: item.to_string(),
+            }),
+            _ => Err(anyhow!("Expected `Pallet.Item`, got `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for AccountStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.pallet, self.item)
+    }
+}
+
+impl Serialize for AccountStorage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Returns the final storage key of `System::Account` for `account_id`.
+///
+/// `twox128("System") ++ twox128("Account") ++ blake2_128(account_id) ++ account_id`
+pub fn account_storage_key(account_id: &AccountId) -> Vec<u8> {
+    account_key(&account_storage_prefix(), account_id)
+}
+
+/// Returns the storage key of `account_id` in the account storage at `prefix`.
+pub fn account_key(prefix: &[u8], account_id: &AccountId) -> Vec<u8> {
+    let account_id: &[u8] = account_id.as_ref();
+    let mut key = prefix.to_vec();
+    key.extend(blake2_128(account_id));
+    key.extend(account_id);
+    key
+}
+
+/// Returns the SCALE encoded `AccountInfo` of an account with a `free` balance.
+///
+/// Only the free balance is replaced: the nonce, the reference counters and the
+/// reserved and frozen balances are kept from the `existing` value of the account, as
+/// well as the `ExtraFlags` of the newer layout, and are those of a newly created
+/// account if it has none.
+pub fn encode_account_info(existing: Option<&AccountInfo>, free: Balance) -> Vec<u8> {
+    let mut info = fresh_account_info(free);
+    if let Some(existing) = existing {
+        info.nonce = existing.nonce;
+        info.consumers = existing.consumers;
+        info.providers = existing.providers;
+        info.sufficients = existing.sufficients;
+        info.data.reserved = existing.data.reserved;
+        info.data.misc_frozen = existing.data.misc_frozen;
+        info.data.fee_frozen = existing.data.fee_frozen;
+    }
+    info.encode()
+}
+
+fn fresh_account_info(free: Balance) -> AccountInfo {
+    frame_system::AccountInfo {
+        nonce: 0u32,
+        consumers: 0,
+        providers: 1,
+        sufficients: 0,
+        data: AccountData {
+            free,
+            reserved: 0,
+            misc_frozen: 0,
+            fee_frozen: 0,
+        },
+    }
+}
+
+/// Returns the prefix of the `Assets::Account` entries of `asset_id`, a double map
+/// keyed by `blake2_128_concat(asset_id) ++ blake2_128_concat(account_id)`.
+///
+/// The asset ids are expected to be `u32`, as in most runtimes.
+pub fn resolve_asset_account(client: &Client<DefaultConfig>, asset_id: u32) -> Result<Vec<u8>> {
+    let entry = client
+        .metadata()
+        .pallet("Assets")
+        .and_then(|pallet| pallet.storage("Account"))
+        .map_err(|_| anyhow!("Runtime has no Assets::Account storage"))?;
+
+    match &entry.ty {
+        StorageEntryType::Map { hashers, .. }
+            if matches!(
+                hashers.as_slice(),
+                [
+                    StorageHasher::Blake2_128Concat,
+                    StorageHasher::Blake2_128Concat
+                ]
+            ) => {}
+        _ => {
+            return Err(anyhow!(
+                "Storage Assets.Account is not a double map keyed by Blake2_128Concat hashers"
+            ))
+        }
+    }
+
+    let encoded_id = asset_id.encode();
+    Ok([
+        &twox_128(b"Assets")[..],
+        &twox_128(b"Account"),
+        &blake2_128(&encoded_id),
+        &encoded_id,
+    ]
+    .concat())
+}
+
+/// Decodes an `Assets::Account` value into the `AccountInfo` of a fresh account whose
+/// free balance is the asset balance.
+///
+/// Only the leading balance is decoded, the rest of `AssetAccount` differs between the
+/// versions of the pallet.
+pub fn decode_asset_account(mut value: &[u8]) -> Result<AccountInfo, codec::Error> {
+    Ok(fresh_account_info(Balance::decode(&mut value)?))
+}
+
+/// The parts of `Assets::Asset` needed to check an asset snapshot.
+pub struct AssetDetails {
+    pub supply: Balance,
+    pub min_balance: Balance,
+}
+
+/// Reads the supply and the minimum balance of `asset_id` from `Assets::Asset`.
+pub async fn asset_details(
+    client: &Client<DefaultConfig>,
+    block_hash: BlockHash,
+    asset_id: u32,
+) -> Result<AssetDetails> {
+    let encoded_id = asset_id.encode();
+    let key = StorageKey(
+        [
+            &twox_128(b"Assets")[..],
+            &twox_128(b"Asset"),
+            &blake2_128(&encoded_id),
+            &encoded_id,
+        ]
+        .concat(),
+    );
+
+    let data = client
+        .rpc()
+        .storage(&key, Some(block_hash))
+        .await?
+        .ok_or_else(|| anyhow!("Asset {} not found at block {:?}", asset_id, block_hash))?;
+
+    // `AssetDetails` starts with the owner, issuer, admin and freezer accounts, then the
+    // supply, the deposit and the minimum balance.
+    let mut value = data
+        .0
+        .get(4 * ACCOUNT_ID_LEN..)
+        .ok_or_else(|| anyhow!("Malformed Assets::Asset value of asset {}", asset_id))?;
+    let supply = Balance::decode(&mut value)?;
+    let _deposit = Balance::decode(&mut value)?;
+    let min_balance = Balance::decode(&mut value)?;
+
+    Ok(AssetDetails {
+        supply,
+        min_balance,
+    })
+}
+
+/// Decodes an entry of the account storage at `prefix` into the account id and its
+/// total (free plus reserved) balance.
+pub fn decode_account_entry(
+    key: &[u8],
+    prefix: &[u8],
+    account: &AccountInfo,
+) -> Result<(AccountId, Balance)> {
+    if key.len() != prefix.len() + BLAKE_HASH_LEN + ACCOUNT_ID_LEN || !key.starts_with(prefix) {
+        return Err(anyhow!(
+            "Malformed account storage key 0x{}",
+            hex::encode(key)
+        ));
+    }
+
+    let (hash, raw_account_id) = key[prefix.len()..].split_at(BLAKE_HASH_LEN);
+
+    if hash != blake2_128(raw_account_id) {
+        return Err(anyhow!(
+            "Account hash mismatch in account storage key 0x{}",
+            hex::encode(key)
+        ));
+    }
+
+    let account_id = AccountId::new(
+        raw_account_id
+            .try_into()
+            .expect("Length of the account id is checked above; qed"),
+    );
+
+    let total = account
+        .data
+        .free
+        .checked_add(account.data.reserved)
+        .ok_or_else(|| anyhow!("Total balance of {} overflows", account_id))?;
+
+    Ok((account_id, total))
+}
+
+/// The bit of `ExtraFlags` set once an account is migrated to the newer balance model.
+const IS_NEW_LOGIC: u128 = 0x8000_0000_0000_0000_0000_0000_0000_0000;
+
+/// Rewrites an `AccountData` decoded from the newer layout, see
+/// [`AccountStorage::has_extra_flags`], into the older one and returns whether the
+/// account is migrated to the newer balance model.
+pub fn normalize_extra_flags(data: &mut AccountData) -> bool {
+    let flags = data.fee_frozen;
+    data.fee_frozen = data.misc_frozen;
+    flags & IS_NEW_LOGIC != 0
+}
+
+/// Returns the SCALE encoded value of `info` in the account storage, restoring the
+/// `flags` of the newer layout normalized away by [`normalize_extra_flags`].
+pub fn encode_account_value(info: &AccountInfo, flags: Option<Balance>) -> Vec<u8> {
+    frame_system::AccountInfo {
+        nonce: info.nonce,
+        consumers: info.consumers,
+        providers: info.providers,
+        sufficients: info.sufficients,
+        data: AccountData {
+            free: info.data.free,
+            reserved: info.data.reserved,
+            misc_frozen: info.data.misc_frozen,
+            fee_frozen: flags.unwrap_or(info.data.fee_frozen),
+        },
+    }
+    .encode()
+}
+
+/// Reads `Balances::InactiveIssuance`, `None` if the runtime does not track it.
+pub async fn inactive_issuance(
+    client: &Client<DefaultConfig>,
+    block_hash: BlockHash,
+) -> Result<Option<Balance>> {
+    let tracked = client
+        .metadata()
+        .pallet("Balances")
+        .and_then(|pallet| pallet.storage("InactiveIssuance"))
+        .is_ok();
+
+    if !tracked {
+        return Ok(None);
+    }
+
+    let key = StorageKey([twox_128(b"Balances"), twox_128(b"InactiveIssuance")].concat());
+
+    let inactive = match client.rpc().storage(&key, Some(block_hash)).await? {
+        Some(data) => Balance::decode(&mut data.0.as_slice())?,
+        None => 0,
+    };
+
+    Ok(Some(inactive))
+}
+
+/// Checks with a single small read that the node still has the state at `block_hash`,
+/// pruned nodes discard it past a few hundred blocks.
+pub async fn probe_state(
+    client: &Client<DefaultConfig>,
+    url: &str,
+    block_number: BlockNumber,
+    block_hash: BlockHash,
+) -> Result<()> {
+    let key = StorageKey([twox_128(b"System"), twox_128(b"Number")].concat());
+    client
+        .rpc()
+        .storage(&key, Some(block_hash))
+        .await
+        .with_context(|| {
+            format!(
+                "State at block #{} is not available on {}\n\
+                 Snapshotting the full state needs an archive node, started with \
+                 `--pruning archive`. Otherwise pick a recent block with --block-number, \
+                 or pass --no-probe to try anyway.",
+                block_number, url
+            )
+        })?;
+    Ok(())
+}
+
+/// Reads the `Balances::ExistentialDeposit` constant, `None` if the runtime has none.
+pub fn existential_deposit(client: &Client<DefaultConfig>) -> Result<Option<Balance>> {
+    match client
+        .metadata()
+        .pallet("Balances")
+        .and_then(|pallet| pallet.constant("ExistentialDeposit"))
+    {
+        Ok(constant) => Ok(Some(Balance::decode(&mut constant.value.as_slice())?)),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Reads the `System::SS58Prefix` constant, `None` if the runtime has none.
+pub fn ss58_prefix(client: &Client<DefaultConfig>) -> Result<Option<u16>> {
+    match client
+        .metadata()
+        .pallet("System")
+        .and_then(|pallet| pallet.constant("SS58Prefix"))
+    {
+        Ok(constant) => Ok(Some(u16::decode(&mut constant.value.as_slice())?)),
+        Err(_) => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `System::Account` key of `//Alice`.
+    const ALICE_KEY: &str = "26aa394eea5630e07c48ae0c9558cef7b99d880ec681799c0cf30e8886371da9\
+                             de1e86a9a8c739864cf3cc5ec2bea59f\
+                             d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+    const ALICE: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+
+    fn account_info(free: Balance, reserved: Balance) -> AccountInfo {
+        let mut info = fresh_account_info(free);
+        info.data.reserved = reserved;
+        info
+    }
+
+    #[test]
+    fn decode_known_key() {
+        let key = hex::decode(ALICE_KEY).unwrap();
+        let prefix = account_storage_prefix();
+        let (account_id, total) =
+            decode_account_entry(&key, &prefix, &account_info(1_000, 0)).unwrap();
+        assert_eq!(account_id, ALICE.parse::<AccountId>().unwrap());
+        assert_eq!(total, 1_000);
+        assert_eq!(account_storage_key(&account_id), key);
+    }
+
+    #[test]
+    fn decode_reserved_balance() {
+        let key = hex::decode(ALICE_KEY).unwrap();
+        let prefix = account_storage_prefix();
+        let (_, total) = decode_account_entry(&key, &prefix, &account_info(1_000, 250)).unwrap();
+        assert_eq!(total, 1_250);
+        assert!(decode_account_entry(&key, &prefix, &account_info(Balance::MAX, 1)).is_err());
+    }
+
+    #[test]
+    fn decode_malformed_keys() {
+        let key = hex::decode(ALICE_KEY).unwrap();
+        let prefix = account_storage_prefix();
+        let info = account_info(1_000, 0);
+
+        // Another storage map.
+        let other_prefix = [twox_128(b"System"), twox_128(b"Events")].concat();
+        let mut other = other_prefix.clone();
+        other.extend(&key[prefix.len()..]);
+        assert!(decode_account_entry(&other, &prefix, &info).is_err());
+        assert!(decode_account_entry(&key, &other_prefix, &info).is_err());
+
+        // Truncated, or with a trailing byte.
+        assert!(decode_account_entry(&key[..key.len() - 1], &prefix, &info).is_err());
+        assert!(decode_account_entry(&prefix, &prefix, &info).is_err());
+        let mut long = key.clone();
+        long.push(0);
+        assert!(decode_account_entry(&long, &prefix, &info).is_err());
+
+        // The hash does not match the trailing account id.
+        let mut mismatch = key.clone();
+        *mismatch.last_mut().unwrap() ^= 1;
+        let err = decode_account_entry(&mismatch, &prefix, &info).unwrap_err();
+        assert!(err.to_string().contains("hash mismatch"));
+    }
+
+    #[test]
+    fn encode_account_info_keeps_the_existing_account() {
+        let mut existing = fresh_account_info(10);
+        existing.nonce = 7;
+        existing.consumers = 2;
+        existing.providers = 3;
+        existing.sufficients = 1;
+        existing.data.reserved = 5;
+        existing.data.misc_frozen = 4;
+        existing.data.fee_frozen = 1 << 127;
+
+        let decode = |value: Vec<u8>| AccountInfo::decode(&mut value.as_slice()).unwrap();
+        let info = decode(encode_account_info(Some(&existing), 42));
+        assert_eq!(
+            (info.nonce, info.consumers, info.providers, info.sufficients),
+            (7, 2, 3, 1)
+        );
+        assert_eq!(
+            (
+                info.data.free,
+                info.data.reserved,
+                info.data.misc_frozen,
+                info.data.fee_frozen
+            ),
+            (42, 5, 4, 1 << 127)
+        );
+
+        let info = decode(encode_account_info(Some(&existing), 0));
+        assert_eq!((info.nonce, info.data.free, info.data.reserved), (7, 0, 5));
+
+        assert_eq!(
+            encode_account_info(None, 42),
+            fresh_account_info(42).encode()
+        );
+    }
+}
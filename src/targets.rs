@@ -0,0 +1,141 @@
+//! Tokenomics targets the distribution of a snapshot must meet, see `--target-file`.
+
+use crate::format::BalanceFormatter;
+use crate::snapshot::Entry;
+use crate::Balance;
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+/// Largest share of the total allowed for the `accounts` largest balances together.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TopShare {
+    pub accounts: usize,
+    pub share: f64,
+}
+
+/// Targets read from a JSON file, every one of them optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Targets {
+    /// Largest share of the total a single account may hold.
+    pub max_account_share: Option<f64>,
+    pub max_top_share: Option<TopShare>,
+    /// Fewest accounts holding at least the existential deposit.
+    pub min_accounts_above_existential_deposit: Option<usize>,
+    pub min_accounts: Option<usize>,
+    pub max_accounts: Option<usize>,
+}
+
+/// Outcome of one target.
+pub struct Criterion {
+    pub name: String,
+    pub actual: String,
+    pub target: String,
+    pub pass: bool,
+}
+
+impl Targets {
+    pub fn load(path: &Path) -> Result<Self> {
+        let targets: Self =
+            serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(path)?))
+                .with_context(|| format!("Failed to parse {}", path.display()))?;
+        let shares = [
+            targets.max_account_share,
+            targets.max_top_share.as_ref().map(|top| top.share),
+        ];
+        if shares
+            .iter()
+            .flatten()
+            .any(|share| !(0.0..=1.0).contains(share))
+        {
+            return Err(anyhow!(
+                "Shares of {} must be between 0 and 1",
+                path.display()
+            ));
+        }
+        Ok(targets)
+    }
+
+    /// Checks `entries` against every target given, `existential_deposit` being
+    /// required by `min_accounts_above_existential_deposit`.
+    pub fn check(
+        &self,
+        entries: &[Entry],
+        existential_deposit: Option<Balance>,
+        formatter: &BalanceFormatter,
+    ) -> Result<Vec<Criterion>> {
+        let mut balances = entries
+            .iter()
+            .map(|Entry(_, balance)| *balance)
+            .collect::<Vec<_>>();
+        balances.sort_unstable_by(|a, b| b.cmp(a));
+        let total = balances.iter().map(|balance| *balance as f64).sum::<f64>();
+        let share = |balances: &[Balance]| {
+            if total == 0.0 {
+                0.0
+            } else {
+                balances.iter().map(|balance| *balance as f64).sum::<f64>() / total
+            }
+        };
+
+        let mut criteria = Vec::new();
+        if let Some(max) = self.max_account_share {
+            let actual = share(&balances[..balances.len().min(1)]);
+            criteria.push(Criterion {
+                name: "Largest account share".to_string(),
+                actual: format!("{:.4}", actual),
+                target: format!("<= {}", max),
+                pass: actual <= max,
+            });
+        }
+        if let Some(top) = &self.max_top_share {
+            let actual = share(&balances[..balances.len().min(top.accounts)]);
+            criteria.push(Criterion {
+                name: format!("Share of the {} largest accounts", top.accounts),
+                actual: format!("{:.4}", actual),
+                target: format!("<= {}", top.share),
+                pass: actual <= top.share,
+            });
+        }
+        if let Some(min) = self.min_accounts_above_existential_deposit {
+            let existential_deposit = existential_deposit.ok_or_else(|| {
+                anyhow!(
+                    "The existential deposit is unknown, pass --existential-deposit to check \
+                     min_accounts_above_existential_deposit"
+                )
+            })?;
+            let actual = balances
+                .iter()
+                .filter(|balance| **balance >= existential_deposit)
+                .count();
+            criteria.push(Criterion {
+                name: format!(
+                    "Accounts holding at least {}",
+                    formatter.display(existential_deposit)
+                ),
+                actual: actual.to_string(),
+                target: format!(">= {}", min),
+                pass: actual >= min,
+            });
+        }
+        if let Some(min) = self.min_accounts {
+            criteria.push(Criterion {
+                name: "Accounts".to_string(),
+                actual: balances.len().to_string(),
+                target: format!(">= {}", min),
+                pass: balances.len() >= min,
+            });
+        }
+        if let Some(max) = self.max_accounts {
+            criteria.push(Criterion {
+                name: "Accounts".to_string(),
+                actual: balances.len().to_string(),
+                target: format!("<= {}", max),
+                pass: balances.len() <= max,
+            });
+        }
+        Ok(criteria)
+    }
+}
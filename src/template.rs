@@ -0,0 +1,98 @@
+//! File name template of `--output-template`.
+
+use crate::{BlockHash, BlockNumber};
+use anyhow::{anyhow, Error, Result};
+use serde::Serialize;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const PLACEHOLDERS: [&str; 5] = ["number", "hash", "short_hash", "date", "format"];
+
+/// File name with `{placeholder}`s, e.g. `snapshot-{date}-{short_hash}.{format}`.
+#[derive(Clone, Debug, Serialize)]
+pub struct OutputTemplate(String);
+
+impl FromStr for OutputTemplate {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains(['/', '\\']) {
+            return Err(anyhow!(
+                "Output template {} must be a file name, use --out-dir for the directory",
+                s
+            ));
+        }
+        let mut rest = s;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                return Err(anyhow!("Unmatched }} in output template {}", s));
+            }
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| anyhow!("Unmatched {{ in output template {}", s))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(anyhow!(
+                    "Unknown placeholder {{{}}} in output template {}, expected one of {}",
+                    name,
+                    s,
+                    PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+impl OutputTemplate {
+    /// Returns the file name of the snapshot at the given block, checking that it keeps
+    /// the `extension` of the format, which is how the snapshots are recognized.
+    pub fn render(
+        &self,
+        block_number: BlockNumber,
+        block_hash: BlockHash,
+        extension: &str,
+    ) -> Result<String> {
+        let hash = format!("{:?}", block_hash);
+        let name = self
+            .0
+            .replace("{number}", &block_number.to_string())
+            .replace("{hash}", &hash)
+            .replace("{short_hash}", &hash[2..10])
+            .replace("{date}", &utc_date()?)
+            .replace("{format}", extension);
+
+        let file_stem = name.strip_suffix(&format!(".{}", extension));
+        if file_stem.map_or(true, str::is_empty) {
+            return Err(anyhow!(
+                "Output file name {} from template {} must end with .{}",
+                name,
+                self.0,
+                extension
+            ));
+        }
+        Ok(name)
+    }
+}
+
+/// Returns the current UTC date as `YYYY-MM-DD`.
+fn utc_date() -> Result<String> {
+    let days = (SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() / 86_400) as i64;
+    // Civil date from the days since 1970-01-01, in eras of 400 years starting in March.
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    Ok(format!("{:04}-{:02}-{:02}", year, month, day))
+}
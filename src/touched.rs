@@ -0,0 +1,207 @@
+//! Snapshot of the accounts touched by balance events over a block range.
+
+use crate::dynamic::{self, AccountDecoder};
+use crate::snapshot::{Entry, Snapshot};
+use crate::storage::{account_key, decode_account_entry, normalize_extra_flags};
+use crate::{
+    account_filter, block_hash_at, output, output_dir, AccountInfo, Balance, BlockNumber, Cli,
+    Target,
+};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use codec::Decode;
+use scale_info::{form::PortableForm, Field, TypeDef};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+use subspace_regenesis_tool::{AccountFilter, Disposition};
+use subxt::sp_core::hashing::twox_128;
+use subxt::sp_core::storage::StorageKey;
+use subxt::{DefaultConfig, EventsDecoder};
+
+/// Pallet whose events touch the balances.
+const PALLET: &str = "Balances";
+
+/// Snapshot only the accounts named by a `Balances` event between two blocks, with
+/// their balance at the last one, instead of iterating all the accounts.
+///
+/// The events are only complete on an archive node keeping the state of every block of
+/// the range.
+#[derive(Parser, Debug, Serialize)]
+pub struct TouchedCmd {
+    /// First block of the range, included.
+    #[clap(long)]
+    pub from: BlockNumber,
+
+    /// Last block of the range, included, the target block by default. The balances are
+    /// read at this block.
+    #[clap(long)]
+    pub to: Option<BlockNumber>,
+
+    /// Path of the JSON file, `balances_touched_{from}_{to}.json` in `--out-dir` by
+    /// default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl TouchedCmd {
+    pub async fn run(self, cli: &Cli, target: &Target) -> Result<()> {
+        let client = &target.api.client;
+        let to = self.to.unwrap_or(target.block_number);
+        if self.from > to {
+            return Err(anyhow!("--from #{} is after --to #{}", self.from, to));
+        }
+        if cli.asset_id.is_some() {
+            return Err(anyhow!(
+                "touched only supports the native balances, not --asset-id"
+            ));
+        }
+
+        let path = match self.output {
+            Some(path) => path,
+            None => output_dir(cli)?.join(format!("balances_touched_{}_{}.json", self.from, to)),
+        };
+        if path.exists() && !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ));
+        }
+
+        let metadata = client.metadata();
+        let runtime = metadata.runtime_metadata();
+        let event_type = runtime
+            .pallets
+            .iter()
+            .find(|pallet| pallet.name == PALLET)
+            .and_then(|pallet| pallet.event.as_ref())
+            .ok_or_else(|| anyhow!("Pallet {} has no events in the metadata", PALLET))?
+            .ty
+            .id();
+        let variants: BTreeMap<&str, &[Field<PortableForm>]> =
+            match runtime.types.resolve(event_type).map(|ty| ty.type_def()) {
+                Some(TypeDef::Variant(variant)) => variant
+                    .variants()
+                    .iter()
+                    .map(|variant| (variant.name().as_str(), variant.fields()))
+                    .collect(),
+                _ => return Err(anyhow!("Events of {} are not an enum", PALLET)),
+            };
+
+        let decoder = EventsDecoder::<DefaultConfig>::new(metadata.clone());
+        let events_key = StorageKey([twox_128(b"System"), twox_128(b"Events")].concat());
+
+        let mut touched = BTreeSet::new();
+        let mut event_counts = BTreeMap::<String, usize>::new();
+        for block_number in self.from..=to {
+            let block_hash = block_hash_at(client, block_number).await?;
+            let data = match client.rpc().storage(&events_key, Some(block_hash)).await? {
+                Some(data) => data,
+                None => continue,
+            };
+            let events = decoder
+                .decode_events(&mut data.0.as_slice())
+                .map_err(|err| {
+                    anyhow!(
+                        "Failed to decode the events of block #{}: {}",
+                        block_number,
+                        err
+                    )
+                })?;
+            for (_, event) in events {
+                if event.pallet != PALLET {
+                    continue;
+                }
+                let fields = variants.get(event.variant.as_str()).ok_or_else(|| {
+                    anyhow!(
+                        "Event {}.{} of block #{} not found in the metadata",
+                        PALLET,
+                        event.variant,
+                        block_number
+                    )
+                })?;
+                touched.extend(dynamic::event_accounts(
+                    &runtime.types,
+                    fields,
+                    &event.data.0,
+                )?);
+                *event_counts.entry(event.variant).or_default() += 1;
+            }
+        }
+
+        for (variant, count) in &event_counts {
+            status!("{}.{} events: {}", PALLET, variant, count);
+        }
+        status!(
+            "Accounts touched between block #{} and #{}: {}",
+            self.from,
+            to,
+            touched.len()
+        );
+
+        let block_hash = block_hash_at(client, to).await?;
+        let prefix = cli.account_storage.resolve(client)?;
+        let extra_flags = cli.account_storage.has_extra_flags(client)?;
+        let account_decoder = if cli.dynamic {
+            Some(AccountDecoder::new(&cli.account_storage, client)?)
+        } else {
+            None
+        };
+        let filter = account_filter()?;
+
+        let mut snapshot = Snapshot::new();
+        let mut special = 0usize;
+        let mut reaped = 0usize;
+        for account_id in &touched {
+            let key = StorageKey(account_key(&prefix, account_id));
+            let data = match client.rpc().storage(&key, Some(block_hash)).await? {
+                Some(data) => data,
+                None => {
+                    reaped += 1;
+                    continue;
+                }
+            };
+            let mut info = match &account_decoder {
+                Some(decoder) => decoder.decode(&data.0)?,
+                None => AccountInfo::decode(&mut data.0.as_slice())?,
+            };
+            if extra_flags {
+                normalize_extra_flags(&mut info.data);
+            }
+            let (_, total) = decode_account_entry(&key.0, &prefix, &info)?;
+            if filter.classify(account_id.as_ref(), total) == Disposition::Skip {
+                special += 1;
+                continue;
+            }
+            snapshot.push(Entry(
+                account_id.clone(),
+                cli.balance_kind.exported(&info.data)?,
+            ));
+        }
+
+        let total = snapshot
+            .iter()
+            .try_fold(0 as Balance, |total, Entry(_, balance)| {
+                total.checked_add(*balance)
+            })
+            .ok_or_else(|| anyhow!("Total balance overflows"))?;
+
+        let mut writer = output::FileWriter::create(&path)?;
+        output::write_json(&mut writer, &snapshot, cli.json_indent)?;
+        let bytes_written = writer.finish(cli.fsync)?;
+
+        status!("Special accounts skipped: {}", special);
+        status!("Accounts reaped by block #{}: {}", to, reaped);
+        status!("Total: {}", cli.formatter().display(total));
+        status!(
+            "Snapshot of {} touched accounts at block #{} ({:?}) has been written to {} ({} bytes)",
+            snapshot.len(),
+            to,
+            block_hash,
+            path.display(),
+            bytes_written
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+//! Total issuance over a block range.
+
+use crate::{block_hash_at, output, BlockNumber, RuntimeApi};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// Maximum number of blocks sampled in one run.
+const MAX_SAMPLES: u32 = 10_000;
+
+/// Sample `Balances::TotalIssuance` over a block range into a CSV file.
+#[derive(Parser, Debug, Serialize)]
+pub struct TrendCmd {
+    /// First block of the range.
+    #[clap(long)]
+    pub from: BlockNumber,
+
+    /// Last block of the range, inclusive.
+    #[clap(long)]
+    pub to: BlockNumber,
+
+    /// Number of blocks between two samples.
+    #[clap(long, default_value = "1000")]
+    pub step: BlockNumber,
+
+    /// Path of the CSV file, `issuance_{from}_{to}.csv` by default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+impl TrendCmd {
+    pub async fn run(self, api: &RuntimeApi) -> Result<()> {
+        if self.from > self.to {
+            return Err(anyhow!("--from {} is after --to {}", self.from, self.to));
+        }
+        if self.step == 0 {
+            return Err(anyhow!("--step must be greater than 0"));
+        }
+
+        let samples = (self.to - self.from) / self.step + 1;
+        if samples > MAX_SAMPLES {
+            return Err(anyhow!(
+                "{} samples requested, at most {} are allowed, use a larger --step",
+                samples,
+                MAX_SAMPLES
+            ));
+        }
+
+        let path = self
+            .output
+            .unwrap_or_else(|| format!("issuance_{}_{}.csv", self.from, self.to).into());
+
+        let mut writer = output::FileWriter::create(&path)?;
+        writeln!(writer, "block_number,block_hash,total_issuance")?;
+
+        for block_number in (self.from..=self.to).step_by(self.step as usize) {
+            let block_hash = block_hash_at(&api.client, block_number).await?;
+            let total_issuance = api
+                .storage()
+                .balances()
+                .total_issuance(Some(block_hash))
+                .await?;
+            writeln!(
+                writer,
+                "{},{:?},{}",
+                block_number, block_hash, total_issuance
+            )?;
+        }
+
+        writer.finish(false)?;
+
+        println!("{} samples written to {}", samples, path.display());
+
+        Ok(())
+    }
+}
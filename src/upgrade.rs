@@ -0,0 +1,46 @@
+//! Detection of runtime upgrades between the connection and the target block.
+
+use crate::{error, BlockHash};
+use anyhow::Result;
+
+/// Fails if the runtime of the target block `block_hash`, at `spec_version`, is not
+/// the `metadata_spec_version` of the best block the metadata was fetched at.
+///
+/// This covers an upgrade between the connection and the resolution of the best
+/// block, the pagers then keep checking during the iteration, see
+/// [`AccountPager::check_runtime`](crate::pager::AccountPager::check_runtime). A block
+/// selected explicitly is not checked, it is usually in the past and decodes with the
+/// metadata of a later runtime as long as the `AccountInfo` layout is the same.
+pub fn check(
+    metadata_spec_version: u32,
+    spec_version: u32,
+    block_hash: BlockHash,
+    pinned: bool,
+) -> Result<()> {
+    if pinned || spec_version == metadata_spec_version {
+        return Ok(());
+    }
+    Err(error::Error::Assertion(format!(
+        "runtime upgraded during snapshot from spec_version {} to {} at block {:?}; results \
+         invalid, please re-run at a fixed block hash selected with --block-hash",
+        metadata_spec_version, spec_version, block_hash
+    ))
+    .into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pinned_blocks_are_not_checked() {
+        let block_hash = BlockHash::zero();
+        assert!(check(5, 5, block_hash, false).is_ok());
+        assert!(check(5, 6, block_hash, true).is_ok());
+        let err = check(5, 6, block_hash, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<error::Error>(),
+            Some(error::Error::Assertion(_))
+        ));
+    }
+}
@@ -0,0 +1,142 @@
+//! Offline consistency check of a snapshot file.
+
+use crate::snapshot::{self, Entry};
+use crate::storage::account_storage_key;
+use crate::{error, output, split, AccountId, Balance};
+use anyhow::{Context, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+
+/// Check the internal consistency of a snapshot without connecting to a node.
+///
+/// Every address must be valid SS58 and appear once, every balance must parse and,
+/// when the metadata file is present, its digest, account count and total must match.
+#[derive(Parser, Debug, Serialize)]
+pub struct ValidateCmd {
+    /// The snapshot to check.
+    pub path: PathBuf,
+
+    /// Check that the entries are sorted in storage order as well, finding the
+    /// duplicates among neighbours instead of indexing every account.
+    #[clap(long)]
+    pub assert_sorted: bool,
+}
+
+impl ValidateCmd {
+    pub fn run(self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        let entries = if self.path.extension().map_or(false, |ext| ext == "json")
+            && !split::is_manifest(&self.path)
+            && !self.path.to_string_lossy().ends_with(".canonical.json")
+        {
+            // Read the addresses as strings to report all the invalid ones.
+            let raw: Vec<(String, Balance)> =
+                serde_json::from_reader(std::io::BufReader::new(std::fs::File::open(&self.path)?))
+                    .with_context(|| format!("Failed to parse {}", self.path.display()))?;
+            raw.into_iter()
+                .enumerate()
+                .filter_map(
+                    |(index, (address, balance))| match address.parse::<AccountId>() {
+                        Ok(account_id) => Some(Entry(account_id, balance)),
+                        Err(err) => {
+                            problems.push(format!(
+                                "entry {}: invalid SS58 address {}: {:?}",
+                                index, address, err
+                            ));
+                            None
+                        }
+                    },
+                )
+                .collect()
+        } else {
+            snapshot::load(&self.path)?
+        };
+
+        let mut seen = BTreeSet::new();
+        let mut previous_key = None;
+        let mut total: Balance = 0;
+        let mut overflowed = false;
+        for (index, Entry(account_id, balance)) in entries.iter().enumerate() {
+            if self.assert_sorted {
+                let key = account_storage_key(account_id);
+                match previous_key.as_ref().map(|previous| key.cmp(previous)) {
+                    Some(Ordering::Equal) => {
+                        problems.push(format!("entry {}: duplicate account {}", index, account_id))
+                    }
+                    Some(Ordering::Less) => problems.push(format!(
+                        "entry {}: {} is out of storage order",
+                        index, account_id
+                    )),
+                    _ => {}
+                }
+                previous_key = Some(key);
+            } else if !seen.insert(account_id) {
+                problems.push(format!("entry {}: duplicate account {}", index, account_id));
+            }
+            match total.checked_add(*balance) {
+                Some(sum) => total = sum,
+                None => overflowed = true,
+            }
+        }
+        if overflowed {
+            problems.push("total balance overflows u128".to_string());
+        }
+
+        match snapshot::load_metadata(&self.path)? {
+            Some(metadata) => {
+                if let Some(sha256) = &metadata.sha256 {
+                    let actual = output::sha256_file(&self.path)?;
+                    if &actual != sha256 {
+                        problems.push(format!(
+                            "SHA-256 {} does not match {} in the metadata",
+                            actual, sha256
+                        ));
+                    }
+                }
+                if let Some(accounts) = metadata.accounts {
+                    if accounts != entries.len() {
+                        problems.push(format!(
+                            "{} accounts in the file but {} in the metadata",
+                            entries.len(),
+                            accounts
+                        ));
+                    }
+                }
+                if let Some(expected) = metadata.total.filter(|_| !overflowed) {
+                    if expected != total {
+                        problems.push(format!(
+                            "total {} in the file but {} in the metadata",
+                            total, expected
+                        ));
+                    }
+                }
+            }
+            None => println!("No metadata found, only the entries are checked"),
+        }
+
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        println!(
+            "Accounts: {}, total: {}, problems: {}",
+            entries.len(),
+            total,
+            problems.len()
+        );
+
+        if !problems.is_empty() {
+            return Err(error::Error::Assertion(format!(
+                "{} has {} problems",
+                self.path.display(),
+                problems.len()
+            ))
+            .into());
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+//! Spot check of a snapshot against the chain, see `--post-verify`.
+
+use crate::dynamic::AccountDecoder;
+use crate::snapshot::Entry;
+use crate::storage::{account_key, decode_asset_account, normalize_extra_flags};
+use crate::{error, AccountInfo, BlockHash, Cli};
+use anyhow::{anyhow, Result};
+use codec::Decode;
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+use subxt::sp_core::storage::StorageKey;
+use subxt::{Client, DefaultConfig};
+
+/// Re-reads `--post-verify` accounts of `entries` picked with `--post-verify-seed`, one
+/// storage read each, and fails if any balance differs from the snapshot.
+pub async fn post_verify(
+    cli: &Cli,
+    client: &Client<DefaultConfig>,
+    prefix: &[u8],
+    block_hash: BlockHash,
+    extra_flags: bool,
+    entries: &[Entry],
+) -> Result<()> {
+    let count = cli.post_verify.unwrap_or(0);
+    let seed = cli.post_verify_seed.unwrap_or(0);
+    let decoder = if cli.dynamic {
+        Some(AccountDecoder::new(&cli.account_storage, client)?)
+    } else {
+        None
+    };
+
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let sample = rand::seq::index::sample(&mut rng, entries.len(), count.min(entries.len()));
+
+    let mut mismatches = Vec::new();
+    for index in sample.iter() {
+        let Entry(account_id, balance) = &entries[index];
+        let key = StorageKey(account_key(prefix, account_id));
+        let queried = match client.rpc().storage(&key, Some(block_hash)).await? {
+            Some(data) => {
+                let mut info = match &decoder {
+                    Some(decoder) => decoder.decode(&data.0)?,
+                    None if cli.asset_id.is_some() => decode_asset_account(&data.0)?,
+                    None => AccountInfo::decode(&mut data.0.as_slice())?,
+                };
+                if extra_flags {
+                    normalize_extra_flags(&mut info.data);
+                }
+                Some(cli.balance_kind.exported(&info.data)?)
+            }
+            None => None,
+        };
+        if queried != Some(*balance) {
+            mismatches.push((account_id, *balance, queried));
+        }
+    }
+
+    for (account_id, balance, queried) in &mismatches {
+        match queried {
+            Some(queried) => status!(
+                "Post-verify: {} has {} in the snapshot but {} on chain",
+                account_id,
+                balance,
+                queried
+            ),
+            None => status!(
+                "Post-verify: {} has {} in the snapshot but is not on chain",
+                account_id,
+                balance
+            ),
+        }
+    }
+    if !mismatches.is_empty() {
+        return Err(error::Error::Assertion(format!(
+            "{} of {} re-read accounts differ from the snapshot",
+            mismatches.len(),
+            sample.len()
+        ))
+        .into());
+    }
+
+    status!(
+        "Post-verify: {} accounts re-read at block {:?} match the snapshot (seed {})",
+        sample.len(),
+        block_hash,
+        seed
+    );
+    Ok(())
+}
+
+/// Returns the option which changes the balances after they are queried, making them
+/// impossible to compare with the chain.
+pub fn incompatible_option(cli: &Cli) -> Option<&'static str> {
+    if cli.balance_expr.is_some() {
+        Some("--balance-expr")
+    } else if cli.round_to.is_some() {
+        Some("--round-to")
+    } else if cli.overrides_file.is_some() {
+        Some("--overrides-file")
+    } else if cli.baseline.is_some() {
+        Some("--baseline")
+    } else if cli.append.is_some() {
+        Some("--append")
+    } else if cli.redact {
+        Some("--redact")
+    } else {
+        None
+    }
+}
+
+pub fn check_options(cli: &Cli) -> Result<()> {
+    match incompatible_option(cli) {
+        Some(option) => Err(anyhow!(
+            "--post-verify can't be combined with {}, which changes the balances",
+            option
+        )),
+        None => Ok(()),
+    }
+}
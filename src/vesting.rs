@@ -0,0 +1,211 @@
+//! Export of the whole `Vesting::VestingSchedules` storage.
+
+use crate::warnings::{Warning, Warnings};
+use crate::{output, output_dir, special_accounts, AccountId, Balance, BlockNumber, Cli, Target};
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use serde::Serialize;
+use std::path::PathBuf;
+
+/// Length of the account id at the end of a `Blake2_128Concat` storage key.
+const ACCOUNT_ID_LEN: usize = 32;
+
+/// Returns the part of a schedule still locked at `block_number`, same as
+/// `VestingSchedule::locked_amount` of orml_vesting.
+pub fn locked_amount(
+    start: BlockNumber,
+    period: BlockNumber,
+    period_count: u32,
+    per_period: Balance,
+    block_number: BlockNumber,
+) -> Balance {
+    let elapsed = block_number
+        .saturating_sub(start)
+        .checked_div(period)
+        .unwrap_or(period_count);
+    let unrealized = period_count.saturating_sub(elapsed);
+    per_period.saturating_mul(unrealized.into())
+}
+
+/// Returns the account id at the end of a `Vesting::VestingSchedules` storage key.
+pub fn key_account_id(key: &[u8]) -> Result<AccountId> {
+    let raw_account_id = key
+        .len()
+        .checked_sub(ACCOUNT_ID_LEN)
+        .map(|start| &key[start..])
+        .ok_or_else(|| {
+            anyhow!(
+                "Malformed Vesting::VestingSchedules storage key 0x{}",
+                hex::encode(key)
+            )
+        })?;
+    Ok(AccountId::new(
+        raw_account_id
+            .try_into()
+            .expect("Length of the account id is checked above; qed"),
+    ))
+}
+
+/// Export the vesting schedules of every account at the block into a JSON file.
+#[derive(Parser, Debug, Serialize)]
+pub struct VestingCmd {
+    /// Path of the JSON file, `vesting_{block_number}.json` under `--out-dir` by
+    /// default.
+    #[clap(long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Serialize)]
+struct Schedule {
+    start: BlockNumber,
+    period: BlockNumber,
+    period_count: u32,
+    per_period: Balance,
+    /// `per_period * period_count`.
+    total: Balance,
+    /// Part of the total still locked at the block.
+    locked: Balance,
+}
+
+#[derive(Debug, Serialize)]
+struct VestingAccount {
+    account_id: AccountId,
+    /// Whether the account is one of the compiled-in token grants.
+    token_grant: bool,
+    schedules: Vec<Schedule>,
+    total: Balance,
+    locked: Balance,
+    free: Balance,
+    frozen: Balance,
+}
+
+impl VestingCmd {
+    pub async fn run(self, cli: &Cli, target: &Target) -> Result<()> {
+        let Target {
+            api,
+            block_hash,
+            block_number,
+            ..
+        } = target;
+
+        let path = match self.output {
+            Some(path) => path,
+            None => output_dir(cli)?.join(format!("vesting_{}.json", block_number)),
+        };
+        if path.exists() && !cli.force {
+            return Err(anyhow!(
+                "{} already exists, pass --force to overwrite it",
+                path.display()
+            ));
+        }
+
+        let (_, token_grants) = special_accounts()?;
+        let mut warnings = Warnings::open(
+            cli.warnings_file.as_deref(),
+            cli.print_warnings,
+            cli.formatter(),
+        )?;
+
+        let mut accounts = Vec::new();
+        let mut iter = api
+            .storage()
+            .vesting()
+            .vesting_schedules_iter(Some(*block_hash))
+            .await?;
+
+        while let Some((key, schedules)) = iter.next().await? {
+            let account_id = key_account_id(&key.0)?;
+
+            let schedules = schedules
+                .0
+                .into_iter()
+                .map(|schedule| Schedule {
+                    start: schedule.start,
+                    period: schedule.period,
+                    period_count: schedule.period_count,
+                    per_period: schedule.per_period,
+                    total: schedule
+                        .per_period
+                        .saturating_mul(schedule.period_count.into()),
+                    locked: locked_amount(
+                        schedule.start,
+                        schedule.period,
+                        schedule.period_count,
+                        schedule.per_period,
+                        *block_number,
+                    ),
+                })
+                .collect::<Vec<_>>();
+
+            let sum = |amount: fn(&Schedule) -> Balance| {
+                schedules
+                    .iter()
+                    .try_fold(0, |sum: Balance, schedule| {
+                        sum.checked_add(amount(schedule))
+                    })
+                    .ok_or_else(|| anyhow!("Vesting schedules of {} overflow", account_id))
+            };
+            let total = sum(|schedule| schedule.total)?;
+            let locked = sum(|schedule| schedule.locked)?;
+
+            let account = api
+                .storage()
+                .system()
+                .account(&account_id, Some(*block_hash))
+                .await?;
+            let frozen = account.data.misc_frozen.max(account.data.fee_frozen);
+
+            // The vesting lock is only lowered on claim, so it may exceed the locked
+            // amount but never fall short of it.
+            if locked > account.data.free || frozen < locked {
+                warnings.emit(Warning::VestingLocked {
+                    account: account_id.clone(),
+                    locked,
+                    free: account.data.free,
+                    frozen,
+                })?;
+            }
+
+            accounts.push(VestingAccount {
+                token_grant: token_grants.contains(&account_id),
+                account_id,
+                schedules,
+                total,
+                locked,
+                free: account.data.free,
+                frozen,
+            });
+        }
+
+        let mut writer = output::FileWriter::create(&path)?;
+        serde_json::to_writer_pretty(&mut writer, &accounts)?;
+        writer.finish(cli.fsync)?;
+
+        let missing_grants = token_grants
+            .iter()
+            .filter(|grant| !accounts.iter().any(|account| &account.account_id == *grant))
+            .count();
+
+        println!(
+            "Vesting schedules of {} accounts at block #{} ({:?}) written to {}",
+            accounts.len(),
+            block_number,
+            block_hash,
+            path.display()
+        );
+        println!(
+            "Token grants without a vesting schedule: {}",
+            missing_grants
+        );
+
+        let warning_count = warnings.finish()?;
+        if warning_count > 0 {
+            println!(
+                "Accounts with an inconsistent vesting lock: {}",
+                warning_count
+            );
+        }
+
+        Ok(())
+    }
+}
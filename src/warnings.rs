@@ -0,0 +1,194 @@
+//! Collection of the warnings encountered during a snapshot run.
+
+use crate::format::BalanceFormatter;
+use crate::{AccountId, Balance, BlockNumber};
+use serde::Serialize;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A warning-level diagnostic, written as one JSON line to `--warnings-file`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum Warning {
+    /// The sum of all the accounts does not match the total issuance.
+    IssuanceMismatch {
+        iterated: Balance,
+        expected: Balance,
+    },
+    /// The total issuance is zero at the block, e.g. the genesis or a wrong target.
+    ZeroIssuance {
+        chain: String,
+        spec_version: u32,
+        block_number: BlockNumber,
+    },
+    /// A new account was left out by `--balance-min` or `--balance-max`.
+    OutOfRange {
+        account: AccountId,
+        balance: Balance,
+    },
+    /// A new account was left out by `--min-age-block`, absent at the reference block.
+    TooNew {
+        account: AccountId,
+        balance: Balance,
+    },
+    /// Part of the balance of a new account was withheld by `--balance-kind`.
+    Withheld {
+        account: AccountId,
+        total: Balance,
+        exported: Balance,
+    },
+    /// A new account is written with a balance below the existential deposit.
+    BelowExistentialDeposit {
+        account: AccountId,
+        balance: Balance,
+        existential_deposit: Balance,
+    },
+    /// A new account holding nothing on chain is written, under `--zero-balance warn`.
+    ZeroBalance { account: AccountId },
+    /// The value at a storage key failed to decode and was skipped.
+    Undecodable { key: String, error: String },
+    /// An account appears in several of the special account lists.
+    SpecialAccountOverlap {
+        account: AccountId,
+        lists: Vec<&'static str>,
+    },
+    /// The vesting lock of an account is inconsistent with its vesting schedules.
+    VestingLocked {
+        account: AccountId,
+        locked: Balance,
+        free: Balance,
+        frozen: Balance,
+    },
+}
+
+impl Warning {
+    /// Whether the warning details an account counted in the console output, those are
+    /// not printed by default since the console already prints their totals.
+    fn is_per_account(&self) -> bool {
+        matches!(
+            self,
+            Self::OutOfRange { .. }
+                | Self::TooNew { .. }
+                | Self::Withheld { .. }
+                | Self::BelowExistentialDeposit { .. }
+                | Self::ZeroBalance { .. }
+        )
+    }
+
+    fn message(&self, formatter: &BalanceFormatter) -> String {
+        match self {
+            Self::IssuanceMismatch { iterated, expected } => format!(
+                "total balance of all accounts {} does not match the total issuance {}",
+                formatter.display(*iterated),
+                formatter.display(*expected)
+            ),
+            Self::ZeroIssuance {
+                chain,
+                spec_version,
+                block_number,
+            } => format!(
+                "total issuance is ZERO at block #{} of {} (spec_version {}), this is most likely \
+                 the genesis block, a wrong node or a runtime without the Balances pallet",
+                block_number, chain, spec_version
+            ),
+            Self::OutOfRange { account, balance } => format!(
+                "account {} with balance {} is outside of the balance range",
+                account,
+                formatter.display(*balance)
+            ),
+            Self::TooNew { account, balance } => format!(
+                "account {} with balance {} did not exist at the --min-age-block",
+                account,
+                formatter.display(*balance)
+            ),
+            Self::Withheld {
+                account,
+                total,
+                exported,
+            } => format!(
+                "account {} exports {} out of its total balance {}",
+                account,
+                formatter.display(*exported),
+                formatter.display(*total)
+            ),
+            Self::BelowExistentialDeposit {
+                account,
+                balance,
+                existential_deposit,
+            } => format!(
+                "account {} is written with {}, below the existential deposit {}",
+                account,
+                formatter.display(*balance),
+                formatter.display(*existential_deposit)
+            ),
+            Self::ZeroBalance { account } => {
+                format!("account {} is written with a zero total balance", account)
+            }
+            Self::Undecodable { key, error } => {
+                format!("skipped undecodable value at {}: {}", key, error)
+            }
+            Self::SpecialAccountOverlap { account, lists } => format!(
+                "account {} is present in several special lists: {}",
+                account,
+                lists.join(", ")
+            ),
+            Self::VestingLocked {
+                account,
+                locked,
+                free,
+                frozen,
+            } => format!(
+                "account {} has {} locked by vesting but a free balance of {} and a frozen balance of {}",
+                account,
+                formatter.display(*locked),
+                formatter.display(*free),
+                formatter.display(*frozen)
+            ),
+        }
+    }
+}
+
+/// Sink of the warnings, printing them and/or recording them as JSON lines.
+pub struct Warnings {
+    file: Option<BufWriter<File>>,
+    print: bool,
+    formatter: BalanceFormatter,
+    count: usize,
+}
+
+impl Warnings {
+    /// Records the warnings to `path` if given.
+    ///
+    /// Without a file only the warnings about the whole run are printed, `print` prints
+    /// all of them including the per-account ones.
+    pub fn open(path: Option<&Path>, print: bool, formatter: BalanceFormatter) -> io::Result<Self> {
+        let file = path.map(File::create).transpose()?.map(BufWriter::new);
+        Ok(Self {
+            file,
+            print,
+            formatter,
+            count: 0,
+        })
+    }
+
+    pub fn emit(&mut self, warning: Warning) -> io::Result<()> {
+        self.count += 1;
+        if let Some(file) = &mut self.file {
+            serde_json::to_writer(&mut *file, &warning)?;
+            writeln!(file)?;
+        }
+        if self.print || (self.file.is_none() && !warning.is_per_account()) {
+            status!("Warning: {}", warning.message(&self.formatter));
+        }
+        Ok(())
+    }
+
+    /// Flushes the warnings file and returns the number of warnings emitted.
+    pub fn finish(self) -> io::Result<usize> {
+        if let Some(mut file) = self.file {
+            file.flush()?;
+        }
+        Ok(self.count)
+    }
+}